@@ -0,0 +1,474 @@
+//! Key agent: keeps the unlocked identity and repo keys in memory for a while, so the
+//! git filters don't unlock them again for every file (like ssh-agent).
+//!
+//! One JSON request and one JSON response per connection, over a Unix socket only the
+//! owning user can reach.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use zeroize::{Zeroize, Zeroizing};
+
+/// How long unlocked keys are kept unless `--lifetime` says otherwise
+pub const DEFAULT_LIFETIME: Duration = Duration::from_secs(3600);
+/// Overrides the socket location
+const SOCKET_ENV: &str = "ARCANE_AGENT_SOCK";
+/// A stuck client must not block the agent (or a filter waiting on it)
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub(crate) enum Request {
+    GetKey { label: String },
+    PutKey { label: String, key: String },
+    GetIdentity,
+    PutIdentity { secret: String },
+    Lock,
+    Status,
+    Stop,
+}
+
+/// Requests carry key material, so they wipe it when dropped
+impl Drop for Request {
+    fn drop(&mut self) {
+        match self {
+            Request::PutKey { key, .. } => key.zeroize(),
+            Request::PutIdentity { secret } => secret.zeroize(),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub(crate) struct Response {
+    pub(crate) ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) value: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status: Option<AgentStatus>,
+}
+
+/// What `arcane agent status` shows
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AgentStatus {
+    pub pid: u32,
+    pub socket: String,
+    pub identity: bool,
+    pub keys: usize,
+    pub lifetime_secs: u64,
+    /// Seconds until the oldest entry expires
+    pub next_expiry_secs: Option<u64>,
+}
+
+struct Cached {
+    secret: Zeroizing<String>,
+    expires: Instant,
+}
+
+pub(crate) struct Agent {
+    lifetime: Duration,
+    identity: Option<Cached>,
+    keys: HashMap<String, Cached>,
+}
+
+impl Agent {
+    /// An empty agent that keeps what it's given for `lifetime`
+    pub(crate) fn new(lifetime: Duration) -> Self {
+        Self {
+            lifetime,
+            identity: None,
+            keys: HashMap::new(),
+        }
+    }
+
+    fn cache(&self, secret: String) -> Cached {
+        Cached {
+            secret: Zeroizing::new(secret),
+            expires: Instant::now() + self.lifetime,
+        }
+    }
+
+    /// Drop expired entries (their secrets are zeroed on drop)
+    pub(crate) fn purge(&mut self) {
+        let now = Instant::now();
+        if self.identity.as_ref().is_some_and(|c| c.expires <= now) {
+            self.identity = None;
+        }
+        self.keys.retain(|_, c| c.expires > now);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.identity.is_none() && self.keys.is_empty()
+    }
+
+    /// When the next entry expires
+    fn next_expiry(&self) -> Option<Instant> {
+        self.identity
+            .iter()
+            .chain(self.keys.values())
+            .map(|c| c.expires)
+            .min()
+    }
+
+    /// Returns the response, and whether the agent should stop
+    pub(crate) fn handle(&mut self, mut request: Request) -> (Response, bool) {
+        self.purge();
+        let found = |value: Option<&Cached>| Response {
+            ok: value.is_some(),
+            value: value.map(|c| c.secret.to_string()),
+            ..Default::default()
+        };
+
+        match &mut request {
+            Request::GetKey { label } => (found(self.keys.get(label.as_str())), false),
+            Request::PutKey { label, key } => {
+                let cached = self.cache(std::mem::take(key));
+                self.keys.insert(label.clone(), cached);
+                (ok(), false)
+            }
+            Request::GetIdentity => (found(self.identity.as_ref()), false),
+            Request::PutIdentity { secret } => {
+                self.identity = Some(self.cache(std::mem::take(secret)));
+                (ok(), false)
+            }
+            Request::Lock => {
+                self.identity = None;
+                self.keys.clear();
+                (ok(), false)
+            }
+            Request::Status => {
+                let now = Instant::now();
+                let status = AgentStatus {
+                    pid: std::process::id(),
+                    socket: socket_path()
+                        .map(|p| p.display().to_string())
+                        .unwrap_or_default(),
+                    identity: self.identity.is_some(),
+                    keys: self.keys.len(),
+                    lifetime_secs: self.lifetime.as_secs(),
+                    next_expiry_secs: self
+                        .identity
+                        .iter()
+                        .chain(self.keys.values())
+                        .map(|c| c.expires.saturating_duration_since(now).as_secs())
+                        .min(),
+                };
+                (
+                    Response {
+                        ok: true,
+                        status: Some(status),
+                        ..Default::default()
+                    },
+                    false,
+                )
+            }
+            Request::Stop => (ok(), true),
+        }
+    }
+}
+
+fn ok() -> Response {
+    Response {
+        ok: true,
+        ..Default::default()
+    }
+}
+
+/// `$ARCANE_AGENT_SOCK`, or `~/.arcane/agent.sock`
+pub fn socket_path() -> Result<PathBuf> {
+    if let Ok(path) = std::env::var(SOCKET_ENV) {
+        return Ok(PathBuf::from(path));
+    }
+    let home = dirs::home_dir().context("Could not find home directory")?;
+    Ok(home.join(".arcane").join("agent.sock"))
+}
+
+/// Run the agent in the foreground until `arcane agent stop`, or until everything it
+/// held has expired. Entries are wiped when they expire, not when next asked for.
+/// `identity` is an already unlocked `AGE-SECRET-KEY-...` to hold from the start.
+#[cfg(unix)]
+pub fn serve(lifetime: Duration, identity: Option<Zeroizing<String>>) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    let path = socket_path()?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+        let mode = std::fs::metadata(parent)?.permissions().mode();
+        if mode & 0o022 != 0 {
+            return Err(anyhow::anyhow!(
+                "{:?} is writable by other users; refusing to put the agent socket there",
+                parent
+            ));
+        }
+    }
+    if path.exists() {
+        if UnixStream::connect(&path).is_ok() {
+            return Err(anyhow::anyhow!("An agent is already running on {:?}", path));
+        }
+        // Left behind by an agent that was killed
+        std::fs::remove_file(&path)?;
+    }
+
+    let listener = UnixListener::bind(&path)
+        .with_context(|| format!("Failed to bind agent socket {:?}", path))?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+
+    let mut agent = Agent::new(lifetime);
+    if let Some(secret) = identity {
+        agent.identity = Some(agent.cache(secret.to_string()));
+    }
+
+    let my_uid = unsafe { libc::geteuid() };
+    listener.set_nonblocking(true)?;
+    loop {
+        let held = !agent.is_empty();
+        agent.purge();
+        if held && agent.is_empty() {
+            break;
+        }
+        let timeout = agent
+            .next_expiry()
+            .map(|at| at.saturating_duration_since(Instant::now()));
+        if !wait_readable(&listener, timeout)? {
+            continue;
+        }
+
+        let Ok((mut stream, _)) = listener.accept() else {
+            continue;
+        };
+        // The socket mode already keeps others out; this also covers a loosened mode
+        if peer_uid(&stream).ok() != Some(my_uid) {
+            continue;
+        }
+        // The listener is non-blocking; the connection mustn't be
+        if stream.set_nonblocking(false).is_err()
+            || stream.set_read_timeout(Some(IO_TIMEOUT)).is_err()
+            || stream.set_write_timeout(Some(IO_TIMEOUT)).is_err()
+        {
+            continue;
+        }
+
+        let mut line = Zeroizing::new(String::new());
+        if BufReader::new(&stream).read_line(&mut line).is_err() {
+            continue;
+        }
+        let (mut response, stop) = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => agent.handle(request),
+            Err(e) => (
+                Response {
+                    error: Some(format!("Bad request: {}", e)),
+                    ..Default::default()
+                },
+                false,
+            ),
+        };
+        if let Ok(json) = serde_json::to_string(&response) {
+            let json = Zeroizing::new(json);
+            let _ = writeln!(stream, "{}", json.as_str());
+        }
+        if let Some(value) = &mut response.value {
+            value.zeroize();
+        }
+        if stop {
+            break;
+        }
+    }
+
+    let _ = std::fs::remove_file(&path);
+    Ok(())
+}
+
+/// Wait until a connection is pending or `timeout` (forever if `None`) passes.
+/// Returns whether one is pending.
+#[cfg(unix)]
+fn wait_readable(
+    listener: &std::os::unix::net::UnixListener,
+    timeout: Option<Duration>,
+) -> Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut fd = libc::pollfd {
+        fd: listener.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // Round up, so we don't wake just before the entry expires
+    let millis = timeout.map_or(-1, |t| {
+        t.as_millis().saturating_add(1).min(i32::MAX as u128) as libc::c_int
+    });
+    let rc = unsafe { libc::poll(&mut fd, 1, millis) };
+    if rc < 0 {
+        let err = std::io::Error::last_os_error();
+        if err.kind() == std::io::ErrorKind::Interrupted {
+            return Ok(false);
+        }
+        return Err(err).context("Failed to wait for agent connections");
+    }
+    Ok(rc > 0)
+}
+
+/// UID of the process on the other end of the socket
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(stream: &std::os::unix::net::UnixStream) -> std::io::Result<u32> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let rc = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(cred.uid)
+}
+
+/// UID of the process on the other end of the socket
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+fn peer_uid(stream: &std::os::unix::net::UnixStream) -> std::io::Result<u32> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut uid = 0;
+    let mut gid = 0;
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(uid)
+}
+
+#[cfg(unix)]
+fn request(request: &Request) -> Result<Response> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use std::os::unix::net::UnixStream;
+
+    let path = socket_path()?;
+    let meta = std::fs::metadata(&path).context("No agent running")?;
+    // Never hand keys to (or take keys from) a socket someone else could have planted
+    if meta.uid() != unsafe { libc::geteuid() } || meta.permissions().mode() & 0o077 != 0 {
+        return Err(anyhow::anyhow!(
+            "Agent socket {:?} is not private to this user; ignoring it",
+            path
+        ));
+    }
+
+    let mut stream = UnixStream::connect(&path).context("No agent running")?;
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+    let json = Zeroizing::new(serde_json::to_string(request)?);
+    writeln!(stream, "{}", json.as_str())?;
+
+    let mut line = Zeroizing::new(String::new());
+    BufReader::new(&stream).read_line(&mut line)?;
+    let mut response: Response = serde_json::from_str(&line).context("Bad agent response")?;
+    if let Some(error) = response.error.take() {
+        return Err(anyhow::anyhow!("Agent error: {}", error));
+    }
+    Ok(response)
+}
+
+#[cfg(not(unix))]
+fn request(_request: &Request) -> Result<Response> {
+    Err(anyhow::anyhow!("The agent needs Unix sockets"))
+}
+
+pub fn is_running() -> bool {
+    request(&Request::Status).is_ok()
+}
+
+pub fn status() -> Result<AgentStatus> {
+    request(&Request::Status)?
+        .status
+        .take()
+        .context("Agent sent no status")
+}
+
+/// Forget every identity and key (the agent keeps running)
+pub fn lock() -> Result<()> {
+    request(&Request::Lock).map(|_| ())
+}
+
+pub fn stop() -> Result<()> {
+    request(&Request::Stop).map(|_| ())
+}
+
+/// A cached repo key. `None` if there is no agent or it doesn't hold `label`.
+pub fn get_key(label: &str) -> Option<Zeroizing<Vec<u8>>> {
+    let mut response = request(&Request::GetKey {
+        label: label.to_string(),
+    })
+    .ok()?;
+    let value = Zeroizing::new(response.value.take()?);
+    hex::decode(value.as_str()).ok().map(Zeroizing::new)
+}
+
+/// Hand an unlocked repo key to the agent. Best effort: without an agent this is a no-op.
+pub fn put_key(label: &str, key: &[u8]) {
+    let _ = request(&Request::PutKey {
+        label: label.to_string(),
+        key: hex::encode(key),
+    });
+}
+
+/// The cached master identity (`AGE-SECRET-KEY-...`), if the agent holds one
+pub fn get_identity() -> Option<Zeroizing<String>> {
+    request(&Request::GetIdentity)
+        .ok()?
+        .value
+        .take()
+        .map(Zeroizing::new)
+}
+
+pub fn put_identity(secret: &str) -> Result<()> {
+    request(&Request::PutIdentity {
+        secret: secret.to_string(),
+    })
+    .map(|_| ())
+}
+
+/// Parse `30m`, `2h`, `1d` or plain seconds
+pub fn parse_lifetime(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    let (number, unit) = match s.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&s[..i], c),
+        _ => (s, 's'),
+    };
+    let n: u64 = number
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid lifetime '{}' (e.g. 30m, 2h, 3600)", s))?;
+    let multiplier = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86400,
+        _ => return Err(anyhow::anyhow!("Invalid lifetime unit in '{}'", s)),
+    };
+    let secs = n
+        .checked_mul(multiplier)
+        .filter(|&secs| {
+            Instant::now()
+                .checked_add(Duration::from_secs(secs))
+                .is_some()
+        })
+        .ok_or_else(|| anyhow::anyhow!("Lifetime '{}' is too long", s))?;
+    if secs == 0 {
+        return Err(anyhow::anyhow!("Lifetime must be more than zero"));
+    }
+    Ok(Duration::from_secs(secs))
+}
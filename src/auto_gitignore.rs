@@ -60,7 +60,6 @@ pub const ALWAYS_IGNORE: &[&str] = &[
     "*.p12",
     // Arcane internal
     ".arcane/shadow/",
-    ".arcane/keys/",
 ];
 
 /// Patterns that indicate sensitive files
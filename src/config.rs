@@ -1,7 +1,11 @@
 use crate::ai_service::{AIConfig, AIProvider};
 use serde::{Deserialize, Serialize};
 
+pub mod dotenv;
 pub mod env;
+pub mod fields;
+pub mod scanner;
+pub mod schema;
 
 use std::collections::HashMap;
 use std::fs;
@@ -28,7 +32,6 @@ pub const DEFAULT_SYSTEM_PROMPT: &str = r#"You are a Lead Software Engineer and
 pub const DEFAULT_IGNORE_PATTERNS: &[&str] = &[
     // Arcane internal
     ".arcane/shadow/",
-    ".arcane/keys/",
     ".gemini/",
     // IDE & Editor
     ".vscode/",
@@ -75,7 +78,7 @@ pub const DEFAULT_IGNORE_PATTERNS: &[&str] = &[
 
 pub const DEFAULT_GITATTRIBUTES_PATTERNS: &[&str] = &[
     // Auto-encrypt .env files with git-arcane filter
-    "*.env filter=git-arcane diff=git-arcane",
+    "*.env filter=git-arcane diff=git-arcane merge=git-arcane",
     // Binary files (don't diff)
     "*.lock binary",
     "*.png binary",
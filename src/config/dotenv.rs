@@ -0,0 +1,391 @@
+//! Dotenv parsing: `KEY=value` lines with single, double and backtick quotes, escapes,
+//! multiline values, an optional `export ` prefix and `${VAR}` / `${VAR:-default}`
+//! expansion.
+//!
+//! Expansion only sees variables set earlier in the file or in an earlier layer
+//! (`base.env` before `<env>.env`), never the shell's environment, so an env file
+//! resolves the same way on every machine.
+
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quote {
+    None,
+    /// `'...'`: literal
+    Single,
+    /// `"..."`: escapes and expansion
+    Double,
+    /// `` `...` ``: literal, for values with both kinds of quotes
+    Backtick,
+}
+
+/// One logical line of a dotenv file. A quoted value can span several physical lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Line<'a> {
+    Entry {
+        key: &'a str,
+        /// The value as written: between the quotes, or without its inline comment
+        value: &'a str,
+        quote: Quote,
+        /// Every physical line of the entry, without the final newline
+        raw: &'a str,
+        /// 1-based line the entry starts on
+        line: usize,
+    },
+    /// Blank line or comment
+    Other(&'a str),
+    /// Not something we can read; tools that rewrite files keep it as is
+    Invalid {
+        raw: &'a str,
+        line: usize,
+        reason: &'static str,
+    },
+}
+
+/// A reference to a variable nothing set before it. It expands to nothing, which is
+/// rarely what was meant: `PASS=ab$cd` is `ab`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Undefined {
+    /// The entry the reference is in
+    pub key: String,
+    /// The variable it refers to
+    pub name: String,
+    pub line: usize,
+}
+
+impl fmt::Display for Undefined {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}: {} refers to ${}, which isn't set (quote the value if the $ is literal)",
+            self.line, self.key, self.name
+        )
+    }
+}
+
+/// Split dotenv content into logical lines. Never fails: see `Line::Invalid`.
+pub fn lines(content: &str) -> Vec<Line<'_>> {
+    let mut lines = Vec::new();
+    let (mut start, mut number) = (0, 1);
+    while start < content.len() {
+        let (line, end) = lex_line(content, start, number);
+        lines.push(line);
+        number += content[start..end].matches('\n').count() + 1;
+        start = end + 1;
+    }
+    lines
+}
+
+/// Parse dotenv content into `vars`. Values are expanded against what `vars` already
+/// holds, so parsing `base.env` and then `<env>.env` into one map layers them.
+/// Returns the references to variables that weren't set, for warnings.
+pub fn parse_into(content: &str, vars: &mut HashMap<String, String>) -> Result<Vec<Undefined>> {
+    let mut undefined = Vec::new();
+    for line in lines(content) {
+        match line {
+            Line::Entry {
+                key,
+                value,
+                quote,
+                line,
+                ..
+            } => {
+                let mut missing = Vec::new();
+                let value = resolve_with(value, quote, vars, &mut missing);
+                undefined.extend(missing.into_iter().map(|name| Undefined {
+                    key: key.to_string(),
+                    name,
+                    line,
+                }));
+                vars.insert(key.to_string(), value);
+            }
+            Line::Invalid { line, reason, .. } => {
+                return Err(anyhow!("line {}: {}", line, reason));
+            }
+            Line::Other(_) => {}
+        }
+    }
+    Ok(undefined)
+}
+
+pub fn parse(content: &str) -> Result<HashMap<String, String>> {
+    let mut vars = HashMap::new();
+    parse_into(content, &mut vars)?;
+    Ok(vars)
+}
+
+/// Final value of an entry: escapes applied and variables expanded
+pub fn resolve(value: &str, quote: Quote, vars: &HashMap<String, String>) -> String {
+    resolve_with(value, quote, vars, &mut Vec::new())
+}
+
+/// `resolve`, noting the names of unset variables it expanded to nothing in `missing`
+fn resolve_with(
+    value: &str,
+    quote: Quote,
+    vars: &HashMap<String, String>,
+    missing: &mut Vec<String>,
+) -> String {
+    match quote {
+        Quote::Single | Quote::Backtick => value.to_string(),
+        Quote::Double => expand(value, vars, true, missing),
+        Quote::None => expand(value, vars, false, missing),
+    }
+}
+
+/// `value` the way it should be written after `KEY=`: quoted only when it has to be
+pub fn quote(value: &str) -> String {
+    let plain = value.trim() == value
+        && !value.contains(['\n', '\r', '"', '\'', '`', '$'])
+        && !value.contains(" #")
+        && !value.contains("\t#");
+    if plain {
+        value.to_string()
+    } else if !value.contains('\'') {
+        format!("'{}'", value)
+    } else {
+        let escaped = value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('$', "\\$")
+            .replace('\r', "\\r");
+        format!("\"{}\"", escaped)
+    }
+}
+
+/// Letters, digits, `_`, `.` and `-`, not starting with a digit
+pub fn is_valid_key(key: &str) -> bool {
+    key.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+}
+
+/// Lex the logical line starting at `start`. Returns it and where it ends
+/// (the index of its last newline, or the end of the content).
+fn lex_line(content: &str, start: usize, number: usize) -> (Line<'_>, usize) {
+    let eol = line_end(content, start);
+    let physical = trim_cr(&content[start..eol]);
+    let text = physical.trim_start();
+    if text.is_empty() || text.starts_with('#') {
+        return (Line::Other(physical), eol);
+    }
+    let invalid = |reason| {
+        let line = Line::Invalid {
+            raw: physical,
+            line: number,
+            reason,
+        };
+        (line, eol)
+    };
+
+    let mut pos = start + (physical.len() - text.len());
+    if let Some(rest) = text.strip_prefix("export") {
+        if rest.starts_with([' ', '\t']) {
+            pos += text.len() - rest.trim_start().len();
+        }
+    }
+    let Some(eq) = content[pos..eol].find('=') else {
+        return invalid("expected KEY=value");
+    };
+    let key = content[pos..pos + eq].trim_end();
+    if !is_valid_key(key) {
+        return invalid("invalid variable name");
+    }
+
+    let after_eq = pos + eq + 1;
+    let rest = trim_cr(&content[after_eq..eol]);
+    let quote = match rest.trim_start().chars().next() {
+        Some('\'') => Quote::Single,
+        Some('"') => Quote::Double,
+        Some('`') => Quote::Backtick,
+        _ => {
+            let value = strip_comment(rest).trim();
+            let line = Line::Entry {
+                key,
+                value,
+                quote: Quote::None,
+                raw: physical,
+                line: number,
+            };
+            return (line, eol);
+        }
+    };
+
+    let body = after_eq + (rest.len() - rest.trim_start().len()) + 1;
+    let Some(close) = closing_quote(&content[body..], quote) else {
+        return invalid("unterminated quote");
+    };
+    let close = body + close;
+    let end = line_end(content, close);
+    let raw = trim_cr(&content[start..end]);
+    let trailing = trim_cr(&content[close + 1..end]).trim();
+    if !trailing.is_empty() && !trailing.starts_with('#') {
+        let line = Line::Invalid {
+            raw,
+            line: number,
+            reason: "unexpected text after closing quote",
+        };
+        return (line, end);
+    }
+    let line = Line::Entry {
+        key,
+        value: &content[body..close],
+        quote,
+        raw,
+        line: number,
+    };
+    (line, end)
+}
+
+fn line_end(content: &str, from: usize) -> usize {
+    content[from..]
+        .find('\n')
+        .map_or(content.len(), |i| from + i)
+}
+
+fn trim_cr(line: &str) -> &str {
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+/// Index of the quote that closes a value; only double quotes have escapes
+fn closing_quote(body: &str, quote: Quote) -> Option<usize> {
+    let delimiter = match quote {
+        Quote::Single => '\'',
+        Quote::Double => '"',
+        Quote::Backtick => '`',
+        Quote::None => return None,
+    };
+    let mut chars = body.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == '\\' && quote == Quote::Double {
+            chars.next();
+        } else if c == delimiter {
+            return Some(i);
+        }
+    }
+    None
+}
+
+/// Cut an unquoted value at ` #`. `COLOR=#fff` keeps its `#`.
+fn strip_comment(value: &str) -> &str {
+    let bytes = value.as_bytes();
+    (1..bytes.len())
+        .find(|&i| bytes[i] == b'#' && matches!(bytes[i - 1], b' ' | b'\t'))
+        .map_or(value, |i| &value[..i])
+}
+
+fn expand(
+    value: &str,
+    vars: &HashMap<String, String>,
+    escapes: bool,
+    missing: &mut Vec<String>,
+) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        match c {
+            '\\' if escapes => {
+                let Some(next) = rest.chars().next() else {
+                    out.push('\\');
+                    break;
+                };
+                rest = &rest[next.len_utf8()..];
+                match next {
+                    'n' => out.push('\n'),
+                    'r' => out.push('\r'),
+                    't' => out.push('\t'),
+                    '"' | '\\' | '$' => out.push(next),
+                    _ => {
+                        out.push('\\');
+                        out.push(next);
+                    }
+                }
+            }
+            '$' => match variable(rest, vars, escapes, missing) {
+                Some((text, after)) => {
+                    out.push_str(&text);
+                    rest = after;
+                }
+                None => out.push('$'),
+            },
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Expand the variable reference after a `$`: `NAME`, `{NAME}`, `{NAME:-default}`
+/// (default when unset or empty) or `{NAME-default}` (default when unset).
+/// Unset variables expand to nothing (and go in `missing`, unless there's a default);
+/// `None` means it isn't a reference at all.
+fn variable<'a>(
+    rest: &'a str,
+    vars: &HashMap<String, String>,
+    escapes: bool,
+    missing: &mut Vec<String>,
+) -> Option<(String, &'a str)> {
+    let name_len = |s: &str| {
+        s.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(s.len())
+    };
+
+    let Some(inner) = rest.strip_prefix('{') else {
+        let len = name_len(rest);
+        if len == 0 || rest.starts_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+        let name = &rest[..len];
+        let value = vars.get(name).cloned().unwrap_or_else(|| {
+            missing.push(name.to_string());
+            String::new()
+        });
+        return Some((value, &rest[len..]));
+    };
+
+    let close = matching_brace(inner)?;
+    let (expr, after) = (&inner[..close], &inner[close + 1..]);
+    let (name, operator) = expr.split_at(name_len(expr));
+    if name.is_empty() {
+        return None;
+    }
+    let value = vars.get(name);
+    let expanded = if operator.is_empty() {
+        value.cloned().unwrap_or_else(|| {
+            missing.push(name.to_string());
+            String::new()
+        })
+    } else if let Some(default) = operator.strip_prefix(":-") {
+        match value {
+            Some(value) if !value.is_empty() => value.clone(),
+            _ => expand(default, vars, escapes, missing),
+        }
+    } else if let Some(default) = operator.strip_prefix('-') {
+        match value {
+            Some(value) => value.clone(),
+            None => expand(default, vars, escapes, missing),
+        }
+    } else {
+        return None;
+    };
+    Some((expanded, after))
+}
+
+/// The `}` closing a `${`, skipping nested ones in defaults
+fn matching_brace(inner: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in inner.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
@@ -2,19 +2,34 @@ use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Environment {
     pub name: String,
     pub variables: HashMap<String, String>,
+    /// File each variable was last set in, relative to the project root
+    #[serde(default)]
+    pub sources: HashMap<String, PathBuf>,
+    /// `$NAME` references to variables that weren't set, as `<file>: <problem>`
+    #[serde(skip)]
+    pub undefined: Vec<String>,
 }
 
+use crate::config::dotenv;
+use crate::config::fields::{self, StructuredFormat};
+use crate::config::schema::SCHEMA_FILE;
 use crate::security::{ArcaneSecurity, RepoKey};
 
+/// Extensions an environment file can have, in the order they're looked for.
+/// YAML, JSON and TOML files are flattened, and may have field-sealed values.
+const ENV_FILE_EXTENSIONS: &[&str] = &["env", "yaml", "yml", "json", "toml"];
+
 impl Environment {
     /// Load an environment by name (e.g., "staging", "production")
     /// Merges base.env with [name].env. Supports encrypted files.
+    /// Either layer may be YAML, JSON or TOML instead (`base.yaml`, `production.toml`).
+    /// `${VAR}` in [name].env can refer to base.env.
     pub fn load(
         name: &str,
         project_root: &Path,
@@ -22,48 +37,156 @@ impl Environment {
         repo_key: Option<&RepoKey>,
     ) -> Result<Self> {
         let envs_dir = project_root.join("config").join("envs");
+        let mut env = Self {
+            name: name.to_string(),
+            variables: HashMap::new(),
+            sources: HashMap::new(),
+            undefined: Vec::new(),
+        };
 
         // 1. Load base.env (if exists)
-        let mut variables = HashMap::new();
-        let base_path = envs_dir.join("base.env");
-        if base_path.exists() {
-            let base_vars = load_and_decrypt(&base_path, security, repo_key)?;
-            variables.extend(base_vars);
+        if let Some(base_path) = env_file(&envs_dir, "base") {
+            env.load_layer(&base_path, project_root, security, repo_key)?;
         }
 
         // 2. Load specific env file (e.g. staging.env)
-        let env_path = envs_dir.join(format!("{}.env", name));
-        if env_path.exists() {
-            let env_vars = load_and_decrypt(&env_path, security, repo_key)?;
-            variables.extend(env_vars);
+        if let Some(env_path) = env_file(&envs_dir, name) {
+            env.load_layer(&env_path, project_root, security, repo_key)?;
         } else if name != "staging" && name != "production" {
             // Check if it exists in root (legacy support for simple .env)
             let legacy_path = project_root.join(format!("{}.env", name));
             if legacy_path.exists() {
-                let env_vars = load_and_decrypt(&legacy_path, security, repo_key)?;
-                variables.extend(env_vars);
+                env.load_layer(&legacy_path, project_root, security, repo_key)?;
             }
         }
 
-        Ok(Self {
-            name: name.to_string(),
-            variables,
-        })
+        Ok(env)
     }
+
+    /// Read one file on top of what's loaded so far, and note where its keys came from
+    fn load_layer(
+        &mut self,
+        path: &Path,
+        project_root: &Path,
+        security: &ArcaneSecurity,
+        repo_key: Option<&RepoKey>,
+    ) -> Result<()> {
+        let (keys, undefined) = load_into(path, security, repo_key, &mut self.variables)?;
+        let source = path.strip_prefix(project_root).unwrap_or(path);
+        for key in keys {
+            self.sources.insert(key, source.to_path_buf());
+        }
+        self.undefined.extend(
+            undefined
+                .into_iter()
+                .map(|u| format!("{}: {}", source.display(), u)),
+        );
+        Ok(())
+    }
+}
+
+/// How two environments differ. Keys are sorted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EnvDiff {
+    pub only_left: Vec<String>,
+    pub only_right: Vec<String>,
+    /// Set in both, to different values
+    pub changed: Vec<String>,
+    /// Number of keys set to the same value in both
+    pub same: usize,
 }
 
-fn load_and_decrypt(
+pub fn diff_envs(left: &Environment, right: &Environment) -> EnvDiff {
+    let mut diff = EnvDiff::default();
+    for (key, value) in &left.variables {
+        match right.variables.get(key) {
+            None => diff.only_left.push(key.clone()),
+            Some(other) if other != value => diff.changed.push(key.clone()),
+            Some(_) => diff.same += 1,
+        }
+    }
+    diff.only_right = right
+        .variables
+        .keys()
+        .filter(|key| !left.variables.contains_key(*key))
+        .cloned()
+        .collect();
+    diff.only_left.sort();
+    diff.only_right.sort();
+    diff.changed.sort();
+    diff
+}
+
+/// Environment a file belongs to, for files that get an environment key:
+/// `config/envs/<env>.<ext>` (any of `ENV_FILE_EXTENSIONS`), or `<env>.env` at the
+/// repo root (legacy layout). `base.*` is shared by every environment and has none,
+/// and `schema.toml` isn't an environment.
+pub fn env_name_for_path(path: &str) -> Option<&str> {
+    let path = path.trim_start_matches("./");
+    let (dir, file) = path.rsplit_once('/').unwrap_or(("", path));
+    let name = match dir {
+        "" => file.strip_suffix(".env")?,
+        "config/envs" if path != SCHEMA_FILE => ENV_FILE_EXTENSIONS
+            .iter()
+            .find_map(|ext| file.strip_suffix(ext)?.strip_suffix('.'))?,
+        _ => return None,
+    };
+    if name.is_empty() || name == "base" || name.starts_with('.') {
+        return None;
+    }
+    Some(name)
+}
+
+/// `<dir>/<name>.env`, or the first of its YAML/JSON/TOML variants that exists.
+/// `schema.toml` describes the environments and isn't one.
+fn env_file(dir: &Path, name: &str) -> Option<PathBuf> {
+    ENV_FILE_EXTENSIONS
+        .iter()
+        .map(|ext| dir.join(format!("{}.{}", name, ext)))
+        .filter(|path| !path.ends_with(SCHEMA_FILE))
+        .find(|path| path.exists())
+}
+
+/// The file holding an environment's own variables (not `base`'s), found the way
+/// `Environment::load` finds it. `config/envs/<name>.env` if there's none yet.
+pub fn env_file_path(project_root: &Path, name: &str) -> PathBuf {
+    let envs_dir = project_root.join("config").join("envs");
+    if let Some(path) = env_file(&envs_dir, name) {
+        return path;
+    }
+    let legacy_path = project_root.join(format!("{}.env", name));
+    if name != "staging" && name != "production" && legacy_path.exists() {
+        return legacy_path;
+    }
+    envs_dir.join(format!("{}.env", name))
+}
+
+/// Decrypted text of an env file, as a smudged checkout would have it
+pub fn read_env_file(
     path: &Path,
     security: &ArcaneSecurity,
     repo_key: Option<&RepoKey>,
-) -> Result<HashMap<String, String>> {
+) -> Result<String> {
     let content = fs::read(path).with_context(|| format!("Failed to read env file: {:?}", path))?;
 
-    // Hybrid Mode: Try decrypt (if key exists), fallback to plaintext
-    let decrypted_bytes = if let Some(key) = repo_key {
+    let decrypted_bytes = if ArcaneSecurity::is_sealed(&content) {
+        // Sealed file: the header names its key, so a failure here is a real error
+        match repo_key.map(|key| security.decrypt_with_repo_key(key, &content)) {
+            Some(Ok(d)) => d,
+            _ => {
+                let key = security
+                    .load_repo_key_for_blob(&content)
+                    .with_context(|| format!("No key can unlock {:?}", path))?;
+                security
+                    .decrypt_with_repo_key(&key, &content)
+                    .with_context(|| format!("Failed to decrypt {:?}", path))?
+            }
+        }
+    } else if let Some(key) = repo_key {
+        // Legacy headerless blobs can't be told apart from plaintext: try, then fall back
         match security.decrypt_with_repo_key(key, &content) {
             Ok(d) => d,
-            Err(_) => content, // Assume plaintext if decryption fails
+            Err(_) => content,
         }
     } else {
         content // No key, assume plaintext
@@ -72,15 +195,381 @@ fn load_and_decrypt(
     let content_str = String::from_utf8(decrypted_bytes)
         .context(format!("File {:?} is not valid UTF-8 text", path))?;
 
+    let format = StructuredFormat::from_path(path);
+    if let Some(format) = format.filter(|_| fields::has_sealed_fields(&content_str)) {
+        return security
+            .open_fields(&content_str, format, repo_key)
+            .with_context(|| format!("Failed to decrypt {:?}", path));
+    }
+    Ok(content_str)
+}
+
+/// Seal env file content like the clean filter would, and write it over the file.
+/// The working tree never holds the plaintext.
+pub fn write_env_file(
+    path: &Path,
+    project_root: &Path,
+    content: &str,
+    security: &ArcaneSecurity,
+) -> Result<()> {
+    let relative = path.strip_prefix(project_root).unwrap_or(path);
+    let sealed = security.seal_for_path(
+        &relative.to_string_lossy().replace('\\', "/"),
+        content.as_bytes(),
+    )?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // Write next to it and rename, so a failure never leaves half a file
+    let tmp = path.with_extension("arcane-tmp");
+    fs::write(&tmp, sealed)?;
+    fs::rename(&tmp, path).with_context(|| format!("Failed to write {:?}", path))?;
+    Ok(())
+}
+
+/// Read an env file on top of `variables`, which dotenv values can refer to.
+/// Returns the keys the file sets, and its references to variables that aren't set.
+fn load_into(
+    path: &Path,
+    security: &ArcaneSecurity,
+    repo_key: Option<&RepoKey>,
+    variables: &mut HashMap<String, String>,
+) -> Result<(Vec<String>, Vec<dotenv::Undefined>)> {
+    let content_str = read_env_file(path, security, repo_key)?;
+    if let Some(format) = StructuredFormat::from_path(path) {
+        let flat = fields::flatten(&content_str, format)
+            .with_context(|| format!("Failed to read {:?}", path))?;
+        let keys = flat.keys().cloned().collect();
+        variables.extend(flat);
+        return Ok((keys, Vec::new()));
+    }
+    let undefined = dotenv::parse_into(&content_str, variables)
+        .with_context(|| format!("Invalid env file {:?}", path))?;
+    let keys = dotenv::lines(&content_str)
+        .into_iter()
+        .filter_map(|line| match line {
+            dotenv::Line::Entry { key, .. } => Some(key.to_string()),
+            _ => None,
+        })
+        .collect();
+    Ok((keys, undefined))
+}
+
+/// Replace every value in dotenv content using `mask`, keeping keys, comments and layout.
+/// A multiline value is masked as one. Lines we can't read are masked whole.
+pub fn mask_env_values(content: &str, mask: impl Fn(&str) -> String) -> String {
+    let masked: Vec<String> = dotenv::lines(content)
+        .into_iter()
+        .map(|line| {
+            let raw = match line {
+                dotenv::Line::Other(raw) => return raw.to_string(),
+                dotenv::Line::Entry { raw, .. } | dotenv::Line::Invalid { raw, .. } => raw,
+            };
+            // Keep the spacing before the value, so masking adds no whitespace changes
+            match raw.split_once('=') {
+                Some((key, value)) if value.trim().is_empty() => format!("{}=", key),
+                Some((key, value)) => {
+                    let space = &value[..value.len() - value.trim_start().len()];
+                    format!("{}={}{}", key, space, mask(value.trim()))
+                }
+                None => {
+                    let indent = &raw[..raw.len() - raw.trim_start().len()];
+                    format!("{}{}", indent, mask(raw.trim()))
+                }
+            }
+        })
+        .collect();
+
+    let mut output = masked.join("\n");
+    if content.ends_with('\n') {
+        output.push('\n');
+    }
+    output
+}
+
+/// Set `key` in dotenv content, quoting the value if it needs it. The last entry that
+/// sets it is rewritten in place (keeping an `export ` prefix and its spacing);
+/// a new key is appended.
+pub fn set_env_value(content: &str, key: &str, value: &str) -> String {
+    let lines = parse_env_lines(content);
+    let last = lines
+        .iter()
+        .rposition(|line| matches!(line, EnvLine::Entry { key: k, .. } if *k == key));
+
+    let mut output: Vec<String> = lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| match line {
+            EnvLine::Entry { raw, .. } if Some(i) == last => {
+                let (lhs, _) = raw.split_once('=').unwrap_or((raw, ""));
+                format!("{}={}", lhs, dotenv::quote(value))
+            }
+            EnvLine::Entry { raw, .. } | EnvLine::Other(raw) => raw.to_string(),
+        })
+        .collect();
+    if last.is_none() {
+        output.push(format!("{}={}", key, dotenv::quote(value)));
+    }
+    output.join("\n") + "\n"
+}
+
+/// Remove every line that sets `key`; `None` if there's none
+pub fn unset_env_value(content: &str, key: &str) -> Option<String> {
+    let lines = parse_env_lines(content);
+    let kept: Vec<&str> = lines
+        .iter()
+        .filter_map(|line| match line {
+            EnvLine::Entry { key: k, .. } if *k == key => None,
+            EnvLine::Entry { raw, .. } | EnvLine::Other(raw) => Some(*raw),
+        })
+        .collect();
+    if kept.len() == lines.len() {
+        return None;
+    }
+    Some(if kept.is_empty() {
+        String::new()
+    } else {
+        kept.join("\n") + "\n"
+    })
+}
+
+/// Let the user edit `content` in `$VISUAL`/`$EDITOR` and return the result.
+/// The file lives in a private directory on tmpfs (`$XDG_RUNTIME_DIR` or `/dev/shm`)
+/// and is overwritten with zeros before it's removed. Without tmpfs, the plaintext
+/// would land on disk in the temp dir; that only happens if `allow_disk(temp_dir)`.
+pub fn edit_privately(
+    file_name: &str,
+    content: &str,
+    allow_disk: impl FnOnce(&Path) -> bool,
+) -> Result<String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+
+    let base = match tmpfs_dir() {
+        Some(dir) => dir,
+        None => {
+            let temp_dir = std::env::temp_dir();
+            if !allow_disk(&temp_dir) {
+                return Err(anyhow::anyhow!(
+                    "No tmpfs ($XDG_RUNTIME_DIR or /dev/shm) to edit in; not writing the plaintext to {:?}",
+                    temp_dir
+                ));
+            }
+            temp_dir
+        }
+    };
+    let scratch = ScratchDir::new(&base)?;
+    let path = scratch.path.join(file_name);
+    {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&path)?;
+        std::io::Write::write_all(&mut file, content.as_bytes())?;
+    }
+
+    // Through the shell, so EDITOR="code --wait" works
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(&path)
+        .status()
+        .with_context(|| format!("Failed to run editor '{}'", editor))?;
+    if !status.success() {
+        return Err(anyhow::anyhow!(
+            "Editor '{}' exited with {}",
+            editor,
+            status
+        ));
+    }
+    fs::read_to_string(&path).context("Edited file is not valid UTF-8 text")
+}
+
+/// Private scratch directory, wiped on drop
+struct ScratchDir {
+    path: PathBuf,
+}
+
+/// `$XDG_RUNTIME_DIR` or `/dev/shm`, whichever exists
+fn tmpfs_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_dir())
+        .or_else(|| Some(PathBuf::from("/dev/shm")).filter(|dir| dir.is_dir()))
+}
+
+impl ScratchDir {
+    fn new(base: &Path) -> Result<Self> {
+        let path = base.join(format!("arcane-edit-{}", uuid::Uuid::new_v4()));
+
+        let mut builder = fs::DirBuilder::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+        builder
+            .create(&path)
+            .with_context(|| format!("Failed to create {:?}", path))?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        // Editors may leave swap and backup files next to it: wipe everything
+        if let Ok(entries) = fs::read_dir(&self.path) {
+            for entry in entries.flatten() {
+                if let Ok(meta) = entry.metadata() {
+                    if meta.is_file() {
+                        let _ = fs::write(entry.path(), vec![0u8; meta.len() as usize]);
+                    }
+                }
+            }
+        }
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Result of a key-by-key three-way merge of dotenv content
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvMerge {
+    pub content: String,
+    /// Keys changed differently on both sides (written with conflict markers)
+    pub conflicts: Vec<String>,
+}
+
+/// A dotenv entry (all of its lines, if the value spans several) or anything else
+/// (comments, blanks, lines we can't read)
+enum EnvLine<'a> {
+    Entry {
+        key: &'a str,
+        value: &'a str,
+        raw: &'a str,
+    },
+    Other(&'a str),
+}
+
+fn parse_env_lines(content: &str) -> Vec<EnvLine<'_>> {
+    dotenv::lines(content)
+        .into_iter()
+        .map(|line| match line {
+            dotenv::Line::Entry { key, raw, .. } => EnvLine::Entry {
+                key,
+                value: raw.split_once('=').map_or("", |(_, value)| value.trim()),
+                raw,
+            },
+            dotenv::Line::Other(raw) | dotenv::Line::Invalid { raw, .. } => EnvLine::Other(raw),
+        })
+        .collect()
+}
+
+/// Map of key -> (value, raw line). Later duplicates win, like `load`.
+fn env_entries<'a>(lines: &[EnvLine<'a>]) -> HashMap<&'a str, (&'a str, &'a str)> {
     let mut map = HashMap::new();
-    for line in content_str.lines() {
-        let line = line.trim();
-        if line.is_empty() || line.starts_with('#') {
-            continue;
+    for line in lines {
+        if let EnvLine::Entry { key, value, raw } = line {
+            map.insert(*key, (*value, *raw));
+        }
+    }
+    map
+}
+
+/// Three-way merge of dotenv files, key by key.
+/// Layout and comments follow `ours`; keys added only in `theirs` are appended
+/// together with the comment lines directly above them.
+pub fn merge_env(base: &str, ours: &str, theirs: &str) -> EnvMerge {
+    let base_lines = parse_env_lines(base);
+    let ours_lines = parse_env_lines(ours);
+    let theirs_lines = parse_env_lines(theirs);
+
+    let base_map = env_entries(&base_lines);
+    let ours_map = env_entries(&ours_lines);
+    let theirs_map = env_entries(&theirs_lines);
+
+    let mut out: Vec<String> = Vec::new();
+    let mut conflicts: Vec<String> = Vec::new();
+
+    let value = |entry: Option<&(&str, &str)>| entry.map(|(v, _)| v.to_string());
+    let raw = |entry: Option<&(&str, &str)>| entry.map(|(_, r)| r.to_string());
+
+    // Resolve one key. Returns the line to emit (None = deleted) or a conflict.
+    let resolve = |key: &str| -> Result<Option<String>, ()> {
+        let (b, o, t) = (base_map.get(key), ours_map.get(key), theirs_map.get(key));
+        if value(o) == value(t) || value(t) == value(b) {
+            Ok(raw(o))
+        } else if value(o) == value(b) {
+            Ok(raw(t))
+        } else {
+            Err(())
+        }
+    };
+
+    let mut conflict_block = |key: &str, out: &mut Vec<String>| {
+        out.push("<<<<<<< ours".to_string());
+        if let Some(line) = raw(ours_map.get(key)) {
+            out.push(line);
+        }
+        out.push("=======".to_string());
+        if let Some(line) = raw(theirs_map.get(key)) {
+            out.push(line);
+        }
+        out.push(">>>>>>> theirs".to_string());
+        if !conflicts.iter().any(|k| k == key) {
+            conflicts.push(key.to_string());
+        }
+    };
+
+    for line in &ours_lines {
+        match line {
+            EnvLine::Other(raw) => out.push(raw.to_string()),
+            EnvLine::Entry { key, .. } => match resolve(key) {
+                Ok(Some(line)) => out.push(line),
+                Ok(None) => {}
+                Err(()) => conflict_block(key, &mut out),
+            },
         }
-        if let Some((key, value)) = line.split_once('=') {
-            map.insert(key.trim().to_string(), value.trim().to_string());
+    }
+
+    // Keys that only exist on their side (added by them, or deleted by us)
+    let mut pending_comments: Vec<&str> = Vec::new();
+    for line in &theirs_lines {
+        match line {
+            EnvLine::Other(raw) => {
+                if raw.trim().is_empty() {
+                    pending_comments.clear();
+                } else {
+                    pending_comments.push(raw);
+                }
+            }
+            EnvLine::Entry { key, .. } => {
+                let comments = std::mem::take(&mut pending_comments);
+                if ours_map.contains_key(key) {
+                    continue;
+                }
+                match resolve(key) {
+                    Ok(Some(line)) => {
+                        out.extend(comments.iter().map(|c| c.to_string()));
+                        out.push(line);
+                    }
+                    Ok(None) => {}
+                    Err(()) => conflict_block(key, &mut out),
+                }
+            }
         }
     }
-    Ok(map)
+
+    let mut content = out.join("\n");
+    if !content.is_empty() {
+        content.push('\n');
+    }
+
+    EnvMerge { content, conflicts }
 }
@@ -0,0 +1,648 @@
+//! Field-level encryption for YAML, JSON and TOML. Only the values of secret keys are
+//! sealed, in place, so the rest of the document stays readable and diffable.
+//!
+//! A sealed value is written as `"ENC[arcane,<base64>]"`. The ciphertext holds the key
+//! name and the value exactly as it was written (quotes, nested blocks and all), so
+//! opening a document gives back the original text byte for byte.
+
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::LazyLock;
+
+/// Which keys are secret, relative to the repo root
+pub const FIELDS_CONFIG_FILE: &str = ".arcane/fields.toml";
+
+const DEFAULT_SUFFIX: &str = "_secret";
+const MARKER_PREFIX: &str = "ENC[arcane,";
+
+/// A sealed value with the whitespace before it, which belongs to the sealed text
+static SEALED_FIELD: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"[ \t]*"ENC\[arcane,([A-Za-z0-9+/=]+)\]""#).unwrap());
+
+/// `key:` at the start of a YAML line, after any `- ` list markers
+static YAML_KEY: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r#"^(?P<prefix>[ \t]*(?:- +)*)(?P<key>"(?:[^"\\]|\\.)*"|'(?:[^']|'')*'|[^\s#'"\[\]{},&*!|>%@`][^#]*?)[ \t]*:(?P<rest>[ \t].*|)$"#,
+    )
+    .unwrap()
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StructuredFormat {
+    Yaml,
+    Json,
+    Toml,
+}
+
+impl StructuredFormat {
+    /// Format of a file, by extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "yaml" | "yml" => Some(Self::Yaml),
+            "json" => Some(Self::Json),
+            "toml" => Some(Self::Toml),
+            _ => None,
+        }
+    }
+
+    /// Format of a document whose path we don't know: the strictest one it parses as
+    pub fn detect(content: &str) -> Self {
+        [Self::Json, Self::Toml]
+            .into_iter()
+            .find(|format| format.parse(content).is_ok())
+            .unwrap_or(Self::Yaml)
+    }
+
+    fn spans(self, content: &str) -> Vec<ValueSpan> {
+        match self {
+            Self::Yaml => yaml_spans(content),
+            Self::Json => json_spans(content),
+            Self::Toml => toml_spans(content),
+        }
+    }
+
+    fn parse(self, content: &str) -> Result<Value> {
+        Ok(match self {
+            Self::Yaml => {
+                serde_json::to_value(serde_yaml::from_str::<serde_yaml::Value>(content)?)?
+            }
+            Self::Json => serde_json::from_str(content)?,
+            Self::Toml => toml::from_str(content)?,
+        })
+    }
+}
+
+/// `.arcane/fields.toml`: which keys get their values encrypted
+///
+/// ```toml
+/// encrypted_regex = "^(password|token|dsn)$"
+/// encrypted_suffix = "_secret"
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldsConfig {
+    /// Keys matching this regex are secret
+    #[serde(default)]
+    pub encrypted_regex: Option<String>,
+    /// Keys ending with this (case-insensitive) are secret; empty turns it off
+    #[serde(default = "default_suffix")]
+    pub encrypted_suffix: String,
+}
+
+fn default_suffix() -> String {
+    DEFAULT_SUFFIX.to_string()
+}
+
+impl Default for FieldsConfig {
+    fn default() -> Self {
+        Self {
+            encrypted_regex: None,
+            encrypted_suffix: default_suffix(),
+        }
+    }
+}
+
+impl FieldsConfig {
+    /// Load `.arcane/fields.toml` from a repo; defaults if it doesn't exist
+    pub fn load(repo_root: &Path) -> Result<Self> {
+        let path = repo_root.join(FIELDS_CONFIG_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(&path)?;
+        toml::from_str(&content).with_context(|| format!("Invalid {}", FIELDS_CONFIG_FILE))
+    }
+
+    pub fn rules(&self) -> Result<FieldRules> {
+        let regex = self
+            .encrypted_regex
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .with_context(|| format!("Invalid encrypted_regex in {}", FIELDS_CONFIG_FILE))?;
+        Ok(FieldRules {
+            regex,
+            suffix: self.encrypted_suffix.to_lowercase(),
+        })
+    }
+}
+
+pub struct FieldRules {
+    regex: Option<Regex>,
+    suffix: String,
+}
+
+impl FieldRules {
+    pub fn is_secret(&self, key: &str) -> bool {
+        (!self.suffix.is_empty() && key.to_lowercase().ends_with(&self.suffix))
+            || self.regex.as_ref().is_some_and(|r| r.is_match(key))
+    }
+}
+
+/// A `key: value` pair; `start..end` is everything after the `:` or `=` that makes up
+/// the value, leading whitespace included, trailing comment excluded
+#[derive(Debug)]
+struct ValueSpan {
+    key: String,
+    start: usize,
+    end: usize,
+}
+
+/// Seal the values of secret keys in place. `seal` encrypts one value.
+/// Returns `None` if no key in the document is secret.
+///
+/// Fails rather than leave a secret in plaintext: the result is parsed again, and
+/// every secret key must hold a sealed value.
+pub fn seal_fields(
+    content: &str,
+    format: StructuredFormat,
+    rules: &FieldRules,
+    mut seal: impl FnMut(&[u8]) -> Result<Vec<u8>>,
+) -> Result<Option<String>> {
+    let spans = format.spans(content);
+
+    let mut output = String::with_capacity(content.len());
+    let mut pos = 0;
+    let mut found = false;
+    for span in spans {
+        // Keys nested in a value that's already sealed go along with it
+        if span.start < pos || !rules.is_secret(&span.key) {
+            continue;
+        }
+        found = true;
+        let raw = &content[span.start..span.end];
+        output.push_str(&content[pos..span.start]);
+        if is_marker(raw.trim()) {
+            output.push_str(raw);
+        } else {
+            let mut payload = span.key.into_bytes();
+            payload.push(0);
+            payload.extend_from_slice(raw.as_bytes());
+            let sealed = BASE64.encode(seal(&payload)?);
+            output.push_str(&format!(" \"{}{}]\"", MARKER_PREFIX, sealed));
+        }
+        pos = span.end;
+    }
+    output.push_str(&content[pos..]);
+
+    if !found {
+        // Secret keys we couldn't find a value for are reported below; anything else,
+        // including a document that doesn't parse, is sealed whole instead
+        match format.parse(content) {
+            Ok(doc) if has_secret_key(&doc, rules) => {}
+            _ => return Ok(None),
+        }
+    }
+    check_sealed(&parse(format, &output)?, rules, "")?;
+    Ok(Some(output))
+}
+
+/// Replace every sealed value with the text it was sealed from. `open` decrypts one value.
+///
+/// A value only opens under the key it was sealed for, so ciphertext copied to another
+/// key (say, one the app logs) is an error instead of a silently moved secret.
+pub fn open_fields(
+    content: &str,
+    format: StructuredFormat,
+    mut open: impl FnMut(&[u8]) -> Result<Vec<u8>>,
+) -> Result<String> {
+    let spans = format.spans(content);
+    let mut output = String::with_capacity(content.len());
+    let mut pos = 0;
+    for caps in SEALED_FIELD.captures_iter(content) {
+        let whole = caps.get(0).unwrap();
+        // The innermost value the marker is in
+        let key = spans
+            .iter()
+            .filter(|span| span.start <= whole.start() && whole.end() <= span.end)
+            .max_by_key(|span| span.start)
+            .map(|span| span.key.as_str())
+            .context("Sealed value outside of a key")?;
+        let blob = BASE64
+            .decode(&caps[1])
+            .context("Invalid sealed field encoding")?;
+        let payload = open(&blob)?;
+        let nul = payload
+            .iter()
+            .position(|&b| b == 0)
+            .context("Invalid sealed field")?;
+        if payload[..nul] != *key.as_bytes() {
+            return Err(anyhow::anyhow!(
+                "The value of '{}' was sealed for '{}'; it was moved or copied",
+                key,
+                String::from_utf8_lossy(&payload[..nul])
+            ));
+        }
+        let raw = &payload[nul + 1..];
+        output.push_str(&content[pos..whole.start()]);
+        output.push_str(std::str::from_utf8(raw).context("Sealed field is not valid UTF-8")?);
+        pos = whole.end();
+    }
+    output.push_str(&content[pos..]);
+    Ok(output)
+}
+
+/// Whether a document has sealed values in it
+pub fn has_sealed_fields(content: &str) -> bool {
+    content.contains(MARKER_PREFIX) && SEALED_FIELD.is_match(content)
+}
+
+/// The ciphertext of every sealed value in a document
+pub fn sealed_field_blobs(content: &str) -> Vec<Vec<u8>> {
+    SEALED_FIELD
+        .captures_iter(content)
+        .filter_map(|caps| BASE64.decode(&caps[1]).ok())
+        .collect()
+}
+
+/// Byte ranges of the sealed values in a line, for the secret scanner to skip
+pub fn sealed_field_ranges(line: &str) -> Vec<Range<usize>> {
+    if !line.contains(MARKER_PREFIX) {
+        return Vec::new();
+    }
+    SEALED_FIELD.find_iter(line).map(|m| m.range()).collect()
+}
+
+/// Flatten an (opened) document into variables: nested keys are joined with `_` and
+/// upper-cased (`database.url` -> `DATABASE_URL`); lists are kept as JSON
+pub fn flatten(content: &str, format: StructuredFormat) -> Result<HashMap<String, String>> {
+    let mut vars = HashMap::new();
+    match parse(format, content)? {
+        Value::Object(map) => flatten_into(&mut vars, "", &Value::Object(map)),
+        _ => return Err(anyhow::anyhow!("Expected a map at the top level")),
+    }
+    Ok(vars)
+}
+
+fn flatten_into(vars: &mut HashMap<String, String>, prefix: &str, value: &Value) {
+    let text = match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let name = if prefix.is_empty() {
+                    key.to_uppercase()
+                } else {
+                    format!("{}_{}", prefix, key.to_uppercase())
+                };
+                flatten_into(vars, &name, value);
+            }
+            return;
+        }
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    };
+    vars.insert(prefix.to_string(), text);
+}
+
+fn parse(format: StructuredFormat, content: &str) -> Result<Value> {
+    format
+        .parse(content)
+        .with_context(|| format!("Invalid {:?} document", format))
+}
+
+fn is_marker(value: &str) -> bool {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix("]\""))
+        .is_some_and(|v| v.starts_with(MARKER_PREFIX))
+}
+
+fn has_secret_key(value: &Value, rules: &FieldRules) -> bool {
+    match value {
+        Value::Object(map) => map
+            .iter()
+            .any(|(k, v)| rules.is_secret(k) || has_secret_key(v, rules)),
+        Value::Array(items) => items.iter().any(|v| has_secret_key(v, rules)),
+        _ => false,
+    }
+}
+
+fn check_sealed(value: &Value, rules: &FieldRules, path: &str) -> Result<()> {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                if !rules.is_secret(key) {
+                    check_sealed(value, rules, &path)?;
+                    continue;
+                }
+                let sealed = value
+                    .as_str()
+                    .is_some_and(|s| is_marker(&format!("\"{}\"", s)));
+                if !sealed {
+                    return Err(anyhow::anyhow!(
+                        "Can't encrypt '{}' in place (inline tables and flow maps aren't supported)",
+                        path
+                    ));
+                }
+            }
+        }
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                check_sealed(item, rules, &format!("{}[{}]", path, i))?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start_matches([' ', '\t']).len()
+}
+
+fn yaml_spans(content: &str) -> Vec<ValueSpan> {
+    // Byte offset where each line starts, plus the end of the document
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let mut starts = Vec::with_capacity(lines.len() + 1);
+    let mut offset = 0;
+    for line in &lines {
+        starts.push(offset);
+        offset += line.len();
+    }
+    starts.push(offset);
+
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i].trim_end_matches(['\n', '\r']);
+        let Some(caps) = YAML_KEY.captures(line) else {
+            i += 1;
+            continue;
+        };
+        if line.trim_start().starts_with('#') {
+            i += 1;
+            continue;
+        }
+        let key_indent = caps["prefix"].len();
+        let rest = caps.name("rest").unwrap();
+        let value = rest.as_str().trim();
+        let empty = value.is_empty() || value.starts_with('#');
+
+        // Lines indented past the key (or a `- ` list at its level) belong to the value
+        let mut last = i;
+        let mut j = i + 1;
+        while j < lines.len() {
+            let next = lines[j].trim_end_matches(['\n', '\r']);
+            if next.trim().is_empty() {
+                j += 1;
+                continue;
+            }
+            let indent = indent_of(next);
+            let list_item = empty && indent == key_indent && next[indent..].starts_with('-');
+            if indent <= key_indent && !list_item {
+                break;
+            }
+            last = j;
+            j += 1;
+        }
+
+        let start = starts[i] + rest.start();
+        let end = if last > i {
+            starts[last] + lines[last].trim_end_matches(['\n', '\r']).len()
+        } else {
+            starts[i] + rest.start() + rest.as_str().len() - yaml_comment_len(rest.as_str())
+        };
+        spans.push(ValueSpan {
+            key: unquote(&caps["key"]),
+            start,
+            end,
+        });
+
+        // A block scalar's lines are text, not keys
+        i = if value.starts_with('|') || value.starts_with('>') {
+            last + 1
+        } else {
+            i + 1
+        };
+    }
+    spans
+}
+
+/// Length of a trailing ` # comment` (and the spaces before it) on a single-line value
+fn yaml_comment_len(rest: &str) -> usize {
+    let value = rest.trim_start();
+    if value.starts_with('#') {
+        return rest.len();
+    }
+    // A `#` inside a quoted value isn't a comment
+    let bytes = value.as_bytes();
+    let from = match bytes.first() {
+        Some(b'"' | b'\'') => string_end(bytes, 0),
+        _ => 0,
+    };
+    let end = bytes[from..]
+        .windows(2)
+        .position(|w| matches!(w[0], b' ' | b'\t') && w[1] == b'#')
+        .map_or(value.len(), |n| from + n);
+    value.len() - value[..end].trim_end().len()
+}
+
+fn unquote(key: &str) -> String {
+    let key = key.trim();
+    if key.len() >= 2
+        && ((key.starts_with('"') && key.ends_with('"'))
+            || (key.starts_with('\'') && key.ends_with('\'')))
+    {
+        key[1..key.len() - 1].to_string()
+    } else {
+        key.to_string()
+    }
+}
+
+/// End of a string starting at `i` (on the opening quote), past the closing quote
+fn string_end(bytes: &[u8], i: usize) -> usize {
+    let quote = bytes[i];
+    let triple = bytes[i..].starts_with(&[quote; 3]);
+    let mut j = if triple { i + 3 } else { i + 1 };
+    while j < bytes.len() {
+        if bytes[j] == b'\\' && quote == b'"' {
+            j += 2;
+            continue;
+        }
+        if triple {
+            if bytes[j..].starts_with(&[quote; 3]) {
+                // Up to two more quotes may close a multi-line string
+                let mut end = j + 3;
+                while end < bytes.len() && bytes[end] == quote && end < j + 5 {
+                    end += 1;
+                }
+                return end;
+            }
+        } else if bytes[j] == quote || bytes[j] == b'\n' {
+            return j + 1;
+        }
+        j += 1;
+    }
+    bytes.len()
+}
+
+/// End of a JSON or TOML value starting at or after `i`. Strings and nested
+/// arrays/objects are followed to their end; anything else runs to a delimiter.
+fn value_end(bytes: &[u8], mut i: usize, toml: bool) -> usize {
+    while i < bytes.len() && matches!(bytes[i], b' ' | b'\t') {
+        i += 1;
+    }
+    if i >= bytes.len() {
+        return i;
+    }
+    match bytes[i] {
+        b'"' => string_end(bytes, i),
+        b'\'' if toml => string_end(bytes, i),
+        b'[' | b'{' => {
+            let mut depth = 0;
+            let mut j = i;
+            while j < bytes.len() {
+                match bytes[j] {
+                    b'"' => {
+                        j = string_end(bytes, j);
+                        continue;
+                    }
+                    b'\'' if toml => {
+                        j = string_end(bytes, j);
+                        continue;
+                    }
+                    b'#' if toml => {
+                        while j < bytes.len() && bytes[j] != b'\n' {
+                            j += 1;
+                        }
+                        continue;
+                    }
+                    b'[' | b'{' => depth += 1,
+                    b']' | b'}' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return j + 1;
+                        }
+                    }
+                    _ => {}
+                }
+                j += 1;
+            }
+            bytes.len()
+        }
+        _ => {
+            let mut j = i;
+            while j < bytes.len() {
+                let b = bytes[j];
+                if b == b'\n' || (toml && b == b'#') || (!toml && matches!(b, b',' | b'}' | b']')) {
+                    break;
+                }
+                j += 1;
+            }
+            while j > i && bytes[j - 1].is_ascii_whitespace() {
+                j -= 1;
+            }
+            j
+        }
+    }
+}
+
+fn json_spans(content: &str) -> Vec<ValueSpan> {
+    let bytes = content.as_bytes();
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'"' {
+            i += 1;
+            continue;
+        }
+        let end = string_end(bytes, i);
+        let mut colon = end;
+        while colon < bytes.len() && bytes[colon].is_ascii_whitespace() {
+            colon += 1;
+        }
+        if colon < bytes.len() && bytes[colon] == b':' {
+            let key = serde_json::from_str::<String>(&content[i..end])
+                .unwrap_or_else(|_| content[i + 1..end - 1].to_string());
+            spans.push(ValueSpan {
+                key,
+                start: colon + 1,
+                end: value_end(bytes, colon + 1, false),
+            });
+            // Keep going inside the value, for nested keys
+            i = colon + 1;
+        } else {
+            i = end;
+        }
+    }
+    spans
+}
+
+fn toml_spans(content: &str) -> Vec<ValueSpan> {
+    let bytes = content.as_bytes();
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        // At the start of a line
+        let line_end = content[i..].find('\n').map_or(bytes.len(), |n| i + n);
+        let line = content[i..line_end].trim_start();
+        let first = line.bytes().next();
+        if matches!(first, None | Some(b'#') | Some(b'[')) {
+            i = line_end + 1;
+            continue;
+        }
+
+        // Dotted keys: the last part is the one that counts
+        let mut j = i + indent_of(&content[i..line_end]);
+        let mut key = String::new();
+        loop {
+            while j < line_end && matches!(bytes[j], b' ' | b'\t') {
+                j += 1;
+            }
+            if j >= line_end {
+                break;
+            }
+            let part_end = match bytes[j] {
+                b'"' | b'\'' => string_end(bytes, j),
+                _ => {
+                    let mut k = j;
+                    while k < line_end
+                        && (bytes[k].is_ascii_alphanumeric() || matches!(bytes[k], b'_' | b'-'))
+                    {
+                        k += 1;
+                    }
+                    k
+                }
+            };
+            key = unquote(&content[j..part_end]);
+            j = part_end;
+            while j < line_end && matches!(bytes[j], b' ' | b'\t') {
+                j += 1;
+            }
+            if j < line_end && bytes[j] == b'.' {
+                j += 1;
+                continue;
+            }
+            break;
+        }
+        if j >= line_end || bytes[j] != b'=' || key.is_empty() {
+            i = line_end + 1;
+            continue;
+        }
+
+        let end = value_end(bytes, j + 1, true);
+        spans.push(ValueSpan {
+            key,
+            start: j + 1,
+            end,
+        });
+        // Carry on after the line the value ends on
+        i = content[end..]
+            .find('\n')
+            .map_or(bytes.len(), |n| end + n + 1);
+    }
+    spans
+}
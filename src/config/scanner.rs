@@ -0,0 +1,150 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use crate::security::Severity;
+
+/// Per-repo scanner settings, relative to the repo root
+pub const SCANNER_CONFIG_FILE: &str = ".arcane/scanner.toml";
+
+/// Findings that existed when the scanner was adopted, relative to the repo root
+pub const SCAN_BASELINE_FILE: &str = ".arcane/scan-baseline.json";
+
+/// 2: fingerprints hash the redacted line instead of the line itself
+const SCAN_BASELINE_VERSION: u8 = 2;
+
+/// `.arcane/scanner.toml`: extra rules, allowlists and built-in rules to turn off
+///
+/// ```toml
+/// disable = ["Generic Secret", "entropy"]
+///
+/// [[rules]]
+/// name = "Acme Internal Token"
+/// regex = "acme_[a-z0-9]{32}"
+/// severity = "critical"
+/// keywords = ["acme_"]
+///
+/// [allowlist]
+/// paths = ["tests/fixtures/**"]
+/// regexes = ["EXAMPLE"]
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScannerConfig {
+    /// Built-in rules to turn off, by name (`entropy` turns off the entropy detector)
+    #[serde(default)]
+    pub disable: Vec<String>,
+    #[serde(default)]
+    pub rules: Vec<CustomRule>,
+    #[serde(default)]
+    pub allowlist: Allowlist,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CustomRule {
+    pub name: String,
+    pub regex: String,
+    #[serde(default = "default_severity")]
+    pub severity: Severity,
+    /// Only lines containing one of these (case-insensitive) are matched against `regex`
+    #[serde(default)]
+    pub keywords: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Allowlist {
+    /// Glob patterns of files that are never reported
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// Findings on lines matching one of these are dropped
+    #[serde(default)]
+    pub regexes: Vec<String>,
+}
+
+fn default_severity() -> Severity {
+    Severity::High
+}
+
+impl ScannerConfig {
+    /// Load `.arcane/scanner.toml` from a repo; defaults if it doesn't exist
+    pub fn load(repo_root: &Path) -> Result<Self> {
+        let path = repo_root.join(SCANNER_CONFIG_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(&path)?;
+        toml::from_str(&content).with_context(|| format!("Invalid {}", SCANNER_CONFIG_FILE))
+    }
+}
+
+/// `.arcane/scan-baseline.json`, written by `arcane scan --update-baseline`.
+/// Findings whose fingerprint is listed here aren't reported again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanBaseline {
+    pub version: u8,
+    pub findings: Vec<BaselineEntry>,
+}
+
+/// One accepted finding. Holds no secret: only a hash of the line it was on, with the
+/// secret itself masked out (see `finding_fingerprint`).
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BaselineEntry {
+    pub path: String,
+    pub rule: String,
+    pub fingerprint: String,
+}
+
+impl Default for ScanBaseline {
+    fn default() -> Self {
+        Self {
+            version: SCAN_BASELINE_VERSION,
+            findings: Vec::new(),
+        }
+    }
+}
+
+impl ScanBaseline {
+    /// Load the repo's baseline; empty if it doesn't exist
+    pub fn load(repo_root: &Path) -> Result<Self> {
+        let path = repo_root.join(SCAN_BASELINE_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(&path)?;
+        let baseline: Self = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid {}", SCAN_BASELINE_FILE))?;
+        if baseline.version != SCAN_BASELINE_VERSION {
+            return Err(anyhow::anyhow!(
+                "Unsupported {} version {}; run 'arcane scan --update-baseline' to rewrite it",
+                SCAN_BASELINE_FILE,
+                baseline.version
+            ));
+        }
+        Ok(baseline)
+    }
+
+    /// Write the baseline, sorted so it diffs cleanly
+    pub fn save(&mut self, repo_root: &Path) -> Result<()> {
+        self.findings.sort();
+        self.findings.dedup();
+        let path = repo_root.join(SCAN_BASELINE_FILE);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut content = serde_json::to_string_pretty(self)?;
+        content.push('\n');
+        fs::write(&path, content)?;
+        Ok(())
+    }
+
+    pub fn fingerprints(&self) -> HashSet<String> {
+        self.findings
+            .iter()
+            .map(|f| f.fingerprint.clone())
+            .collect()
+    }
+}
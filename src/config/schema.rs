@@ -0,0 +1,199 @@
+//! `config/envs/schema.toml`: what each environment variable must look like, checked
+//! before a deploy so a missing or malformed value fails here instead of on the server.
+//!
+//! ```toml
+//! [DATABASE_URL]
+//! type = "url"
+//! description = "Postgres connection string"
+//!
+//! [LOG_LEVEL]
+//! type = "enum"
+//! values = ["debug", "info", "warn"]
+//! required = false
+//!
+//! [STRIPE_KEY]
+//! type = "regex"
+//! pattern = "sk_(test|live)_[A-Za-z0-9]+"
+//! environments = ["staging", "production"]
+//! ```
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Relative to the repo root. Not an environment, even though it sits with them.
+pub const SCHEMA_FILE: &str = "config/envs/schema.toml";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VarType {
+    /// Anything
+    #[default]
+    String,
+    /// `scheme://...`
+    Url,
+    Int,
+    /// `true`/`false`, `1`/`0`, `yes`/`no`
+    Bool,
+    /// One of `values`
+    Enum,
+    /// Matches all of `pattern`
+    Regex,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VarSpec {
+    /// Defaults to true: listing a variable usually means the app needs it
+    #[serde(default = "default_required")]
+    pub required: bool,
+    #[serde(rename = "type", default)]
+    pub kind: VarType,
+    pub description: Option<String>,
+    /// Environments the variable applies to; all of them if empty
+    #[serde(default)]
+    pub environments: Vec<String>,
+    /// Allowed values, for `enum`
+    #[serde(default)]
+    pub values: Vec<String>,
+    /// For `regex`
+    pub pattern: Option<String>,
+    #[serde(skip)]
+    regex: Option<Regex>,
+}
+
+fn default_required() -> bool {
+    true
+}
+
+/// A variable that doesn't match the schema. Never holds the value itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaViolation {
+    pub key: String,
+    pub problem: String,
+}
+
+impl fmt::Display for SchemaViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.key, self.problem)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EnvSchema {
+    pub variables: BTreeMap<String, VarSpec>,
+}
+
+impl EnvSchema {
+    /// The repo's schema, or `None` if it has none
+    pub fn load(project_root: &Path) -> Result<Option<Self>> {
+        let path = project_root.join(SCHEMA_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content =
+            fs::read_to_string(&path).with_context(|| format!("Failed to read {}", SCHEMA_FILE))?;
+        Self::parse(&content)
+            .with_context(|| format!("Invalid {}", SCHEMA_FILE))
+            .map(Some)
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut variables: BTreeMap<String, VarSpec> = toml::from_str(content)?;
+        for (key, spec) in variables.iter_mut() {
+            match spec.kind {
+                VarType::Enum if spec.values.is_empty() => {
+                    return Err(anyhow::anyhow!("{}: enum needs `values`", key));
+                }
+                VarType::Regex => {
+                    let pattern = spec
+                        .pattern
+                        .as_deref()
+                        .with_context(|| format!("{}: regex needs `pattern`", key))?;
+                    let regex = Regex::new(&format!("^(?:{})$", pattern))
+                        .with_context(|| format!("{}: invalid pattern", key))?;
+                    spec.regex = Some(regex);
+                }
+                _ => {}
+            }
+        }
+        Ok(Self { variables })
+    }
+
+    /// Check an environment's decrypted variables. Variables the schema doesn't
+    /// mention are fine.
+    pub fn check(&self, env_name: &str, vars: &HashMap<String, String>) -> Vec<SchemaViolation> {
+        let mut violations = Vec::new();
+        for (key, spec) in &self.variables {
+            if !spec.environments.is_empty() && !spec.environments.iter().any(|e| e == env_name) {
+                continue;
+            }
+            let problem = match vars.get(key).filter(|value| !value.is_empty()) {
+                None if spec.required => match &spec.description {
+                    Some(description) => format!("required ({}), but not set", description),
+                    None => "required, but not set".to_string(),
+                },
+                None => continue,
+                Some(value) => match spec.type_problem(value) {
+                    Some(problem) => problem,
+                    None => continue,
+                },
+            };
+            violations.push(SchemaViolation {
+                key: key.clone(),
+                problem,
+            });
+        }
+        violations
+    }
+}
+
+impl VarSpec {
+    /// What's wrong with `value`, worded without repeating it
+    fn type_problem(&self, value: &str) -> Option<String> {
+        let valid = match self.kind {
+            VarType::String => true,
+            VarType::Url => is_url(value),
+            VarType::Int => value.parse::<i64>().is_ok(),
+            VarType::Bool => matches!(
+                value.to_ascii_lowercase().as_str(),
+                "true" | "false" | "1" | "0" | "yes" | "no"
+            ),
+            VarType::Enum => self.values.iter().any(|v| v == value),
+            VarType::Regex => self.regex.as_ref().is_some_and(|re| re.is_match(value)),
+        };
+        if valid {
+            return None;
+        }
+        Some(match self.kind {
+            VarType::Enum => format!("expected one of {}", self.values.join(", ")),
+            VarType::Regex => format!(
+                "doesn't match /{}/",
+                self.pattern.as_deref().unwrap_or_default()
+            ),
+            VarType::Url => "expected a URL (scheme://...)".to_string(),
+            VarType::Int => "expected an integer".to_string(),
+            VarType::Bool => "expected true or false".to_string(),
+            VarType::String => unreachable!("strings are always valid"),
+        })
+    }
+}
+
+fn is_url(value: &str) -> bool {
+    let Some((scheme, rest)) = value.split_once("://") else {
+        return false;
+    };
+    scheme
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '.' | '-'))
+        && !rest.is_empty()
+        && !rest.contains(char::is_whitespace)
+}
@@ -190,46 +190,44 @@ fn perform_auto_commit(repo_path: &Path) -> Result<()> {
         // Only scan ADDED lines (starting with '+') to avoid false positives on removed secrets
         // Skip lines from examples/ directories (demo files with fake secrets)
 
-        // Parse diff to find current file being modified
-        let mut current_file = String::new();
-        let mut added_lines = Vec::new();
-
-        for line in diff.lines() {
-            if line.starts_with("+++ ") {
-                // Extract file path from diff header line: +++ b/path/to/file
-                current_file = line
-                    .trim_start_matches("+++ ")
-                    .trim_start_matches("b/")
-                    .to_string();
-            } else if line.starts_with('+') && !line.starts_with("+++") {
-                // Skip scanning for:
-                // - examples/ directories (demo files with fake secrets)
-                // - config/envs/ (managed by Arcane encryption)
-                // - .env files (will be encrypted by Arcane)
-                let is_arcane_managed = current_file.starts_with("examples/")
-                    || current_file.starts_with("config/envs/")
-                    || current_file.ends_with(".env")
-                    || current_file.contains("/examples/")
-                    || current_file.contains("demo");
-
-                if !is_arcane_managed {
-                    added_lines.push(line.to_string());
-                }
+        // Rules and allowlists from .arcane/scanner.toml. A broken config must not
+        // silently fall back to fewer rules, so nothing is committed until it's fixed.
+        let scanner = match crate::security::SecretScanner::for_repo(repo_path) {
+            Ok(scanner) => scanner,
+            Err(e) => {
+                crate::daemon::log_event(&format!(
+                    "❌ Scanner config error, not committing: {:#}",
+                    e
+                ));
+                return Ok(());
             }
-        }
+        };
 
-        let added_content = added_lines.join("\n");
+        // Skip scanning for:
+        // - examples/ directories (demo files with fake secrets)
+        // - config/envs/ (managed by Arcane encryption)
+        // - .env files (will be encrypted by Arcane)
+        let is_arcane_managed = |file: &str| {
+            file.starts_with("examples/")
+                || file.starts_with("config/envs/")
+                || file.ends_with(".env")
+                || file.contains("/examples/")
+                || file.contains("demo")
+        };
 
-        let scanner = crate::security::SecretScanner::new();
-        let matches = scanner.scan(&added_content);
+        // Findings on added lines, with the line number in the changed file
+        let matches: Vec<(PathBuf, crate::security::SecretFinding)> = scanner
+            .scan_diff(&diff)
+            .into_iter()
+            .filter(|(path, _)| !is_arcane_managed(&path.to_string_lossy()))
+            .flat_map(|(path, findings)| findings.into_iter().map(move |f| (path.clone(), f)))
+            .collect();
         if !matches.is_empty() {
             // Build detailed, actionable alert
             let secret_list: Vec<String> = matches
                 .iter()
                 .take(3) // Show max 3 to keep readable
-                .map(|m| format!("• Line {}: {} - \"{}\"", m.line, m.name, 
-                    if m.snippet.len() > 40 { format!("{}...", &m.snippet[..40]) } else { m.snippet.clone() }
-                ))
+                .map(|(path, m)| format!("• {}:{}:{}: {} ({})", path.display(), m.line, m.column, m.name, m.redacted))
                 .collect();
             
             let more_msg = if matches.len() > 3 {
@@ -247,17 +245,28 @@ fn perform_auto_commit(repo_path: &Path) -> Result<()> {
             crate::daemon::log_event(&log_msg);
 
             // Desktop notification (brief)
+            let (first_path, first) = &matches[0];
             notify_user(
                 "🛑 Secret Detected - Commit Blocked",
-                &format!("{} secret(s) found in source code. Check TUI for details.", matches.len()),
+                &format!(
+                    "{} secret(s) found in source code, e.g. {} ({}) in {}. Check TUI for details.",
+                    matches.len(),
+                    first.name,
+                    first.redacted,
+                    first_path.display()
+                ),
             );
 
             // Persist Alert to Status
             if let Some(mut status) = crate::DaemonStatus::load() {
                 status.last_alert = Some(format!(
-                    "{} - {} secret(s) blocked",
+                    "{} - {} secret(s) blocked, first: {}:{} {} ({})",
                     chrono::Local::now().format("%H:%M:%S"),
-                    matches.len()
+                    matches.len(),
+                    first_path.display(),
+                    first.line,
+                    first.name,
+                    first.redacted
                 ));
                 let _ = status.save();
             }
@@ -382,7 +391,7 @@ fn auto_init_repo(path: &Path) -> Result<()> {
             .open(attr_file)?;
         use std::io::Write;
         writeln!(file, "\n# Arcane Transparent Encryption")?;
-        writeln!(file, "*.env filter=git-arcane diff=git-arcane")?;
+        writeln!(file, "*.env filter=git-arcane diff=git-arcane merge=git-arcane")?;
     }
 
     // 2. Initialize Keys (Arcane Init)
@@ -1,3 +1,4 @@
+use crate::security::ArcaneSecurity;
 use serde::{Deserialize, Serialize};
 use std::path::Path;
 use std::process::Command;
@@ -38,6 +39,9 @@ impl ArcaneDoctor {
         // 2. Check Key Configuration
         checks.push(self.check_key_configuration(repo_path));
 
+        // 3. Check key files against the signed manifest
+        checks.push(self.check_key_manifest(repo_path));
+
         // Determine overall health
         let overall_health = if checks.iter().any(|c| matches!(c.status, CheckStatus::Fail)) {
             CheckStatus::Fail
@@ -101,7 +105,22 @@ impl ArcaneDoctor {
     }
 
     fn check_key_configuration(&self, repo_path: &Path) -> DoctorCheck {
-        let keys_dir = repo_path.join(".git").join("arcane").join("keys");
+        let tracked = ArcaneSecurity::tracked_keys_dir(repo_path);
+        let local = ArcaneSecurity::local_keys_dir(repo_path);
+        let keys_dir = ArcaneSecurity::keys_dir_for(repo_path);
+        let layout = if keys_dir == tracked {
+            "tracked keyring (.arcane/keys)"
+        } else {
+            "local keyring (.git/arcane/keys)"
+        };
+
+        if tracked.is_dir() && has_entries(&local) {
+            return DoctorCheck {
+                name: "Key Configuration".to_string(),
+                status: CheckStatus::Warning,
+                message: "Both .arcane/keys and .git/arcane/keys exist; only the tracked keyring is used. Remove the local one once the tracked keyring is committed.".to_string(),
+            };
+        }
 
         if !keys_dir.exists() {
             return DoctorCheck {
@@ -117,7 +136,7 @@ impl ArcaneDoctor {
                     DoctorCheck {
                         name: "Key Configuration".to_string(),
                         status: CheckStatus::Pass,
-                        message: "Repository keys found.".to_string(),
+                        message: format!("Repository keys found in {}.", layout),
                     }
                 } else {
                     DoctorCheck {
@@ -134,4 +153,43 @@ impl ArcaneDoctor {
             },
         }
     }
+
+    fn check_key_manifest(&self, repo_path: &Path) -> DoctorCheck {
+        let name = "Key Manifest".to_string();
+        match ArcaneSecurity::inspect_keyrings(repo_path) {
+            Ok(Some(audit)) if audit.is_clean() => DoctorCheck {
+                name,
+                status: CheckStatus::Pass,
+                message: format!(
+                    "All key files match the signed manifest ({} entries).",
+                    audit.entries
+                ),
+            },
+            Ok(Some(audit)) => DoctorCheck {
+                name,
+                status: CheckStatus::Fail,
+                message: format!(
+                    "Unaccounted keys: {}. Run 'arcane keys verify'.",
+                    audit.problems().join("; ")
+                ),
+            },
+            Ok(None) => DoctorCheck {
+                name,
+                status: CheckStatus::Pass,
+                message: "No signed key manifest (run 'arcane keys sign' to start one)."
+                    .to_string(),
+            },
+            Err(e) => DoctorCheck {
+                name,
+                status: CheckStatus::Fail,
+                message: format!("Key manifest failed verification: {:#}", e),
+            },
+        }
+    }
+}
+
+fn has_entries(dir: &Path) -> bool {
+    dir.read_dir()
+        .map(|mut entries| entries.next().is_some())
+        .unwrap_or(false)
 }
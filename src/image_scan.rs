@@ -0,0 +1,235 @@
+//! Secret scanning for Docker images: reads the `docker save` tarball layer by layer
+//! so a `.env` or key file baked in by a `.dockerignore` slip never reaches a server.
+
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::auto_gitignore::AutoGitIgnore;
+use crate::security::{looks_binary, SecretFinding, SecretScanner, MAX_SCAN_FILE_BYTES};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+/// A tar header is one 512-byte block with "ustar" at offset 257
+const TAR_BLOCK: usize = 512;
+const USTAR_OFFSET: usize = 257;
+
+/// Trees the base image's OS owns. Their contents aren't ours, and names like
+/// `pam.d/common-auth` would drown out the real hits. Env and key files in them are
+/// still reported: `COPY .env /etc/app/` is as much a leak as anywhere else.
+const SYSTEM_DIRS: &[&str] = &[
+    "bin/",
+    "sbin/",
+    "lib/",
+    "lib64/",
+    "etc/",
+    "usr/bin/",
+    "usr/sbin/",
+    "usr/lib/",
+    "usr/lib64/",
+    "usr/libexec/",
+    "usr/share/",
+    "usr/include/",
+    "var/lib/dpkg/",
+    "var/lib/apt/",
+    "var/lib/rpm/",
+];
+
+/// Private key files; a `.pem` counts when it's in a `private/` directory
+const KEY_FILE_EXTENSIONS: &[&str] = &["key", "p12", "pfx"];
+const SSH_KEY_NAMES: &[&str] = &["id_rsa", "id_dsa", "id_ecdsa", "id_ed25519"];
+
+/// Dependency trees, wherever they are; `js-tokens` isn't a token
+const VENDORED_DIRS: &[&str] = &["node_modules/", "site-packages/", "dist-packages/"];
+
+/// Something in an image that shouldn't ship
+#[derive(Debug, Clone)]
+pub struct ImageFinding {
+    /// Short id of the layer the file is in
+    pub layer: String,
+    /// Path inside the image, without the leading `/`
+    pub path: PathBuf,
+    /// The file name looks like a secret (`.env`, `credentials.json`, ...)
+    pub sensitive: bool,
+    pub findings: Vec<SecretFinding>,
+}
+
+/// What a scan looked at and found
+#[derive(Debug, Default)]
+pub struct ImageScan {
+    pub layers: usize,
+    pub files: usize,
+    /// Layers in a format we can't read (zstd). Nothing is known about what's in them.
+    pub skipped_layers: usize,
+    pub findings: Vec<ImageFinding>,
+}
+
+pub struct ImageScanner {
+    scanner: SecretScanner,
+    gitignore: AutoGitIgnore,
+}
+
+impl ImageScanner {
+    /// Scanner with the repo's `.arcane/scanner.toml` applied; allowlisted paths are
+    /// matched against paths inside the image
+    pub fn for_repo(repo_root: &Path) -> Result<Self> {
+        Ok(Self::new(SecretScanner::for_repo(repo_root)?, repo_root))
+    }
+
+    pub fn new(scanner: SecretScanner, repo_root: &Path) -> Self {
+        Self {
+            scanner,
+            gitignore: AutoGitIgnore::new(repo_root),
+        }
+    }
+
+    /// Scan a local image, streaming `docker save` instead of writing it to disk
+    pub fn scan_image(&self, image: &str) -> Result<ImageScan> {
+        let mut child = Command::new("docker")
+            .args(["save", image])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Failed to run docker save")?;
+        let stdout = child.stdout.take().context("No docker save output")?;
+
+        let scan = self.scan_archive(stdout);
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            let err = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow::anyhow!(
+                "docker save {} failed: {}",
+                image,
+                err.trim()
+            ));
+        }
+        scan
+    }
+
+    /// Scan a `docker save` tarball (legacy `<id>/layer.tar` or OCI `blobs/sha256/<id>`).
+    /// Layers are found by sniffing each entry, so the manifest isn't needed.
+    pub fn scan_archive(&self, reader: impl Read) -> Result<ImageScan> {
+        let mut scan = ImageScan::default();
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries().context("Invalid image archive")? {
+            let mut entry = entry.context("Invalid image archive")?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let layer = layer_id(&entry.path()?);
+
+            let mut head = Vec::with_capacity(TAR_BLOCK);
+            (&mut entry).take(TAR_BLOCK as u64).read_to_end(&mut head)?;
+            let (gzipped, plain) = (head.starts_with(GZIP_MAGIC), is_tar(&head));
+            if head.starts_with(ZSTD_MAGIC) {
+                scan.skipped_layers += 1;
+            }
+            // Anything else is the manifest or an image config
+            let rest = Cursor::new(head).chain(entry);
+            if gzipped {
+                self.scan_layer(&layer, GzDecoder::new(rest), &mut scan)?;
+            } else if plain {
+                self.scan_layer(&layer, rest, &mut scan)?;
+            }
+        }
+
+        // Drain the end-of-archive padding so the writer doesn't hit a broken pipe
+        io::copy(&mut archive.into_inner(), &mut io::sink())?;
+        Ok(scan)
+    }
+
+    fn scan_layer(&self, layer: &str, reader: impl Read, scan: &mut ImageScan) -> Result<()> {
+        scan.layers += 1;
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive
+            .entries()
+            .with_context(|| format!("Invalid layer {}", layer))?
+        {
+            let mut entry = entry.with_context(|| format!("Invalid layer {}", layer))?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let path = entry.path()?.components().collect::<PathBuf>();
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            // Whiteouts mark deletions; the deleted file is still in a lower layer
+            if name.starts_with(".wh.") || self.scanner.is_path_allowed(&path) {
+                continue;
+            }
+            // In third-party trees only env and key files count; their names alone
+            // (`auth`, `token`) would flag half the OS
+            let third_party = is_third_party(&path);
+            let sensitive = is_dotenv(name)
+                || is_key_file(&path)
+                || (!third_party && self.gitignore.is_sensitive_path(Path::new(name)));
+            if third_party && !sensitive {
+                continue;
+            }
+            scan.files += 1;
+
+            let mut findings = Vec::new();
+            if !third_party && entry.size() <= MAX_SCAN_FILE_BYTES {
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data)?;
+                if !looks_binary(&data) {
+                    if let Ok(content) = std::str::from_utf8(&data) {
+                        findings = self.scanner.scan_file(&path, content);
+                    }
+                }
+            }
+
+            if sensitive || !findings.is_empty() {
+                scan.findings.push(ImageFinding {
+                    layer: layer.to_string(),
+                    path,
+                    sensitive,
+                    findings,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_tar(head: &[u8]) -> bool {
+    head.len() == TAR_BLOCK && head[USTAR_OFFSET..].starts_with(b"ustar")
+}
+
+/// `abc123.../layer.tar` or `blobs/sha256/abc123...` -> `abc123...`, shortened like docker does
+fn layer_id(path: &Path) -> String {
+    let id = match path.file_name().and_then(|n| n.to_str()) {
+        Some("layer.tar") => path.parent().and_then(|p| p.file_name()),
+        _ => path.file_name(),
+    };
+    id.map(|id| id.to_string_lossy().chars().take(12).collect())
+        .unwrap_or_default()
+}
+
+fn is_third_party(path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    SYSTEM_DIRS.iter().any(|dir| path.starts_with(dir))
+        || VENDORED_DIRS
+            .iter()
+            .any(|dir| path.starts_with(dir) || path.contains(&format!("/{}", dir)))
+}
+
+/// `server.key`, `cert.p12`, `id_ed25519` or `ssl/private/server.pem`
+fn is_key_file(path: &Path) -> bool {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    KEY_FILE_EXTENSIONS.contains(&extension)
+        || SSH_KEY_NAMES.contains(&name)
+        || (extension == "pem"
+            && path
+                .parent()
+                .and_then(|dir| dir.file_name())
+                .is_some_and(|dir| dir == "private"))
+}
+
+/// `.env`, `.env.production`, `prod.env`; templates like `.env.example` are fine
+fn is_dotenv(name: &str) -> bool {
+    let is_template =
+        name.contains(".example") || name.contains(".sample") || name.contains(".template");
+    (name.starts_with(".env") || name.ends_with(".env")) && !is_template
+}
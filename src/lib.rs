@@ -1,3 +1,4 @@
+pub mod agent;
 pub mod ai_service;
 
 pub mod auto_gitattributes;
@@ -8,8 +9,11 @@ pub mod doctor;
 pub mod file_watcher;
 pub mod git_operations;
 pub mod history;
+pub mod image_scan;
+pub mod manifest;
 pub mod rebase_manager;
 pub mod repo_manager;
+pub mod scan_report;
 pub mod security;
 pub mod shadow;
 pub mod timeline;
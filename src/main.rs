@@ -1,3 +1,4 @@
+use arcane::agent;
 use arcane::ai_service;
 use arcane::config;
 use arcane::doctor;
@@ -5,6 +6,7 @@ use arcane::file_watcher;
 use arcane::git_operations;
 // use arcane::history; // Unused
 // use arcane::repo_manager; // Unused
+use arcane::scan_report;
 use arcane::security;
 use arcane::shadow;
 // use arcane::timeline; // Unused
@@ -13,7 +15,7 @@ use clap::{Arg, Command};
 use config::ConfigManager;
 use file_watcher::FileWatcher;
 use git_operations::GitOperations;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 pub mod ops;
 pub mod tui; // TUI Module // Ops Module (Arcane Ops)
@@ -65,6 +67,22 @@ async fn main() {
         .subcommand(
             Command::new("smudge")
                 .about("Git smudge filter (decrypt)")
+                .arg(Arg::new("file").required(false))
+                .hide(true),
+        )
+        .subcommand(
+            Command::new("textconv")
+                .about("Git diff driver (decrypt to stdout)")
+                .arg(Arg::new("file").required(true))
+                .hide(true),
+        )
+        .subcommand(
+            Command::new("merge-driver")
+                .about("Git merge driver for encrypted env files")
+                .arg(Arg::new("base").required(true))
+                .arg(Arg::new("ours").required(true))
+                .arg(Arg::new("theirs").required(true))
+                .arg(Arg::new("path").required(false))
                 .hide(true),
         )
         .subcommand(
@@ -74,7 +92,51 @@ async fn main() {
         .subcommand(
             Command::new("scan")
                 .about("Check files for leaked secrets (API keys, passwords)")
-                .arg(Arg::new("path").required(true)),
+                .arg(
+                    Arg::new("path")
+                        .required(false)
+                        .help("File to scan (default: the whole repo)"),
+                )
+                .arg(
+                    Arg::new("update-baseline")
+                        .long("update-baseline")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with("path")
+                        .help("Accept all current findings; later scans only report new ones"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_parser(["text", "json", "sarif"])
+                        .default_value("text"),
+                )
+                .arg(
+                    Arg::new("fail-on")
+                        .long("fail-on")
+                        .value_parser(["low", "medium", "high", "critical"])
+                        .default_value("low")
+                        .help("Exit 1 only for findings of at least this severity"),
+                )
+                .arg(
+                    Arg::new("history")
+                        .long("history")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with_all(["path", "update-baseline"])
+                        .help("Scan every committed version of every file, not the working tree"),
+                )
+                .arg(
+                    Arg::new("since")
+                        .long("since")
+                        .requires("history")
+                        .help("Only commits after this revision"),
+                )
+                .arg(
+                    Arg::new("ref")
+                        .long("ref")
+                        .requires("history")
+                        .action(clap::ArgAction::Append)
+                        .help("Branch or other ref to scan (repeatable; default: all refs)"),
+                ),
         )
         .subcommand(
             Command::new("team")
@@ -93,7 +155,36 @@ async fn main() {
                     Command::new("invite")
                         .about("Create an invite for a user")
                         .arg(Arg::new("team_name").required(true))
-                        .arg(Arg::new("user_pk").required(true).help("User's Public Key")),
+                        .arg(Arg::new("user_pk").required(true).help("User's Public Key"))
+                        .arg(
+                            Arg::new("expires")
+                                .long("expires")
+                                .default_value("7d")
+                                .help("How long the invite is valid (e.g. 12h, 7d)"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("rotate")
+                        .about("Replace a team's key, re-wrap its repos and re-invite its members")
+                        .arg(Arg::new("team_name").required(true))
+                        .arg(
+                            Arg::new("repo")
+                                .long("repo")
+                                .action(clap::ArgAction::Append)
+                                .help("Repo to re-wrap (repeatable; default: this repo)"),
+                        )
+                        .arg(
+                            Arg::new("drop")
+                                .long("drop")
+                                .action(clap::ArgAction::Append)
+                                .help("Public key of a member who should not be re-invited"),
+                        )
+                        .arg(
+                            Arg::new("expires")
+                                .long("expires")
+                                .default_value("7d")
+                                .help("How long the new invites are valid"),
+                        ),
                 )
                 .subcommand(
                     Command::new("accept")
@@ -104,7 +195,21 @@ async fn main() {
                     Command::new("add")
                         .about("Add a team member directly (Legacy/Tier 3 only)")
                         .arg(Arg::new("alias").required(true))
-                        .arg(Arg::new("key").required(true)),
+                        .arg(
+                            Arg::new("key")
+                                .required(true)
+                                .num_args(1..)
+                                .help("age1... key, SSH public key or authorized_keys line"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("remove")
+                        .about("Revoke a member: rotate the key and re-seal all secrets")
+                        .arg(
+                            Arg::new("alias")
+                                .required(true)
+                                .help("Member alias, team:<name> or machine:<id>"),
+                        ),
                 )
                 .subcommand(Command::new("list").about("List team members")),
         )
@@ -168,11 +273,21 @@ async fn main() {
                         .action(clap::ArgAction::SetTrue)
                         .help("Automatically generate Traefik labels for routing"),
                 )
+                .arg(
+                    Arg::new("warn-image-secrets")
+                        .long("warn-image-secrets")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Push even if the image scan finds secrets or can't read a layer (default: abort)"),
+                )
                 .subcommand(Command::new("gen-key").about("Generate Machine Identity"))
                 .subcommand(
                     Command::new("allow")
                         .about("Whitelist a Machine Key")
-                        .arg(Arg::new("pub_key").required(true)),
+                        .arg(
+                            Arg::new("pub_key")
+                                .required(true)
+                                .help("age1... key or SSH public key"),
+                        ),
                 ),
         )
         .subcommand(
@@ -240,7 +355,180 @@ async fn main() {
                 .subcommand(
                     Command::new("show").about("Show your public key (share this with teammates)"),
                 )
-                .subcommand(Command::new("new").about("Generate a new master identity")),
+                .subcommand(
+                    Command::new("new")
+                        .about("Generate a new master identity")
+                        .arg(
+                            Arg::new("passphrase")
+                                .long("passphrase")
+                                .help("Encrypt it with a passphrase")
+                                .action(clap::ArgAction::SetTrue),
+                        )
+                        .arg(
+                            Arg::new("keyring")
+                                .long("keyring")
+                                .help("Keep the secret in the OS keyring")
+                                .conflicts_with("passphrase")
+                                .action(clap::ArgAction::SetTrue),
+                        ),
+                )
+                .subcommand(
+                    Command::new("protect")
+                        .about("Re-wrap your existing identity (same key, new storage)")
+                        .arg(
+                            Arg::new("passphrase")
+                                .long("passphrase")
+                                .help("Encrypt it with a passphrase")
+                                .action(clap::ArgAction::SetTrue),
+                        )
+                        .arg(
+                            Arg::new("keyring")
+                                .long("keyring")
+                                .help("Move the secret into the OS keyring")
+                                .action(clap::ArgAction::SetTrue),
+                        )
+                        .arg(
+                            Arg::new("plaintext")
+                                .long("plaintext")
+                                .help("Store it unprotected again")
+                                .action(clap::ArgAction::SetTrue),
+                        )
+                        .group(
+                            clap::ArgGroup::new("storage")
+                                .args(["passphrase", "keyring", "plaintext"])
+                                .required(true),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("agent")
+                .about("Keep unlocked keys in memory so git filters don't unlock them per file")
+                .subcommand(
+                    Command::new("start")
+                        .about("Unlock your identity once and start the agent in the background")
+                        .arg(
+                            Arg::new("lifetime")
+                                .long("lifetime")
+                                .default_value("1h")
+                                .help("How long unlocked keys are kept (e.g. 30m, 8h)"),
+                        )
+                        .arg(
+                            Arg::new("foreground")
+                                .long("foreground")
+                                .help("Run in this terminal instead of the background")
+                                .action(clap::ArgAction::SetTrue),
+                        ),
+                )
+                .subcommand(
+                    Command::new("serve")
+                        .about("Agent process (reads the unlocked identity on stdin)")
+                        .hide(true)
+                        .arg(Arg::new("lifetime").long("lifetime").default_value("1h")),
+                )
+                .subcommand(Command::new("lock").about("Forget all cached keys now"))
+                .subcommand(Command::new("status").about("Show what the agent holds"))
+                .subcommand(Command::new("stop").about("Stop the agent")),
+        )
+        .subcommand(
+            Command::new("env")
+                .about("Environment variables, and per-environment keys (e.g. keep production away from developers)")
+                .subcommand(
+                    Command::new("get")
+                        .about("Print a variable (base.env and <env>.env merged)")
+                        .arg(Arg::new("name").required(true).help("Environment, e.g. production"))
+                        .arg(Arg::new("key").required(true)),
+                )
+                .subcommand(
+                    Command::new("set")
+                        .about("Set a variable in <env>.env, written back sealed")
+                        .arg(Arg::new("name").required(true).help("Environment, e.g. production"))
+                        .arg(Arg::new("key").required(true))
+                        .arg(
+                            Arg::new("value")
+                                .required(false)
+                                .help("Read from stdin (or prompted) if left out, to keep it out of shell history"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("unset")
+                        .about("Remove a variable from <env>.env, written back sealed")
+                        .arg(Arg::new("name").required(true).help("Environment, e.g. production"))
+                        .arg(Arg::new("key").required(true)),
+                )
+                .subcommand(
+                    Command::new("list")
+                        .about("List an environment's variables")
+                        .arg(Arg::new("name").required(true).help("Environment, e.g. production"))
+                        .arg(
+                            Arg::new("reveal")
+                                .long("reveal")
+                                .action(clap::ArgAction::SetTrue)
+                                .help("Show values too"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("edit")
+                        .about("Edit <env>.env in $EDITOR; the plaintext stays on tmpfs unless you allow disk")
+                        .arg(Arg::new("name").required(true).help("Environment, e.g. production")),
+                )
+                .subcommand(
+                    Command::new("diff")
+                        .about("Show which variables differ between two environments")
+                        .arg(Arg::new("left").required(true).help("e.g. staging"))
+                        .arg(Arg::new("right").required(true).help("e.g. production"))
+                        .arg(
+                            Arg::new("reveal")
+                                .long("reveal")
+                                .action(clap::ArgAction::SetTrue)
+                                .help("Show values instead of keyed hashes"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("init")
+                        .about("Give an environment its own key")
+                        .arg(Arg::new("name").required(true).help("Environment, e.g. production"))
+                        .arg(
+                            Arg::new("recipients")
+                                .num_args(0..)
+                                .help("Aliases to wrap it for (alice, team:ops, machine:<id>). Default: you"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("grant")
+                        .about("Let a recipient decrypt an environment")
+                        .arg(Arg::new("name").required(true))
+                        .arg(Arg::new("alias").required(true))
+                        .arg(
+                            Arg::new("key")
+                                .long("key")
+                                .help("Public key, if the alias isn't in the repo keyring"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("revoke")
+                        .about("Remove a recipient: rotate the environment key and re-seal its files")
+                        .arg(Arg::new("name").required(true))
+                        .arg(Arg::new("alias").required(true)),
+                )
+                .subcommand(
+                    Command::new("members")
+                        .about("List who can decrypt each environment")
+                        .arg(Arg::new("name").required(false)),
+                ),
+        )
+        .subcommand(
+            Command::new("keys")
+                .about("Manage where this repo's wrapped keys are stored")
+                .subcommand(Command::new("where").about("Show which keyring this repo uses"))
+                .subcommand(Command::new("migrate").about(
+                    "Move keys from .git/arcane/keys to the tracked .arcane/keys so clones can unlock",
+                ))
+                .subcommand(Command::new("verify").about(
+                    "Check the key files against the signed membership manifest",
+                ))
+                .subcommand(Command::new("sign").about(
+                    "Start a signed membership manifest recording the current recipients",
+                )),
         )
         .subcommand(
             Command::new("daemon")
@@ -290,6 +578,11 @@ async fn main() {
                         .help("Path to encrypted .env file (default: .env)")
                         .default_value(".env"),
                 )
+                .arg(
+                    Arg::new("env")
+                        .long("env")
+                        .help("Load config/envs/base.env + <env>.env (with its environment key)"),
+                )
                 .arg(Arg::new("command").num_args(1..).last(true).required(true)),
         )
         .subcommand(Command::new("ui").about("Alias for 'dashboard'").hide(true))
@@ -420,8 +713,34 @@ async fn main() {
                     }
                 }
                 std::process::exit(1);
-            } else {
-                println!("✅ Arcane Checks Passed");
+            }
+
+            // Same rules as `arcane scan`, over what's being committed
+            let security =
+                security::ArcaneSecurity::new(None).expect("Failed to initialize security");
+            match security.scan_staged() {
+                Ok(findings) if findings.is_empty() => println!("✅ Arcane Checks Passed"),
+                Ok(findings) => {
+                    println!("❌ Commit blocked: secrets in staged changes.");
+                    for (path, secrets) in findings {
+                        for secret in secrets {
+                            println!(
+                                "   - {}:{}:{}: {} [{}] {}",
+                                path.display(),
+                                secret.line,
+                                secret.column,
+                                secret.name,
+                                secret.severity,
+                                secret.redacted
+                            );
+                        }
+                    }
+                    std::process::exit(1);
+                }
+                Err(e) => {
+                    println!("❌ Commit blocked: secret scan failed: {:#}", e);
+                    std::process::exit(1);
+                }
             }
         }
         Some(("start", sub_matches)) => {
@@ -486,14 +805,67 @@ async fn main() {
                 std::process::exit(1);
             }
         }
-        Some(("smudge", _)) => {
+        Some(("smudge", sub_matches)) => {
+            let file = sub_matches.get_one::<String>("file").map(|s| s.as_str());
             let security =
                 security::ArcaneSecurity::new(None).expect("Failed to initialize security");
-            if let Err(e) = security.seal_smudge() {
+            if let Err(e) = security.seal_smudge(file) {
                 eprintln!("❌ Smudge Filter Error: {}", e);
                 std::process::exit(1);
             }
         }
+        Some(("textconv", sub_matches)) => {
+            let file = sub_matches
+                .get_one::<String>("file")
+                .expect("File required");
+            let security =
+                security::ArcaneSecurity::new(None).expect("Failed to initialize security");
+            if let Err(e) = security.textconv(Path::new(file)) {
+                eprintln!("❌ Textconv Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(("merge-driver", sub_matches)) => {
+            let base = sub_matches
+                .get_one::<String>("base")
+                .expect("Base required");
+            let ours = sub_matches
+                .get_one::<String>("ours")
+                .expect("Ours required");
+            let theirs = sub_matches
+                .get_one::<String>("theirs")
+                .expect("Theirs required");
+            let display_path = sub_matches
+                .get_one::<String>("path")
+                .map(|s| s.as_str())
+                .unwrap_or("env file");
+
+            let security =
+                security::ArcaneSecurity::new(None).expect("Failed to initialize security");
+            match security.merge_driver(
+                Path::new(base),
+                Path::new(ours),
+                Path::new(theirs),
+                sub_matches.get_one::<String>("path").map(|s| s.as_str()),
+            ) {
+                Ok(conflicts) if conflicts.is_empty() => {}
+                Ok(conflicts) => {
+                    eprintln!(
+                        "⚠️  Arcane merge conflict in {} ({} key(s)):",
+                        display_path,
+                        conflicts.len()
+                    );
+                    for key in conflicts {
+                        eprintln!("   - {}", key);
+                    }
+                    std::process::exit(1);
+                }
+                Err(e) => {
+                    eprintln!("❌ Merge Driver Error: {}", e);
+                    std::process::exit(2);
+                }
+            }
+        }
         Some(("setup", _)) => {
             // Global git filter configuration
             println!("🔧 Setting up Arcane global git filters...");
@@ -504,11 +876,26 @@ async fn main() {
             // Configure git-arcane filter (new standard)
             let filters = [
                 ("filter.git-arcane.clean", format!("'{}' clean %f", exe_str)),
-                ("filter.git-arcane.smudge", format!("'{}' smudge", exe_str)),
+                (
+                    "filter.git-arcane.smudge",
+                    format!("'{}' smudge %f", exe_str),
+                ),
                 ("filter.git-arcane.required", "true".to_string()),
+                (
+                    "diff.git-arcane.textconv",
+                    format!("'{}' textconv", exe_str),
+                ),
+                (
+                    "merge.git-arcane.name",
+                    "Arcane encrypted env merge".to_string(),
+                ),
+                (
+                    "merge.git-arcane.driver",
+                    format!("'{}' merge-driver %O %A %B %P", exe_str),
+                ),
                 // Also register as git-seal for backward compatibility with legacy repos
                 ("filter.git-seal.clean", format!("'{}' clean %f", exe_str)),
-                ("filter.git-seal.smudge", format!("'{}' smudge", exe_str)),
+                ("filter.git-seal.smudge", format!("'{}' smudge %f", exe_str)),
                 ("filter.git-seal.required", "true".to_string()),
             ];
 
@@ -547,38 +934,97 @@ async fn main() {
             }
         }
         Some(("scan", sub_matches)) => {
-            let path_str = sub_matches
-                .get_one::<String>("path")
-                .expect("Path required");
-            let path = Path::new(path_str);
-            if !path.exists() {
-                eprintln!("❌ File not found: {}", path.display());
-                std::process::exit(1);
+            // Exit codes: 0 = nothing at or above --fail-on, 1 = findings that are,
+            // 2 = the scan itself failed
+            if sub_matches.get_flag("update-baseline") {
+                let security =
+                    security::ArcaneSecurity::new(None).expect("Failed to initialize security");
+                match security.update_scan_baseline() {
+                    Ok((path, count)) => {
+                        println!("✅ Recorded {} finding(s) in {}", count, path.display());
+                        println!("   Commit it; later scans only report new findings.");
+                    }
+                    Err(e) => {
+                        eprintln!("❌ Failed to update baseline: {:#}", e);
+                        std::process::exit(2);
+                    }
+                }
+                return;
             }
 
-            match std::fs::read_to_string(path) {
-                Ok(content) => {
-                    let security =
-                        security::ArcaneSecurity::new(None).expect("Failed to initialize security");
-                    let secrets = security.scan_content(&content);
-                    if secrets.is_empty() {
-                        println!("✅ No secrets found in {}", path.display());
-                    } else {
-                        println!("🚫 SECRETS DETECTED in {}:", path.display());
-                        for secret in secrets {
+            let format: scan_report::ScanFormat = sub_matches
+                .get_one::<String>("format")
+                .expect("Format has a default")
+                .parse()
+                .expect("Format is validated by clap");
+            let fail_on: security::Severity = sub_matches
+                .get_one::<String>("fail-on")
+                .expect("Threshold has a default")
+                .parse()
+                .expect("Severity is validated by clap");
+            let path = sub_matches.get_one::<String>("path").map(Path::new);
+
+            let report = if sub_matches.get_flag("history") {
+                let refs: Vec<String> = sub_matches
+                    .get_many::<String>("ref")
+                    .map(|r| r.cloned().collect())
+                    .unwrap_or_default();
+                let since = sub_matches.get_one::<String>("since").map(String::as_str);
+                let scanned = security::ArcaneSecurity::new(None)
+                    .and_then(|security| security.scan_history(&refs, since));
+                match scanned {
+                    Ok(results) => scan_report::ScanReport::from_history(&results),
+                    Err(e) => {
+                        eprintln!("❌ History scan failed: {:#}", e);
+                        std::process::exit(2);
+                    }
+                }
+            } else {
+                match scan_target(path) {
+                    Ok((repo_root, results)) => scan_report::ScanReport::new(&repo_root, &results),
+                    Err(e) => {
+                        eprintln!("❌ Scan failed: {:#}", e);
+                        std::process::exit(2);
+                    }
+                }
+            };
+
+            match format {
+                scan_report::ScanFormat::Json => {
+                    println!("{}", report.to_json().expect("Report serializes"))
+                }
+                scan_report::ScanFormat::Sarif => {
+                    println!("{}", report.to_sarif().expect("Report serializes"))
+                }
+                scan_report::ScanFormat::Text if report.is_empty() => match path {
+                    Some(path) => println!("✅ No secrets found in {}", path.display()),
+                    None => println!("✅ No new secrets found"),
+                },
+                scan_report::ScanFormat::Text => {
+                    println!("🚫 SECRETS DETECTED:");
+                    for finding in &report.findings {
+                        println!(
+                            "   - {}:{}:{}: Found potential '{}' [{}]",
+                            finding.file,
+                            finding.line,
+                            finding.column,
+                            finding.rule,
+                            finding.severity
+                        );
+                        println!("     Match: {}", finding.redacted);
+                        if let (Some(commit), Some(author)) = (&finding.commit, &finding.author) {
                             println!(
-                                "   - Line {}: Found potential '{}'",
-                                secret.line, secret.name
+                                "     Introduced in {} by {}",
+                                &commit[..commit.len().min(10)],
+                                author
                             );
-                            println!("     Code: {}", secret.snippet.trim());
                         }
-                        std::process::exit(1);
                     }
                 }
-                Err(e) => {
-                    eprintln!("❌ Failed to read file: {}", e);
-                    std::process::exit(1);
-                }
+            }
+
+            if report.reaches(fail_on) {
+                std::process::exit(1);
             }
         }
         Some(("team", sub_matches)) => match sub_matches.subcommand() {
@@ -614,23 +1060,91 @@ async fn main() {
                 let team_name = args.get_one::<String>("team_name").expect("Team required");
                 let user_pk = args.get_one::<String>("user_pk").expect("User PK required");
 
+                let expires = parse_duration_arg(args, "expires");
+
                 let security = security::ArcaneSecurity::new(None).expect("Failed to initialize");
-                match security.create_team_invite(team_name, user_pk) {
-                    Ok(_) => println!("✅ Invite created in .git/arcane/invites/{}/", team_name),
+                match security.create_team_invite(team_name, user_pk, expires) {
+                    Ok(path) => {
+                        println!("✅ Invite created: {}", path.display());
+                        let until = chrono::Utc::now()
+                            + chrono::Duration::from_std(expires).unwrap_or_default();
+                        println!(
+                            "   Only that key can accept it, once, until {}.",
+                            until.format("%Y-%m-%d %H:%M UTC")
+                        );
+                    }
                     Err(e) => {
                         eprintln!("❌ Failed to create invite: {}", e);
                         std::process::exit(1);
                     }
                 }
             }
+            Some(("rotate", args)) => {
+                let team_name = args.get_one::<String>("team_name").expect("Team required");
+                let repos: Vec<std::path::PathBuf> = args
+                    .get_many::<String>("repo")
+                    .map(|r| r.map(std::path::PathBuf::from).collect())
+                    .unwrap_or_default();
+                let drop: Vec<String> = args
+                    .get_many::<String>("drop")
+                    .map(|d| d.cloned().collect())
+                    .unwrap_or_default();
+                let expires = parse_duration_arg(args, "expires");
+
+                let security = security::ArcaneSecurity::new(None).expect("Failed to initialize");
+                match security.rotate_team(team_name, &repos, &drop, expires) {
+                    Ok(rotation) => {
+                        println!("✅ Rotated the key of Team '{}'", rotation.team);
+                        let action = if rotation.rekeyed {
+                            "new keys for"
+                        } else {
+                            "re-wrapped"
+                        };
+                        for (repo, keyrings, commit) in &rotation.rewrapped {
+                            print!("   {}: {} {}", repo.display(), action, keyrings.join(", "));
+                            match commit {
+                                Some(commit) => println!(" (commit {})", commit),
+                                None => println!(),
+                            }
+                        }
+                        for (repo, reason) in &rotation.unreachable {
+                            eprintln!("⚠️ Skipped {}: {}", repo.display(), reason);
+                        }
+                        if rotation.rekeyed {
+                            println!("   Re-sealed {} file(s)", rotation.resealed);
+                        }
+                        if rotation.revoked_invites > 0 {
+                            println!(
+                                "   Deleted {} pending invite(s) for the old key",
+                                rotation.revoked_invites
+                            );
+                        }
+                        for invite in &rotation.invites {
+                            println!("   New invite: {}", invite.display());
+                        }
+                        if !rotation.invites.is_empty() || rotation.revoked_invites > 0 {
+                            println!(
+                                "   Commit arcane/invites/ and send the members their invites."
+                            );
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("❌ Failed to rotate team: {:#}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
             Some(("accept", args)) => {
                 let file_path = args.get_one::<String>("file").expect("File path required");
                 let security = security::ArcaneSecurity::new(None).expect("Failed to initialize");
                 match security.accept_team_invite(Path::new(file_path)) {
-                    Ok(team_name) => println!(
-                        "✅ Accepted invite! You are now a member of Team '{}'",
-                        team_name
-                    ),
+                    Ok(team_name) => {
+                        println!(
+                            "✅ Accepted invite! You are now a member of Team '{}'",
+                            team_name
+                        );
+                        println!("   Commit arcane/invites/ so the invite can't be used again.");
+                    }
                     Err(e) => {
                         eprintln!("❌ Failed to accept invite: {}", e);
                         std::process::exit(1);
@@ -639,10 +1153,16 @@ async fn main() {
             }
             Some(("add", args)) => {
                 let alias = args.get_one::<String>("alias").expect("Alias required");
-                let key = args.get_one::<String>("key").expect("Key required");
+                // An authorized_keys line may arrive unquoted, split into words
+                let key = args
+                    .get_many::<String>("key")
+                    .expect("Key required")
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(" ");
 
                 let security = security::ArcaneSecurity::new(None).expect("Failed to initialize");
-                match security.add_team_member(alias, key) {
+                match security.add_team_member(alias, &key) {
                     Ok(_) => println!("✅ Added team member '{}'", alias),
                     Err(e) => {
                         eprintln!("❌ Failed to add member: {}", e);
@@ -650,6 +1170,17 @@ async fn main() {
                     }
                 }
             }
+            Some(("remove", sub_m)) => {
+                let alias = sub_m.get_one::<String>("alias").unwrap();
+                let security = security::ArcaneSecurity::new(None).expect("Failed to initialize");
+                match security.remove_team_member(alias) {
+                    Ok(summary) => print_revocation(&summary),
+                    Err(e) => {
+                        eprintln!("❌ Failed to remove member: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
             Some(("list", _)) => {
                 let security = security::ArcaneSecurity::new(None).expect("Failed to initialize");
                 match security.list_team_members() {
@@ -729,6 +1260,7 @@ async fn main() {
                     let dry_run = sub_matches.get_flag("dry-run");
                     let parallel = sub_matches.get_flag("parallel");
                     let auto_ingress = sub_matches.get_flag("auto-ingress");
+                    let warn_image_secrets = sub_matches.get_flag("warn-image-secrets");
 
                     let deployment_ref = if compose_path.is_some() {
                         // For compose, use app name as the project key
@@ -745,6 +1277,7 @@ async fn main() {
                         ports,
                         compose_path,
                         auto_ingress,
+                        warn_image_secrets,
                         dry_run,
                         parallel,
                     )
@@ -997,15 +1530,65 @@ async fn main() {
                     Err(_) => println!("⚠️ (not required for single image deploys)"),
                 }
 
-                // Check 5: Environment file
+                // Check 5: Environment file (decrypted and parsed, as deploy will)
                 if let Some(env) = env_name {
-                    let env_path = format!("config/envs/{}.env", env);
-                    print!("   ├─ Environment file ({})... ", env_path);
-                    if std::path::Path::new(&env_path).exists() {
-                        println!("✅");
-                    } else {
+                    let project_root = security::ArcaneSecurity::find_repo_root()
+                        .unwrap_or_else(|_| std::path::PathBuf::from("."));
+                    let env_path = arcane::config::env::env_file_path(&project_root, env);
+                    let shown = env_path.strip_prefix(&project_root).unwrap_or(&env_path);
+                    print!("   ├─ Environment file ({})... ", shown.display());
+                    if !env_path.exists() {
                         println!("❌ (not found)");
                         all_passed = false;
+                    } else {
+                        let loaded = security::ArcaneSecurity::new(None).and_then(|security| {
+                            arcane::config::env::Environment::load(
+                                env,
+                                &project_root,
+                                &security,
+                                None,
+                            )
+                        });
+                        match loaded {
+                            Ok(environment) => {
+                                if environment.undefined.is_empty() {
+                                    println!("✅ ({} variables)", environment.variables.len());
+                                } else {
+                                    println!("❌ (references to unset variables)");
+                                    for problem in &environment.undefined {
+                                        println!("   │    - {}", problem);
+                                    }
+                                    all_passed = false;
+                                }
+
+                                // Check 6: Variables match config/envs/schema.toml
+                                use arcane::config::schema::{EnvSchema, SCHEMA_FILE};
+                                print!("   ├─ Schema ({})... ", SCHEMA_FILE);
+                                match EnvSchema::load(&project_root) {
+                                    Ok(None) => println!("⚠️ (none)"),
+                                    Ok(Some(schema)) => {
+                                        let violations = schema.check(env, &environment.variables);
+                                        if violations.is_empty() {
+                                            println!("✅");
+                                        } else {
+                                            println!("❌");
+                                            for violation in violations {
+                                                println!("   │    - {}", violation);
+                                            }
+                                            all_passed = false;
+                                        }
+                                    }
+                                    Err(e) => {
+                                        println!("❌ ({:#})", e);
+                                        all_passed = false;
+                                    }
+                                }
+                            }
+                            Err(e) => {
+                                println!("❌ ({:#})", e);
+                                all_passed = false;
+                            }
+                        }
                     }
                 }
             } else {
@@ -1033,53 +1616,52 @@ async fn main() {
                     .join("identity.age");
 
                 if !identity_path.exists() {
-                    eprintln!("❌ No identity found. Run 'arcane identity new' first.");
-                    std::process::exit(1);
+                    // Without identity.age, Arcane unlocks with the SSH key in ~/.ssh
+                    let security =
+                        security::ArcaneSecurity::new(None).expect("Failed to initialize");
+                    match security.load_master_identity() {
+                        Ok(identity) => {
+                            println!("🔑 Your Arcane Identity (SSH key)");
+                            println!();
+                            println!("Public Key (share this with teammates):");
+                            println!("{}", identity.to_public());
+                        }
+                        Err(e) => {
+                            eprintln!("❌ No identity found: {}", e);
+                            eprintln!(
+                                "   Run 'arcane identity new' first, or add ~/.ssh/id_ed25519."
+                            );
+                            std::process::exit(1);
+                        }
+                    }
+                    return;
                 }
 
-                match std::fs::read_to_string(&identity_path) {
-                    Ok(content) => {
-                        // Parse the identity to get the public key
-                        use age::x25519;
-                        use std::str::FromStr;
-
-                        // Find the secret key line
-                        for line in content.lines() {
-                            if line.starts_with("AGE-SECRET-KEY-") {
-                                match x25519::Identity::from_str(line) {
-                                    Ok(identity) => {
-                                        let public_key = identity.to_public();
-                                        println!("🔑 Your Arcane Identity");
-                                        println!();
-                                        println!("Public Key (share this with teammates):");
-                                        println!("{}", public_key);
-                                        println!();
-                                        println!("Identity File: {}", identity_path.display());
-                                    }
-                                    Err(e) => {
-                                        eprintln!("❌ Failed to parse identity: {}", e);
-                                        std::process::exit(1);
-                                    }
-                                }
-                                return;
-                            }
+                // The public key is read from the file header, so this never prompts
+                let security = security::ArcaneSecurity::new(None).expect("Failed to initialize");
+                match security.master_public_key() {
+                    Ok(public_key) => {
+                        println!("🔑 Your Arcane Identity");
+                        println!();
+                        println!("Public Key (share this with teammates):");
+                        println!("{}", public_key);
+                        println!();
+                        println!("Identity File: {}", identity_path.display());
+                        if let Ok(Some(storage)) = security::ArcaneSecurity::identity_storage() {
+                            println!("Protection: {}", storage);
                         }
-                        eprintln!("❌ No valid identity key found in file");
-                        std::process::exit(1);
                     }
                     Err(e) => {
-                        eprintln!("❌ Failed to read identity file: {}", e);
+                        eprintln!("❌ Failed to read identity: {}", e);
                         std::process::exit(1);
                     }
                 }
             }
-            Some(("new", _)) => {
-                // Generate a new master identity
-                let identity_dir = dirs::home_dir()
+            Some(("new", args)) => {
+                let identity_path = dirs::home_dir()
                     .expect("Could not find home directory")
-                    .join(".arcane");
-
-                let identity_path = identity_dir.join("identity.age");
+                    .join(".arcane")
+                    .join("identity.age");
 
                 if identity_path.exists() {
                     eprintln!(
@@ -1090,24 +1672,21 @@ async fn main() {
                         "   To regenerate, delete it first: rm {}",
                         identity_path.display()
                     );
+                    eprintln!("   To add a passphrase, use 'arcane identity protect --passphrase'");
                     std::process::exit(1);
                 }
 
-                // Create directory if needed
-                std::fs::create_dir_all(&identity_dir).expect("Failed to create .arcane directory");
-
-                // Generate key
-                let (priv_key, pub_key) = security::ArcaneSecurity::generate_machine_identity();
-
-                // Write to file
-                let content = format!(
-                    "# created: {}\n# public key: {}\n{}\n",
-                    chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
-                    pub_key,
-                    priv_key
-                );
-
-                std::fs::write(&identity_path, content).expect("Failed to write identity file");
+                let storage =
+                    identity_storage_arg(args).unwrap_or(security::IdentityStorage::Plaintext);
+                let mut security =
+                    security::ArcaneSecurity::new(None).expect("Failed to initialize");
+                let pub_key = match security.create_master_identity(storage) {
+                    Ok(pub_key) => pub_key,
+                    Err(e) => {
+                        eprintln!("❌ Failed to create identity: {}", e);
+                        std::process::exit(1);
+                    }
+                };
 
                 println!("🔐 Created new Arcane Identity");
                 println!();
@@ -1116,10 +1695,315 @@ async fn main() {
                 println!();
                 println!("Identity saved to: {}", identity_path.display());
                 println!();
-                println!("⚠️  Back up your identity file! It's your master key.");
+                match storage {
+                    security::IdentityStorage::Plaintext => {
+                        println!("⚠️  Back up your identity file! It's your master key.")
+                    }
+                    security::IdentityStorage::Passphrase => println!(
+                        "⚠️  Back up your identity file and remember the passphrase; neither works without the other."
+                    ),
+                    security::IdentityStorage::Keyring => println!(
+                        "🔒 The secret key is in your OS keyring; the file only points to it."
+                    ),
+                }
+            }
+            Some(("protect", args)) => {
+                let storage = identity_storage_arg(args).expect("Storage required");
+                let mut security =
+                    security::ArcaneSecurity::new(None).expect("Failed to initialize");
+                match security.migrate_master_identity(storage) {
+                    Ok(previous) => {
+                        println!("✅ Identity moved from {} to {} storage", previous, storage);
+                        println!("   Same key: nothing needs re-sharing or re-keying.");
+                    }
+                    Err(e) => {
+                        eprintln!("❌ Failed to protect identity: {}", e);
+                        std::process::exit(1);
+                    }
+                }
             }
             _ => println!("Use 'arcane identity --help'"),
         },
+        Some(("agent", sub_matches)) => match sub_matches.subcommand() {
+            Some(("start", args)) => {
+                let lifetime = parse_duration_arg(args, "lifetime");
+                if agent::is_running() {
+                    println!("✅ Agent already running. Use 'arcane agent status'.");
+                    return;
+                }
+
+                // Unlock here, where there is a terminal to ask for the passphrase
+                let security = security::ArcaneSecurity::new(None).expect("Failed to initialize");
+                let secret = match security.load_master_identity() {
+                    Ok(security::MasterIdentity::Age(identity)) => {
+                        use secrecy::ExposeSecret;
+                        Some(zeroize::Zeroizing::new(
+                            identity.to_string().expose_secret().to_string(),
+                        ))
+                    }
+                    Ok(security::MasterIdentity::Ssh { .. }) => {
+                        println!("ℹ️  SSH identities aren't cached; only repo keys will be.");
+                        None
+                    }
+                    Err(e) => {
+                        eprintln!("⚠️  No identity cached: {}", e);
+                        None
+                    }
+                };
+
+                if args.get_flag("foreground") {
+                    println!("🔐 Arcane agent running (keys kept for {:?})", lifetime);
+                    if let Err(e) = agent::serve(lifetime, secret) {
+                        eprintln!("❌ Agent failed: {}", e);
+                        std::process::exit(1);
+                    }
+                    return;
+                }
+
+                // Hand the identity to a detached `agent serve` over its stdin
+                use std::io::Write;
+                use std::os::unix::process::CommandExt;
+                let exe = std::env::current_exe().expect("Failed to get executable path");
+                let child = std::process::Command::new(exe)
+                    .args(["agent", "serve", "--lifetime"])
+                    .arg(format!("{}", lifetime.as_secs()))
+                    .stdin(std::process::Stdio::piped())
+                    .stdout(std::process::Stdio::null())
+                    .stderr(std::process::Stdio::null())
+                    .process_group(0)
+                    .spawn();
+                let mut child = match child {
+                    Ok(child) => child,
+                    Err(e) => {
+                        eprintln!("❌ Failed to start agent: {}", e);
+                        std::process::exit(1);
+                    }
+                };
+                if let Some(mut stdin) = child.stdin.take() {
+                    let line = secret.as_ref().map(|s| s.as_str()).unwrap_or("");
+                    let _ = writeln!(stdin, "{}", line);
+                }
+
+                for _ in 0..50 {
+                    if let Ok(status) = agent::status() {
+                        println!("✅ Arcane agent started (pid {})", status.pid);
+                        println!(
+                            "   Keys are forgotten after {}s, or with 'arcane agent lock'.",
+                            status.lifetime_secs
+                        );
+                        return;
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(100));
+                }
+                eprintln!("❌ Agent did not come up; try 'arcane agent start --foreground'");
+                std::process::exit(1);
+            }
+            Some(("serve", args)) => {
+                let lifetime = parse_duration_arg(args, "lifetime");
+                let mut line = zeroize::Zeroizing::new(String::new());
+                let _ = std::io::stdin().read_line(&mut line);
+                let secret = Some(line.trim())
+                    .filter(|s| !s.is_empty())
+                    .map(|s| zeroize::Zeroizing::new(s.to_string()));
+                if let Err(e) = agent::serve(lifetime, secret) {
+                    eprintln!("❌ Agent failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            Some(("lock", _)) => match agent::lock() {
+                Ok(_) => println!("🔒 Agent locked: all cached keys forgotten."),
+                Err(e) => {
+                    eprintln!("❌ {}", e);
+                    std::process::exit(1);
+                }
+            },
+            Some(("status", _)) => match agent::status() {
+                Ok(status) => {
+                    println!("🔐 Arcane agent (pid {})", status.pid);
+                    println!("   Socket: {}", status.socket);
+                    println!(
+                        "   Identity: {}",
+                        if status.identity {
+                            "unlocked"
+                        } else {
+                            "not cached"
+                        }
+                    );
+                    println!("   Repo keys: {}", status.keys);
+                    println!("   Lifetime: {}s", status.lifetime_secs);
+                    if let Some(secs) = status.next_expiry_secs {
+                        println!("   Next expiry in: {}s", secs);
+                    }
+                }
+                Err(_) => {
+                    println!("⚪ No agent running. Start one with 'arcane agent start'.");
+                }
+            },
+            Some(("stop", _)) => match agent::stop() {
+                Ok(_) => println!("✅ Agent stopped."),
+                Err(e) => {
+                    eprintln!("❌ {}", e);
+                    std::process::exit(1);
+                }
+            },
+            _ => println!("Use 'arcane agent --help'"),
+        },
+        Some(("env", sub_matches)) => {
+            let security =
+                security::ArcaneSecurity::new(None).expect("Failed to initialize security");
+            match sub_matches.subcommand() {
+                Some(("init", args)) => {
+                    let name = args.get_one::<String>("name").unwrap();
+                    let recipients: Vec<String> = args
+                        .get_many::<String>("recipients")
+                        .map(|v| v.cloned().collect())
+                        .unwrap_or_default();
+                    match security.init_env_key(name, &recipients) {
+                        Ok(init) => {
+                            println!("✅ '{}' now has its own key", name);
+                            print_keyring_update(&init.keyring);
+                            for path in &init.resealed {
+                                println!("   Re-sealed {}", path);
+                            }
+                            if let Some(hash) = &init.commit {
+                                println!("   Committed as {}", hash);
+                            }
+                        }
+                        Err(e) => {
+                            eprintln!("❌ Failed to create key for '{}': {}", name, e);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                Some(("grant", args)) => {
+                    let name = args.get_one::<String>("name").unwrap();
+                    let alias = args.get_one::<String>("alias").unwrap();
+                    let key = args.get_one::<String>("key").map(|s| s.as_str());
+                    match security.grant_env_access(name, alias, key) {
+                        Ok(_) => println!("✅ '{}' can now decrypt '{}'", alias, name),
+                        Err(e) => {
+                            eprintln!("❌ Failed to grant access: {}", e);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                Some(("revoke", args)) => {
+                    let name = args.get_one::<String>("name").unwrap();
+                    let alias = args.get_one::<String>("alias").unwrap();
+                    match security.revoke_env_access(name, alias) {
+                        Ok(summary) => print_revocation(&summary),
+                        Err(e) => {
+                            eprintln!("❌ Failed to revoke access: {}", e);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                Some((cmd @ ("get" | "set" | "unset" | "list" | "edit"), args)) => {
+                    if let Err(e) = env_variables(cmd, args, &security) {
+                        eprintln!("❌ {:#}", e);
+                        std::process::exit(1);
+                    }
+                }
+                Some(("diff", args)) => {
+                    if let Err(e) = env_diff(args, &security) {
+                        eprintln!("❌ {:#}", e);
+                        std::process::exit(1);
+                    }
+                }
+                Some(("members", args)) => {
+                    let envs = match args.get_one::<String>("name") {
+                        Some(name) => vec![name.clone()],
+                        None => security.list_env_keyrings().unwrap_or_default(),
+                    };
+                    if envs.is_empty() {
+                        println!("No environment keys. Every environment uses the repo key.");
+                    }
+                    for env in envs {
+                        match security.list_env_members(&env) {
+                            Ok(members) => println!("🔐 {}: {}", env, members.join(", ")),
+                            Err(e) => {
+                                eprintln!("❌ {}", e);
+                                std::process::exit(1);
+                            }
+                        }
+                    }
+                }
+                _ => println!("Use 'arcane env --help'"),
+            }
+        }
+        Some(("keys", sub_matches)) => {
+            let security =
+                security::ArcaneSecurity::new(None).expect("Failed to initialize security");
+            match sub_matches.subcommand() {
+                Some(("where", _)) => match security.keys_dir() {
+                    Ok(dir) => {
+                        let tracked = dir.ends_with(security::TRACKED_KEYS_DIR);
+                        println!("🔑 Keyring: {}", dir.display());
+                        if tracked {
+                            println!("   Tracked: committed with the repo, clones can unlock.");
+                        } else {
+                            println!("   Local: only in this clone. Run 'arcane keys migrate' to share it.");
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("❌ {}", e);
+                        std::process::exit(1);
+                    }
+                },
+                Some(("migrate", _)) => match security.migrate_keys_to_tracked() {
+                    Ok(dir) => {
+                        println!("✅ Keys moved to {}", dir.display());
+                        println!("   Commit them so clones can unlock:");
+                        println!(
+                            "   git add {} && git commit -m \"Track arcane keyring\"",
+                            security::TRACKED_KEYS_DIR
+                        );
+                    }
+                    Err(e) => {
+                        eprintln!("❌ Migration failed: {}", e);
+                        std::process::exit(1);
+                    }
+                },
+                Some(("sign", _)) => match security.init_key_manifest() {
+                    Ok(count) => {
+                        println!("✅ Key manifest started with {} recipient(s)", count);
+                        println!("   Commit it with the keyring so clones can verify it.");
+                    }
+                    Err(e) => {
+                        eprintln!("❌ Failed to start key manifest: {:#}", e);
+                        std::process::exit(1);
+                    }
+                },
+                Some(("verify", _)) => {
+                    let audit = security::ArcaneSecurity::find_repo_root()
+                        .and_then(|root| security::ArcaneSecurity::audit_keyrings(&root));
+                    match audit {
+                        Ok(Some(audit)) => {
+                            println!("📜 Key manifest: {} signed entries", audit.entries);
+                            for (keyring, members) in &audit.members {
+                                println!("   {}: {}", keyring, members.join(", "));
+                            }
+                            if audit.is_clean() {
+                                println!("✅ Every key file is accounted for");
+                            } else {
+                                for problem in audit.problems() {
+                                    eprintln!("❌ {}", problem);
+                                }
+                                std::process::exit(1);
+                            }
+                        }
+                        Ok(None) => {
+                            println!("⚠️ This keyring has no manifest. Run 'arcane keys sign' to start one.");
+                        }
+                        Err(e) => {
+                            eprintln!("❌ Key manifest failed verification: {:#}", e);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                _ => println!("Use 'arcane keys --help'"),
+            }
+        }
         Some(("daemon", sub_matches)) => match sub_matches.subcommand() {
             Some(("run", _)) => {
                 if let Err(e) = arcane::daemon::start_daemon() {
@@ -1183,10 +2067,14 @@ async fn main() {
                 }
             };
 
-            // 2. Load Repo Key (Verify Access)
-            if let Err(e) = security.load_repo_key() {
-                eprintln!("❌ Access Denied: {}", e);
-                std::process::exit(1);
+            let env_name = sub_matches.get_one::<String>("env");
+
+            // 2. Load Repo Key (Verify Access). Named environments may use their own key.
+            if env_name.is_none() {
+                if let Err(e) = security.load_repo_key() {
+                    eprintln!("❌ Access Denied: {}", e);
+                    std::process::exit(1);
+                }
             }
 
             // 3. Decrypt env file if exists
@@ -1196,15 +2084,49 @@ async fn main() {
                 .unwrap_or(".env");
 
             let mut env_vars = std::collections::HashMap::new();
-            if Path::new(env_file).exists() {
+            if let Some(env_name) = env_name {
+                let project_root = security::ArcaneSecurity::find_repo_root()
+                    .unwrap_or_else(|_| std::path::PathBuf::from("."));
+                match arcane::config::env::Environment::load(
+                    env_name,
+                    &project_root,
+                    &security,
+                    None,
+                ) {
+                    Ok(env) => {
+                        for problem in &env.undefined {
+                            eprintln!("⚠️  {}", problem);
+                        }
+                        env_vars = env.variables;
+                        println!(
+                            "✅ Loaded '{}' and injected {} variables.",
+                            env_name,
+                            env_vars.len()
+                        );
+                    }
+                    Err(e) => {
+                        eprintln!("❌ Failed to load environment '{}': {:#}", env_name, e);
+                        std::process::exit(1);
+                    }
+                }
+            } else if Path::new(env_file).exists() {
                 if let Ok(content) = std::fs::read(env_file) {
-                    if let Ok(repo_key) = security.load_repo_key() {
+                    if let Ok(repo_key) = security.load_repo_key_for_blob(&content) {
                         // Try decrypt (assuming it might be ciphertext)
                         if let Ok(decrypted) = security.decrypt_with_repo_key(&repo_key, &content) {
                             if let Ok(str_content) = String::from_utf8(decrypted) {
-                                for line in str_content.lines() {
-                                    if let Some((k, v)) = line.split_once('=') {
-                                        env_vars.insert(k.trim().to_string(), v.trim().to_string());
+                                match arcane::config::dotenv::parse_into(
+                                    &str_content,
+                                    &mut env_vars,
+                                ) {
+                                    Ok(undefined) => {
+                                        for problem in undefined {
+                                            eprintln!("⚠️  {}: {}", env_file, problem);
+                                        }
+                                    }
+                                    Err(e) => {
+                                        eprintln!("❌ Invalid env file {}: {}", env_file, e);
+                                        std::process::exit(1);
                                     }
                                 }
                                 println!(
@@ -1288,6 +2210,282 @@ async fn main() {
     }
 }
 
+/// Scan one file, or the whole repo without a path. Returns the repo root (empty
+/// outside a repo) that the results' paths are under.
+fn scan_target(path: Option<&Path>) -> anyhow::Result<(PathBuf, security::ScanResults)> {
+    let Some(path) = path else {
+        let security = security::ArcaneSecurity::new(None)?;
+        let repo_root = security::ArcaneSecurity::find_repo_root()?;
+        return Ok((repo_root, security.scan_repo()?));
+    };
+
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+
+    // Use the repo's .arcane/scanner.toml when the file is inside one
+    let absolute = std::fs::canonicalize(path)?;
+    let repo_root = absolute
+        .parent()
+        .and_then(|dir| security::ArcaneSecurity::find_repo_root_from(dir).ok());
+    let (scanner, repo_root) = match repo_root {
+        Some(root) => (security::SecretScanner::for_repo(&root)?, root),
+        None => (security::SecretScanner::new(), PathBuf::new()),
+    };
+    let relative = absolute.strip_prefix(&repo_root).unwrap_or(path);
+
+    let findings = scanner.scan_file(relative, &content);
+    let results = if findings.is_empty() {
+        Vec::new()
+    } else {
+        vec![(relative.to_path_buf(), findings)]
+    };
+    Ok((repo_root, results))
+}
+
+/// `arcane env get|set|unset|list|edit`. Files are decrypted in memory and written
+/// back sealed, so plaintext never lands in the working tree.
+fn env_variables(
+    cmd: &str,
+    args: &clap::ArgMatches,
+    security: &security::ArcaneSecurity,
+) -> anyhow::Result<()> {
+    use anyhow::Context;
+    use arcane::config::dotenv;
+    use arcane::config::env::{self, Environment};
+    use arcane::config::fields::StructuredFormat;
+
+    let name = args.get_one::<String>("name").expect("required");
+    let key = args.try_get_one::<String>("key").ok().flatten();
+    let project_root = security::ArcaneSecurity::find_repo_root()?;
+    let path = env::env_file_path(&project_root, name);
+    let shown = path.strip_prefix(&project_root).unwrap_or(&path).display();
+    let read_current = || -> anyhow::Result<String> {
+        if path.exists() {
+            env::read_env_file(&path, security, None)
+        } else {
+            Ok(String::new())
+        }
+    };
+
+    match cmd {
+        "get" => {
+            let key = key.expect("required");
+            let environment = Environment::load(name, &project_root, security, None)?;
+            let value = environment
+                .variables
+                .get(key)
+                .with_context(|| format!("'{}' is not set in '{}'", key, name))?;
+            println!("{}", value);
+        }
+        "list" => {
+            let environment = Environment::load(name, &project_root, security, None)?;
+            let mut keys: Vec<&String> = environment.variables.keys().collect();
+            keys.sort();
+            for key in keys {
+                if args.get_flag("reveal") {
+                    println!("{}={}", key, environment.variables[key]);
+                } else {
+                    println!("{}", key);
+                }
+            }
+        }
+        "set" | "unset" => {
+            if StructuredFormat::from_path(&path).is_some() {
+                return Err(anyhow::anyhow!(
+                    "{} isn't a dotenv file; use 'arcane env edit {}'",
+                    shown,
+                    name
+                ));
+            }
+            let key = key.expect("required");
+            let content = read_current()?;
+            let updated = if cmd == "set" {
+                if !dotenv::is_valid_key(key) {
+                    return Err(anyhow::anyhow!("Invalid variable name '{}'", key));
+                }
+                let value = match args.get_one::<String>("value") {
+                    Some(value) => value.clone(),
+                    None => read_value(key)?,
+                };
+                env::set_env_value(&content, key, &value)
+            } else {
+                env::unset_env_value(&content, key)
+                    .with_context(|| format!("'{}' is not set in {}", key, shown))?
+            };
+            env::write_env_file(&path, &project_root, &updated, security)?;
+            let verb = if cmd == "set" { "Set" } else { "Removed" };
+            println!("✅ {} {} in {} (sealed)", verb, key, shown);
+        }
+        "edit" => {
+            let content = read_current()?;
+            let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("env");
+            let edited = env::edit_privately(file_name, &content, |temp_dir| {
+                use std::io::Write;
+                print!(
+                    "⚠️  No tmpfs here; the plaintext would be written to {} on disk. Continue? [y/N] ",
+                    temp_dir.display()
+                );
+                let _ = std::io::stdout().flush();
+                let mut input = String::new();
+                std::io::stdin().read_line(&mut input).is_ok()
+                    && input.trim().eq_ignore_ascii_case("y")
+            })?;
+            if edited == content {
+                println!("No changes to {}", shown);
+                return Ok(());
+            }
+            env::write_env_file(&path, &project_root, &edited, security)?;
+            println!("✅ Saved {} (sealed)", shown);
+        }
+        _ => unreachable!("not an env variable command"),
+    }
+    Ok(())
+}
+
+/// `arcane env diff <left> <right>`: keys set in only one environment, and keys whose
+/// values differ, with the file each one comes from
+fn env_diff(args: &clap::ArgMatches, security: &security::ArcaneSecurity) -> anyhow::Result<()> {
+    use arcane::config::env::{diff_envs, Environment};
+
+    let left_name = args.get_one::<String>("left").expect("required");
+    let right_name = args.get_one::<String>("right").expect("required");
+    let project_root = security::ArcaneSecurity::find_repo_root()?;
+    let left = Environment::load(left_name, &project_root, security, None)?;
+    let right = Environment::load(right_name, &project_root, security, None)?;
+
+    // Masked like `git diff` masks them: equal values get equal tags
+    let repo_key = security.load_repo_key().ok();
+    let show = |value: &str| -> String {
+        if args.get_flag("reveal") {
+            return value.to_string();
+        }
+        repo_key
+            .as_ref()
+            .and_then(|key| security::ArcaneSecurity::value_tag(key, value).ok())
+            .map(|tag| format!("****({})", tag))
+            .unwrap_or_else(|| "****".to_string())
+    };
+    let source = |env: &Environment, key: &str| {
+        env.sources
+            .get(key)
+            .map(|path| path.display().to_string())
+            .unwrap_or_default()
+    };
+
+    let diff = diff_envs(&left, &right);
+    println!("🔍 {} ↔ {}", left_name, right_name);
+    for (env, keys) in [(&left, &diff.only_left), (&right, &diff.only_right)] {
+        if keys.is_empty() {
+            continue;
+        }
+        println!("\nOnly in {}:", env.name);
+        for key in keys {
+            println!("   {}  ({})", key, source(env, key));
+        }
+    }
+    if !diff.changed.is_empty() {
+        println!("\nDifferent:");
+        let width = left_name.len().max(right_name.len()) + 1;
+        for key in &diff.changed {
+            println!("   {}", key);
+            for env in [&left, &right] {
+                println!(
+                    "      {:<width$}  {}  ({})",
+                    format!("{}:", env.name),
+                    show(&env.variables[key]),
+                    source(env, key),
+                    width = width
+                );
+            }
+        }
+    }
+
+    let differing = diff.only_left.len() + diff.only_right.len() + diff.changed.len();
+    if differing == 0 {
+        println!("\n✅ No differences ({} variables)", diff.same);
+    } else {
+        println!("\n{} differ, {} the same", differing, diff.same);
+    }
+    Ok(())
+}
+
+/// A value left off the command line: prompted without echo, or all of stdin
+/// (so `arcane env set prod TLS_KEY < key.pem` works)
+fn read_value(key: &str) -> anyhow::Result<String> {
+    use std::io::{IsTerminal, Read};
+    if std::io::stdin().is_terminal() {
+        return Ok(rpassword::prompt_password(format!("Value for {}: ", key))?);
+    }
+    let mut value = String::new();
+    std::io::stdin().read_to_string(&mut value)?;
+    let value = value.strip_suffix('\n').unwrap_or(&value);
+    Ok(value.strip_suffix('\r').unwrap_or(value).to_string())
+}
+
+/// A duration option like `--lifetime 8h` or `--expires 7d` (exits on a bad value)
+fn parse_duration_arg(args: &clap::ArgMatches, name: &str) -> std::time::Duration {
+    let value = args.get_one::<String>(name).expect("has default");
+    match agent::parse_lifetime(value) {
+        Ok(lifetime) => lifetime,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Storage picked with `--passphrase` / `--keyring` / `--plaintext`
+fn identity_storage_arg(args: &clap::ArgMatches) -> Option<security::IdentityStorage> {
+    let flag = |name: &str| args.try_get_one::<bool>(name).ok().flatten() == Some(&true);
+    if flag("passphrase") {
+        Some(security::IdentityStorage::Passphrase)
+    } else if flag("keyring") {
+        Some(security::IdentityStorage::Keyring)
+    } else if flag("plaintext") {
+        Some(security::IdentityStorage::Plaintext)
+    } else {
+        None
+    }
+}
+
+fn print_keyring_update(keyring: &security::KeyringUpdate) {
+    println!(
+        "   {} key {} wrapped for: {}",
+        keyring.name,
+        keyring.key_fingerprint,
+        keyring.rewrapped.join(", ")
+    );
+    if !keyring.skipped.is_empty() {
+        println!(
+            "   ⚠️  No public key, lost access to {}: {}",
+            keyring.name,
+            keyring.skipped.join(", ")
+        );
+    }
+}
+
+fn print_revocation(summary: &security::RevocationSummary) {
+    println!("✅ Removed '{}'", summary.alias);
+    for keyring in &summary.keyrings {
+        print_keyring_update(keyring);
+    }
+    println!("   Re-sealed {} file(s):", summary.resealed.len());
+    for path in &summary.resealed {
+        println!("     - {}", path);
+    }
+    if summary.purged_snapshots > 0 {
+        println!(
+            "   Purged from {} history snapshot(s)",
+            summary.purged_snapshots
+        );
+    }
+    match &summary.commit {
+        Some(hash) => println!("   Committed as {}", hash),
+        None => println!("   Nothing to commit"),
+    }
+    println!("   Note: anything they already pulled stays readable to them.");
+}
+
 fn run_dashboard() {
     use crossterm::{
         event::{DisableMouseCapture, EnableMouseCapture},
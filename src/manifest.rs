@@ -0,0 +1,325 @@
+//! Signed, append-only record of keyring membership (`<keys_dir>/manifest.jsonl`).
+//!
+//! Every line adds or removes one recipient and is signed by a member who was already
+//! in the keyring. Lines are hash-chained, so entries can't be reordered or dropped
+//! from the middle. A key file the manifest doesn't account for was put there by
+//! someone who didn't go through `arcane`.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+pub const MANIFEST_FILE: &str = "manifest.jsonl";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ManifestOp {
+    Add,
+    Remove,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ManifestEntry {
+    pub seq: u64,
+    /// SHA-256 of the previous line ("" for the first)
+    pub prev: String,
+    pub at: String,
+    pub op: ManifestOp,
+    /// Environment keyring, or `None` for the repo keyring
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<String>,
+    pub alias: String,
+    pub recipient: String,
+    pub recipient_fingerprint: String,
+    /// Alias and public key of the member who made the change
+    pub signer: String,
+    pub signer_key: String,
+    #[serde(default)]
+    pub sig: String,
+}
+
+impl ManifestEntry {
+    /// What the signature covers: the entry with an empty `sig`
+    fn signed_bytes(&self) -> Result<Vec<u8>> {
+        let mut unsigned = self.clone();
+        unsigned.sig = String::new();
+        Ok(serde_json::to_vec(&unsigned)?)
+    }
+}
+
+/// Recipients of each keyring after replaying the manifest (`None` = repo keyring)
+#[derive(Debug, Clone, Default)]
+pub struct Membership {
+    pub keyrings: HashMap<Option<String>, BTreeMap<String, String>>,
+    /// Hash of the first line, which `arcane` pins per clone
+    pub genesis: String,
+    pub entries: usize,
+}
+
+impl Membership {
+    pub fn recipient(&self, env: Option<&str>, alias: &str) -> Option<&str> {
+        self.keyrings
+            .get(&env.map(String::from))
+            .and_then(|members| members.get(alias))
+            .map(String::as_str)
+    }
+
+    /// Alias a public key is recorded under in a keyring
+    pub fn member_alias(&self, env: Option<&str>, public_key: &str) -> Option<&str> {
+        self.keyrings
+            .get(&env.map(String::from))?
+            .iter()
+            .find(|(_, recipient)| recipient.as_str() == public_key)
+            .map(|(alias, _)| alias.as_str())
+    }
+}
+
+/// Short, stable id of a recipient public key
+pub fn recipient_fingerprint(recipient: &str) -> String {
+    hex::encode(&Sha256::digest(recipient.trim().as_bytes())[..8])
+}
+
+pub fn exists(keys_dir: &Path) -> bool {
+    keys_dir.join(MANIFEST_FILE).exists()
+}
+
+/// Replay and check the manifest. `Ok(None)` if the keyring has none yet (older repos).
+pub fn verify(keys_dir: &Path) -> Result<Option<Membership>> {
+    let path = keys_dir.join(MANIFEST_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(&path)?;
+
+    let mut membership = Membership::default();
+    let mut prev = String::new();
+    for (i, line) in content.lines().filter(|l| !l.trim().is_empty()).enumerate() {
+        let entry: ManifestEntry = serde_json::from_str(line)
+            .with_context(|| format!("Manifest line {} is not valid", i + 1))?;
+        let at = |msg: &str| anyhow::anyhow!("Manifest entry {}: {}", i, msg);
+
+        if entry.seq != i as u64 || entry.prev != prev {
+            return Err(at("out of order or the chain is broken"));
+        }
+        if entry.recipient_fingerprint != recipient_fingerprint(&entry.recipient) {
+            return Err(at("recipient fingerprint doesn't match"));
+        }
+
+        // The first entry adds its own signer; after that the signer must be a member
+        // of the repo keyring or of the keyring being changed
+        let env = entry.env.as_deref();
+        let signer_ok = if i == 0 {
+            entry.op == ManifestOp::Add
+                && env.is_none()
+                && entry.signer_key == entry.recipient
+                && entry.signer == entry.alias
+        } else {
+            membership.member_alias(None, &entry.signer_key) == Some(entry.signer.as_str())
+                || membership.member_alias(env, &entry.signer_key) == Some(entry.signer.as_str())
+        };
+        if !signer_ok {
+            return Err(at(&format!(
+                "signed by '{}', who wasn't a member at that point",
+                entry.signer
+            )));
+        }
+
+        let public = decode_age_public_key(&entry.signer_key)?;
+        let sig: [u8; 64] = hex::decode(&entry.sig)
+            .ok()
+            .and_then(|s| s.try_into().ok())
+            .ok_or_else(|| at("malformed signature"))?;
+        if !xeddsa::verify(&public, &entry.signed_bytes()?, &sig) {
+            return Err(at(&format!("bad signature from '{}'", entry.signer)));
+        }
+
+        let members = membership.keyrings.entry(entry.env.clone()).or_default();
+        match entry.op {
+            ManifestOp::Add => {
+                members.insert(entry.alias.clone(), entry.recipient.clone());
+            }
+            ManifestOp::Remove => {
+                members.remove(&entry.alias);
+            }
+        }
+
+        prev = line_hash(line);
+        if i == 0 {
+            membership.genesis = prev.clone();
+        }
+        membership.entries = i + 1;
+    }
+    Ok(Some(membership))
+}
+
+/// Sign and append one change. `secret` is the signer's raw x25519 secret key.
+/// Checks the result still verifies, so a rejected entry is never left behind.
+pub fn append(
+    keys_dir: &Path,
+    secret: &[u8; 32],
+    signer: &str,
+    op: ManifestOp,
+    env: Option<&str>,
+    alias: &str,
+    recipient: &str,
+) -> Result<()> {
+    let path = keys_dir.join(MANIFEST_FILE);
+    let existing = fs::read_to_string(&path).unwrap_or_default();
+    let lines: Vec<&str> = existing.lines().filter(|l| !l.trim().is_empty()).collect();
+
+    let mut entry = ManifestEntry {
+        seq: lines.len() as u64,
+        prev: lines.last().map(|l| line_hash(l)).unwrap_or_default(),
+        at: chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+        op,
+        env: env.map(String::from),
+        alias: alias.to_string(),
+        recipient: recipient.trim().to_string(),
+        recipient_fingerprint: recipient_fingerprint(recipient),
+        signer: signer.to_string(),
+        signer_key: encode_age_public_key(&xeddsa::public_key(secret))?,
+        sig: String::new(),
+    };
+    entry.sig = hex::encode(xeddsa::sign(secret, &entry.signed_bytes()?));
+
+    let mut content = existing.clone();
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str(&serde_json::to_string(&entry)?);
+    content.push('\n');
+
+    fs::create_dir_all(keys_dir)?;
+    let tmp = keys_dir.join(format!("{}.tmp", MANIFEST_FILE));
+    fs::write(&tmp, &content)?;
+    if let Err(e) = verify_file(&tmp) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e.context("Refusing to write manifest entry"));
+    }
+    fs::rename(&tmp, &path)?;
+    Ok(())
+}
+
+fn verify_file(path: &Path) -> Result<()> {
+    let dir = tempfile_dir_for(path)?;
+    fs::copy(path, dir.join(MANIFEST_FILE))?;
+    let result = verify(&dir).map(|_| ());
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+/// Scratch directory next to `path`, for verifying a manifest before it replaces
+/// the real one
+fn tempfile_dir_for(path: &Path) -> Result<std::path::PathBuf> {
+    let parent = path.parent().context("Manifest has no parent directory")?;
+    let dir = parent.join(format!(".manifest-check-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+fn line_hash(line: &str) -> String {
+    hex::encode(Sha256::digest(line.trim_end().as_bytes()))
+}
+
+/// Raw x25519 secret from an `AGE-SECRET-KEY-1...` string
+pub fn decode_age_secret_key(secret: &str) -> Result<[u8; 32]> {
+    decode_bech32(secret, "age-secret-key-")
+}
+
+fn decode_age_public_key(public: &str) -> Result<[u8; 32]> {
+    decode_bech32(public, "age")
+}
+
+fn encode_age_public_key(public: &[u8; 32]) -> Result<String> {
+    use bech32::ToBase32;
+    Ok(bech32::encode(
+        "age",
+        public.to_base32(),
+        bech32::Variant::Bech32,
+    )?)
+}
+
+fn decode_bech32(s: &str, hrp: &str) -> Result<[u8; 32]> {
+    use bech32::FromBase32;
+    let (found, data, _) =
+        bech32::decode(s.trim()).map_err(|e| anyhow::anyhow!("Invalid age key: {}", e))?;
+    if found != hrp {
+        return Err(anyhow::anyhow!("Not an age key (expected '{}')", hrp));
+    }
+    Vec::<u8>::from_base32(&data)?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Invalid age key length"))
+}
+
+/// XEdDSA (Signal's spec): Ed25519-style signatures made and checked with X25519 keys,
+/// so a member signs with the same age identity that unwraps their repo key.
+pub(crate) mod xeddsa {
+    use curve25519_dalek::constants::ED25519_BASEPOINT_TABLE;
+    use curve25519_dalek::montgomery::MontgomeryPoint;
+    use curve25519_dalek::scalar::Scalar;
+    use rand::RngCore;
+    use sha2::{Digest, Sha512};
+
+    fn clamp(secret: &[u8; 32]) -> [u8; 32] {
+        let mut k = *secret;
+        k[0] &= 248;
+        k[31] &= 127;
+        k[31] |= 64;
+        k
+    }
+
+    fn hash_scalar(parts: &[&[u8]]) -> Scalar {
+        let mut hasher = Sha512::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        Scalar::from_bytes_mod_order_wide(&hasher.finalize().into())
+    }
+
+    /// X25519 public key (the bytes inside `age1...`)
+    pub fn public_key(secret: &[u8; 32]) -> [u8; 32] {
+        MontgomeryPoint::mul_base_clamped(*secret).to_bytes()
+    }
+
+    pub fn sign(secret: &[u8; 32], message: &[u8]) -> [u8; 64] {
+        // Edwards key pair with the sign bit forced to 0, matching what the
+        // verifier derives from the Montgomery public key
+        let k = Scalar::from_bytes_mod_order(clamp(secret));
+        let e = (&k * ED25519_BASEPOINT_TABLE).compress();
+        let a = if e.as_bytes()[31] & 0x80 != 0 { -k } else { k };
+        let a_point = (&a * ED25519_BASEPOINT_TABLE).compress();
+
+        let mut z = [0u8; 64];
+        rand::rng().fill_bytes(&mut z);
+        let mut prefix = [0xffu8; 32];
+        prefix[0] = 0xfe;
+        let r = hash_scalar(&[&prefix, a.as_bytes(), message, &z]);
+        let r_point = (&r * ED25519_BASEPOINT_TABLE).compress();
+        let h = hash_scalar(&[r_point.as_bytes(), a_point.as_bytes(), message]);
+        let s = r + h * a;
+
+        let mut sig = [0u8; 64];
+        sig[..32].copy_from_slice(r_point.as_bytes());
+        sig[32..].copy_from_slice(s.as_bytes());
+        sig
+    }
+
+    pub fn verify(public: &[u8; 32], message: &[u8], sig: &[u8; 64]) -> bool {
+        let Some(a_point) = MontgomeryPoint(*public).to_edwards(0) else {
+            return false;
+        };
+        let mut s_bytes = [0u8; 32];
+        s_bytes.copy_from_slice(&sig[32..]);
+        let Some(s) = Option::<Scalar>::from(Scalar::from_canonical_bytes(s_bytes)) else {
+            return false;
+        };
+        let r_bytes = &sig[..32];
+
+        let h = hash_scalar(&[r_bytes, a_point.compress().as_bytes(), message]);
+        let check = &s * ED25519_BASEPOINT_TABLE - h * a_point;
+        check.compress().as_bytes() == r_bytes
+    }
+}
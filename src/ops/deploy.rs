@@ -2,6 +2,8 @@ use crate::ops::config::{OpsConfig, ServerConfig};
 use crate::ops::shell::Shell;
 use crate::security::ArcaneSecurity;
 use anyhow::{Context, Result};
+use arcane::config::schema::{EnvSchema, SCHEMA_FILE};
+use arcane::image_scan::ImageScanner;
 use futures::stream::{self, StreamExt};
 use serde_yaml::Value as YamlValue;
 use std::collections::HashMap;
@@ -23,6 +25,7 @@ impl ArcaneDeployer {
         ports: Option<Vec<u16>>,
         compose_path: Option<String>,
         auto_ingress: bool, // New arg
+        warn_image_secrets: bool,
         dry_run: bool,
         parallel: bool,
     ) -> Result<()> {
@@ -55,6 +58,7 @@ impl ArcaneDeployer {
                                 ports,
                                 compose_path,
                                 auto_ingress,
+                                warn_image_secrets,
                                 dry_run,
                                 &format!("[{}]", server_name),
                             )
@@ -89,6 +93,7 @@ impl ArcaneDeployer {
                         ports.clone(),
                         compose_path.clone(),
                         auto_ingress,
+                        warn_image_secrets,
                         dry_run,
                         "",
                     )
@@ -111,12 +116,44 @@ impl ArcaneDeployer {
             ports,
             compose_path,
             auto_ingress,
+            warn_image_secrets,
             dry_run,
             "", // No prefix for direct target
         )
         .await
     }
 
+    /// Fail the deploy if the environment doesn't match the repo's schema (if it has one)
+    fn check_schema(
+        project_root: &Path,
+        env_name: &str,
+        variables: &HashMap<String, String>,
+        prefix: &str,
+    ) -> Result<()> {
+        let Some(schema) = EnvSchema::load(project_root)? else {
+            return Ok(());
+        };
+        let violations = schema.check(env_name, variables);
+        if violations.is_empty() {
+            Self::log(prefix, &format!("   ✅ Matches {}", SCHEMA_FILE));
+            return Ok(());
+        }
+
+        Self::log(
+            prefix,
+            &format!("🚫 ENVIRONMENT DOESN'T MATCH {}:", SCHEMA_FILE),
+        );
+        for violation in &violations {
+            Self::log(prefix, &format!("   - {}", violation));
+        }
+        Err(anyhow::anyhow!(
+            "Environment '{}' has {} schema violation(s). Fix them with 'arcane env set {} <KEY>'.",
+            env_name,
+            violations.len(),
+            env_name
+        ))
+    }
+
     /// Internal helper for deploying to a single server.
     /// Dispatches to Compose or Single Image strategy.
     async fn deploy_target(
@@ -126,6 +163,7 @@ impl ArcaneDeployer {
         ports: Option<Vec<u16>>,
         compose_path: Option<String>,
         auto_ingress: bool,
+        warn_image_secrets: bool,
         dry_run: bool,
         prefix: &str,
     ) -> Result<()> {
@@ -173,6 +211,9 @@ impl ArcaneDeployer {
             &security,
             repo_key.as_ref(),
         )?;
+        for problem in &env.undefined {
+            Self::log(prefix, &format!("⚠️  {}", problem));
+        }
 
         if dry_run {
             Self::log(
@@ -184,6 +225,9 @@ impl ArcaneDeployer {
             );
         }
 
+        // 3.5 Check against config/envs/schema.toml, before anything is locked
+        Self::check_schema(&project_root, env_name, &env.variables, prefix)?;
+
         // 4. Acquire Lock
         Self::log(prefix, "🔒 Acquiring distributed lock...");
         let _lock_guard = DeployLock::acquire(server, dry_run, prefix).await?;
@@ -206,6 +250,7 @@ impl ArcaneDeployer {
                 deployment_ref,
                 env.variables,
                 ports,
+                warn_image_secrets,
                 dry_run,
                 prefix,
             )
@@ -402,6 +447,7 @@ impl ArcaneDeployer {
         image: &str,
         env_vars: HashMap<String, String>,
         ports: Option<Vec<u16>>,
+        warn_image_secrets: bool,
         dry_run: bool,
         prefix: &str,
     ) -> Result<()> {
@@ -434,6 +480,13 @@ impl ArcaneDeployer {
             );
         }
 
+        // Scan before anything leaves this machine
+        if dry_run {
+            Self::log(prefix, "   [DRY RUN] Would scan image layers for secrets.");
+        } else {
+            Self::scan_image(image, warn_image_secrets, prefix)?;
+        }
+
         // Push
         Self::log(prefix, "   🚀 Pushing image via Warp Drive (Zstd)...");
         // Shell::push_compressed_image prints to output. We might see interleaving.
@@ -467,6 +520,72 @@ impl ArcaneDeployer {
         Self::deploy_standard(server, image, base_name, env_flags, ports, dry_run, prefix).await
     }
 
+    /// Scan the saved image for secrets and sensitive files. Blocks the push unless
+    /// `warn_only`, in which case the findings are only printed.
+    fn scan_image(image: &str, warn_only: bool, prefix: &str) -> Result<()> {
+        Self::log(prefix, "   🔍 Scanning image layers for secrets...");
+        let project_root = ArcaneSecurity::find_repo_root()?;
+        let scan = ImageScanner::for_repo(&project_root)?.scan_image(image)?;
+
+        if scan.skipped_layers > 0 {
+            Self::log(
+                prefix,
+                &format!(
+                    "   ⚠️  Skipped {} zstd-compressed layer(s); they weren't scanned.",
+                    scan.skipped_layers
+                ),
+            );
+        }
+        if scan.findings.is_empty() && scan.skipped_layers == 0 {
+            Self::log(
+                prefix,
+                &format!(
+                    "   ✅ No secrets in {} files across {} layers.",
+                    scan.files, scan.layers
+                ),
+            );
+            return Ok(());
+        }
+
+        if !scan.findings.is_empty() {
+            Self::log(prefix, "   🚫 SECRETS IN IMAGE:");
+        }
+        for found in &scan.findings {
+            let path = found.path.display();
+            if found.sensitive {
+                Self::log(
+                    prefix,
+                    &format!("      - [{}] /{}: sensitive file", found.layer, path),
+                );
+            }
+            for f in &found.findings {
+                Self::log(
+                    prefix,
+                    &format!(
+                        "      - [{}] /{}:{}:{}: {} [{}] {}",
+                        found.layer, path, f.line, f.column, f.name, f.severity, f.redacted
+                    ),
+                );
+            }
+        }
+
+        if warn_only {
+            Self::log(prefix, "   ⚠️  Pushing anyway (--warn-image-secrets).");
+            return Ok(());
+        }
+        if scan.findings.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Image '{}' has {} layer(s) arcane can't scan. Rebuild with gzip layers, or pass --warn-image-secrets to push anyway.",
+                image,
+                scan.skipped_layers
+            ));
+        }
+        Err(anyhow::anyhow!(
+            "Image '{}' contains secrets. Fix .dockerignore, or pass --warn-image-secrets to push anyway.",
+            image
+        ))
+    }
+
     async fn deploy_blue_green(
         server: &ServerConfig,
         image: &str,
@@ -1,3 +1,4 @@
+use crate::security::ArcaneSecurity;
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use std::fs;
@@ -113,12 +114,7 @@ impl RepoManager {
 
         // 1. Add pinned (explicitly watched) repos
         for config in &self.watched_repos {
-            let is_secured = config
-                .path
-                .join(".git")
-                .join("arcane")
-                .join("keys")
-                .exists();
+            let is_secured = ArcaneSecurity::keys_dir_for(&config.path).exists();
             results.insert(
                 config.path.clone(),
                 RepoStatus {
@@ -147,8 +143,7 @@ impl RepoManager {
                                     .unwrap_or("unknown")
                                     .to_string();
 
-                                let is_secured =
-                                    path.join(".git").join("arcane").join("keys").exists();
+                                let is_secured = ArcaneSecurity::keys_dir_for(&path).exists();
 
                                 results.insert(
                                     path.clone(),
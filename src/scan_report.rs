@@ -0,0 +1,193 @@
+//! Scan results in the shapes other tools read: the JSON `arcane scan --format json`
+//! prints (also what the TUI shows) and SARIF 2.1.0 for code-scanning dashboards.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::security::{FindingKind, HistoryFinding, SecretFinding, Severity};
+
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanFormat {
+    Text,
+    Json,
+    Sarif,
+}
+
+impl std::str::FromStr for ScanFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "sarif" => Ok(Self::Sarif),
+            other => Err(anyhow::anyhow!("Unknown format '{}'", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScanReport {
+    pub findings: Vec<ReportedFinding>,
+}
+
+/// One finding, without the secret
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportedFinding {
+    pub rule_id: String,
+    pub rule: String,
+    pub kind: FindingKind,
+    pub severity: Severity,
+    /// Relative to the repo root, with `/` separators
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    #[serde(rename = "match")]
+    pub redacted: String,
+    /// For history scans: the commit that introduced the finding, and its author
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+}
+
+impl ScanReport {
+    /// Report for scan results; paths under `repo_root` are made relative to it
+    pub fn new(repo_root: &Path, results: &[(PathBuf, Vec<SecretFinding>)]) -> Self {
+        let findings = results
+            .iter()
+            .flat_map(|(path, findings)| {
+                let file = report_path(path.strip_prefix(repo_root).unwrap_or(path));
+                findings
+                    .iter()
+                    .map(move |f| ReportedFinding::new(file.clone(), f))
+            })
+            .collect();
+        Self { findings }
+    }
+
+    /// Report for `arcane scan --history`
+    pub fn from_history(results: &[HistoryFinding]) -> Self {
+        let findings = results
+            .iter()
+            .flat_map(|found| {
+                found.findings.iter().map(|f| ReportedFinding {
+                    commit: Some(found.commit.clone()),
+                    author: Some(found.author.clone()),
+                    ..ReportedFinding::new(report_path(&found.path), f)
+                })
+            })
+            .collect();
+        Self { findings }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    /// Highest severity found, if anything was
+    pub fn max_severity(&self) -> Option<Severity> {
+        self.findings.iter().map(|f| f.severity).max()
+    }
+
+    /// Whether any finding is at least `threshold`
+    pub fn reaches(&self, threshold: Severity) -> bool {
+        self.max_severity().is_some_and(|s| s >= threshold)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn to_sarif(&self) -> Result<String> {
+        // One rule entry per rule that fired; results point at it by id
+        let mut rules = BTreeMap::new();
+        for f in &self.findings {
+            rules.entry(f.rule_id.as_str()).or_insert_with(|| {
+                json!({
+                    "id": f.rule_id,
+                    "name": f.rule,
+                    "shortDescription": { "text": f.rule },
+                    "defaultConfiguration": { "level": sarif_level(f.severity) },
+                    "properties": { "severity": f.severity.to_string() },
+                })
+            });
+        }
+
+        let results: Vec<_> = self
+            .findings
+            .iter()
+            .map(|f| {
+                let mut properties = json!({ "severity": f.severity.to_string() });
+                if let (Some(commit), Some(author)) = (&f.commit, &f.author) {
+                    properties["commit"] = json!(commit);
+                    properties["author"] = json!(author);
+                }
+                json!({
+                    "ruleId": f.rule_id,
+                    "level": sarif_level(f.severity),
+                    "message": {
+                        "text": format!("Potential {} ({})", f.rule, f.redacted),
+                    },
+                    "locations": [{
+                        "physicalLocation": {
+                            "artifactLocation": { "uri": f.file },
+                            "region": { "startLine": f.line, "startColumn": f.column },
+                        },
+                    }],
+                    "properties": properties,
+                })
+            })
+            .collect();
+
+        let sarif = json!({
+            "$schema": SARIF_SCHEMA,
+            "version": "2.1.0",
+            "runs": [{
+                "tool": {
+                    "driver": {
+                        "name": "arcane",
+                        "version": env!("CARGO_PKG_VERSION"),
+                        "rules": rules.into_values().collect::<Vec<_>>(),
+                    },
+                },
+                "results": results,
+            }],
+        });
+        Ok(serde_json::to_string_pretty(&sarif)?)
+    }
+}
+
+impl ReportedFinding {
+    fn new(file: String, f: &SecretFinding) -> Self {
+        Self {
+            rule_id: f.rule_id.clone(),
+            rule: f.name.clone(),
+            kind: f.kind,
+            severity: f.severity,
+            file,
+            line: f.line,
+            column: f.column,
+            redacted: f.redacted.clone(),
+            commit: None,
+            author: None,
+        }
+    }
+}
+
+fn report_path(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+fn sarif_level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Critical | Severity::High => "error",
+        Severity::Medium => "warning",
+        Severity::Low => "note",
+    }
+}
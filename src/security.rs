@@ -1,23 +1,125 @@
 use crate::auto_gitignore::AutoGitIgnore;
+use crate::config::fields::{self, FieldsConfig, StructuredFormat};
+use crate::config::scanner::{BaselineEntry, ScanBaseline, ScannerConfig, SCAN_BASELINE_FILE};
 use crate::config::ArcaneConfig;
+use crate::manifest::{self, ManifestOp};
 use aes_gcm::{
-    aead::{Aead, KeyInit},
+    aead::{Aead, KeyInit, Payload},
     Aes256Gcm, Key, Nonce,
 };
 use age::x25519;
 use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
 use rand::RngCore;
 use regex::Regex;
-use secrecy::ExposeSecret;
+use secrecy::{ExposeSecret, SecretString};
+use sha2::Sha256;
+use std::collections::HashSet;
 use std::fs;
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use zeroize::{Zeroize, ZeroizeOnDrop};
 
 const REPO_KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+/// Domain separator for the subkey used to derive deterministic nonces
+const SIV_NONCE_CONTEXT: &[u8] = b"arcane-siv-nonce-v1";
+/// Domain separator for repo key fingerprints
+const KEY_FINGERPRINT_CONTEXT: &[u8] = b"arcane-key-fingerprint-v1";
+const KEY_FINGERPRINT_LEN: usize = 8;
+
+/// Sealed blob layout:
+/// `MAGIC (7) || version (1) || cipher id (1) || key fingerprint (8) || nonce (12) || ciphertext`
+/// The leading NUL byte never appears in text files, so plaintext can't be mistaken for a seal.
+const SEAL_MAGIC: &[u8] = b"\0ARCANE";
+const SEAL_VERSION: u8 = 1;
+const SEAL_HEADER_LEN: usize = SEAL_MAGIC.len() + 2 + KEY_FINGERPRINT_LEN;
+/// File recording which key a keyring holds: in every keys/history/<timestamp>/
+/// snapshot and in each environment keyring
+const KEY_FINGERPRINT_FILE: &str = "fingerprint";
+/// Per-environment keyrings live in `<keys_dir>/env/<env>/`
+const ENV_KEYS_DIR: &str = "env";
+/// Hash of the key manifest's first entry, pinned per clone in `.git/arcane/`
+const MANIFEST_PIN_FILE: &str = "manifest.pin";
+
+const TEAM_INVITE_VERSION: u8 = 1;
+/// Nonces of accepted or revoked invites, next to the invites (tracked)
+const USED_INVITES_FILE: &str = "used";
+/// Error from `load_master_identity` when there is no identity at all
+const IDENTITY_NOT_FOUND: &str = "Identity file not found";
+/// Body of `identity.age` when the secret key lives in the OS keyring
+const IDENTITY_KEYRING_MARKER: &str = "ARCANE-KEYRING";
+const KEYRING_SERVICE: &str = "arcane";
+const KEYRING_USER: &str = "master-identity";
+/// Passphrase for a protected identity.age, for scripts and CI (otherwise prompted)
+const PASSPHRASE_ENV: &str = "ARCANE_PASSPHRASE";
+/// Tracked keyring location, relative to the repo root
+pub const TRACKED_KEYS_DIR: &str = ".arcane/keys";
+
+/// Files bigger than this aren't scanned: bundles, dumps and other generated output
+pub(crate) const MAX_SCAN_FILE_BYTES: u64 = 1024 * 1024;
+/// How much of a file is checked for NUL bytes to tell binary from text
+const BINARY_SNIFF_BYTES: usize = 8000;
+
+/// Shortest value the entropy detector looks at, with and without a secret-ish key name
+const ENTROPY_MIN_LEN: usize = 20;
+const ENTROPY_MIN_LEN_KEYWORD: usize = 16;
+/// A value is flagged when its Shannon entropy reaches this fraction of the most a
+/// string of its length and charset can have
+const ENTROPY_RATIO: f64 = 0.9;
+const ENTROPY_RATIO_KEYWORD: f64 = 0.75;
+/// Key names that make a random-looking value more likely to be a secret
+const SECRET_KEYWORDS: &[&str] = &[
+    "secret",
+    "token",
+    "password",
+    "passwd",
+    "pwd",
+    "key",
+    "credential",
+    "auth",
+    "private",
+    "salt",
+    "signature",
+];
+/// Key name words whose values are random by design but not secret
+const NON_SECRET_KEYWORDS: &[&str] = &[
+    "sha",
+    "hash",
+    "checksum",
+    "digest",
+    "commit",
+    "rev",
+    "integrity",
+    "version",
+    "uuid",
+    "id",
+    "etag",
+    "nonce",
+];
+
+/// One detection rule: built in, or from `.arcane/scanner.toml`
+struct ScanRule {
+    name: String,
+    regex: Regex,
+    severity: Severity,
+    /// Lowercase; when set, the regex only runs on lines containing one of them
+    keywords: Vec<String>,
+}
 
 pub struct SecretScanner {
-    patterns: Vec<(String, Regex)>,
+    rules: Vec<ScanRule>,
+    /// Literals every match of a rule must contain, so most lines never reach a full
+    /// regex. `None` runs every rule on every line.
+    prefilter: Option<Prefilter>,
+    /// `key = value`, `key: value`, `"key": "value"` and friends
+    assignment: Regex,
+    entropy: bool,
+    allow_paths: Vec<glob::Pattern>,
+    allow_regexes: Vec<Regex>,
+    /// Fingerprints from `.arcane/scan-baseline.json`
+    baseline: HashSet<String>,
 }
 
 impl SecretScanner {
@@ -268,49 +370,513 @@ impl SecretScanner {
             ),
         ];
 
-        let compiled: Vec<(String, Regex)> = patterns
+        let rules: Vec<ScanRule> = patterns
             .into_iter()
-            .filter_map(|(name, pattern)| Regex::new(pattern).map(|re| (name.to_string(), re)).ok())
+            .filter_map(|(name, pattern)| {
+                Regex::new(pattern).ok().map(|regex| ScanRule {
+                    name: name.to_string(),
+                    regex,
+                    severity: builtin_severity(name),
+                    keywords: Vec::new(),
+                })
+            })
             .collect();
 
-        Self { patterns: compiled }
+        let assignment = Regex::new(
+            r#"(?P<key>[A-Za-z_][A-Za-z0-9_.\-]*)["']?\s*(?::=|=>|=|:)\s*["'`]?(?P<value>[^\s"'`,;]+)"#,
+        )
+        .expect("assignment pattern");
+
+        Self {
+            prefilter: Prefilter::new(&rules),
+            rules,
+            assignment,
+            entropy: true,
+            allow_paths: Vec::new(),
+            allow_regexes: Vec::new(),
+            baseline: HashSet::new(),
+        }
+    }
+
+    /// Built-in rules adjusted by a repo's `.arcane/scanner.toml`
+    pub fn with_config(config: &ScannerConfig) -> Result<Self> {
+        let mut scanner = Self::new();
+
+        for name in &config.disable {
+            if name.eq_ignore_ascii_case("entropy") {
+                scanner.entropy = false;
+                continue;
+            }
+            let before = scanner.rules.len();
+            scanner.rules.retain(|r| !r.name.eq_ignore_ascii_case(name));
+            if scanner.rules.len() == before {
+                return Err(anyhow::anyhow!(
+                    "Unknown built-in rule '{}' in disable",
+                    name
+                ));
+            }
+        }
+
+        for rule in &config.rules {
+            let regex = Regex::new(&rule.regex)
+                .with_context(|| format!("Invalid regex in rule '{}'", rule.name))?;
+            scanner.rules.push(ScanRule {
+                name: rule.name.clone(),
+                regex,
+                severity: rule.severity,
+                keywords: rule.keywords.iter().map(|k| k.to_lowercase()).collect(),
+            });
+        }
+
+        for path in &config.allowlist.paths {
+            scanner.allow_paths.push(
+                glob::Pattern::new(path)
+                    .with_context(|| format!("Invalid allowlist path '{}'", path))?,
+            );
+        }
+        for pattern in &config.allowlist.regexes {
+            scanner.allow_regexes.push(
+                Regex::new(pattern)
+                    .with_context(|| format!("Invalid allowlist regex '{}'", pattern))?,
+            );
+        }
+
+        scanner.prefilter = Prefilter::new(&scanner.rules);
+        Ok(scanner)
+    }
+
+    /// Run every rule's regex on every line, as before the prefilter existed
+    #[cfg(test)]
+    pub(crate) fn without_prefilter(mut self) -> Self {
+        self.prefilter = None;
+        self
+    }
+
+    /// Scanner for a repo, with its `.arcane/scanner.toml` applied and the findings in
+    /// `.arcane/scan-baseline.json` left out
+    pub fn for_repo(repo_root: &Path) -> Result<Self> {
+        let mut scanner = Self::with_config(&ScannerConfig::load(repo_root)?)?;
+        scanner.baseline = ScanBaseline::load(repo_root)?.fingerprints();
+        Ok(scanner)
+    }
+
+    /// Whether a path (relative to the repo root) is on the allowlist. The baseline
+    /// itself is full of fingerprints that look random, and is always skipped.
+    pub fn is_path_allowed(&self, path: &Path) -> bool {
+        path == Path::new(SCAN_BASELINE_FILE)
+            || self.allow_paths.iter().any(|p| p.matches_path(path))
+    }
+
+    /// Scan a file's content, unless its path (relative to the repo root) is allowlisted.
+    /// Findings in the baseline are left out.
+    pub fn scan_file(&self, path: &Path, content: &str) -> Vec<SecretFinding> {
+        if self.is_path_allowed(path) {
+            return Vec::new();
+        }
+        let lines: Vec<&str> = content.lines().collect();
+        self.scan(content)
+            .into_iter()
+            .filter(|f| !self.in_baseline(path, f, lines[f.line - 1]))
+            .collect()
+    }
+
+    fn in_baseline(&self, path: &Path, finding: &SecretFinding, line: &str) -> bool {
+        !self.baseline.is_empty()
+            && self
+                .baseline
+                .contains(&finding_fingerprint(path, finding, line))
+    }
+
+    /// Scan the lines a unified diff adds, per file, with line numbers in the new file.
+    /// Context lines only count for `arcane:allow` on the line above. Allowlisted paths,
+    /// deletions and binary (e.g. sealed) files are skipped.
+    pub fn scan_diff(&self, diff: &str) -> ScanResults {
+        // `None` for a file whose path we can't read, so its lines go nowhere
+        let mut files: Vec<(Option<PathBuf>, Vec<DiffLine>)> = Vec::new();
+        let mut next_line = 0;
+        // Lines of the current hunk still to come, per side. Inside a hunk every line
+        // is content, even an added one that reads `+++ b/x`.
+        let (mut old_left, mut new_left) = (0usize, 0usize);
+        for line in diff.lines() {
+            if old_left > 0 || new_left > 0 {
+                let shown = match line.chars().next() {
+                    Some('+') if new_left > 0 => {
+                        new_left -= 1;
+                        Some((&line[1..], true))
+                    }
+                    Some('-') if old_left > 0 => {
+                        old_left -= 1;
+                        continue;
+                    }
+                    Some(' ') | None => {
+                        old_left = old_left.saturating_sub(1);
+                        new_left = new_left.saturating_sub(1);
+                        Some((line.get(1..).unwrap_or(""), false))
+                    }
+                    // "\ No newline at end of file"
+                    Some('\\') => continue,
+                    // The counts were off; read it as a header
+                    _ => {
+                        old_left = 0;
+                        new_left = 0;
+                        None
+                    }
+                };
+                if let Some((text, added)) = shown {
+                    if let Some((_, lines)) = files.last_mut() {
+                        lines.push(DiffLine {
+                            number: next_line,
+                            text,
+                            added,
+                        });
+                    }
+                    next_line += 1;
+                    continue;
+                }
+            }
+
+            // Every file starts a new entry, so a hunk is never put in the one before
+            if line.starts_with("diff --git ") {
+                files.push((None, Vec::new()));
+            } else if let Some(path) = line.strip_prefix("+++ ") {
+                files.push((diff_header_path(path), Vec::new()));
+            } else if let Some(hunk) = line.strip_prefix("@@ ") {
+                // @@ -a,b +c,d @@: b old and d new lines follow; the first new one is c
+                let range = |sign: char| {
+                    let range = hunk
+                        .split_whitespace()
+                        .find_map(|part| part.strip_prefix(sign))?;
+                    let mut parts = range.split(',');
+                    let start: usize = parts.next()?.parse().ok()?;
+                    let count: usize = parts.next().map_or(Some(1), |c| c.parse().ok())?;
+                    Some((start, count))
+                };
+                let (start, count) = range('+').unwrap_or((0, 0));
+                next_line = start;
+                new_left = count;
+                old_left = range('-').map_or(0, |(_, count)| count);
+            }
+        }
+
+        let mut findings = Vec::new();
+        for (path, lines) in files {
+            let Some(path) = path else { continue };
+            if !lines.iter().any(|l| l.added) || self.is_path_allowed(&path) {
+                continue;
+            }
+            // Rebuild the new file with blanks for lines the diff doesn't show, so
+            // line numbers and "the line above" are right
+            let mut content = vec![""; lines.iter().map(|l| l.number).max().unwrap_or(0)];
+            for line in lines.iter().filter(|l| l.number > 0) {
+                content[line.number - 1] = line.text;
+            }
+            let added: HashSet<usize> =
+                lines.iter().filter(|l| l.added).map(|l| l.number).collect();
+            let matches: Vec<SecretFinding> = self
+                .scan(&content.join("\n"))
+                .into_iter()
+                .filter(|f| added.contains(&f.line))
+                .filter(|f| !self.in_baseline(&path, f, content[f.line - 1]))
+                .collect();
+            if !matches.is_empty() {
+                findings.push((path, matches));
+            }
+        }
+        findings
     }
 
     pub fn scan(&self, content: &str) -> Vec<SecretFinding> {
         let mut found = Vec::new();
+        let mut previous_allow = None;
         // Split by lines to provide line numbers
         for (line_idx, line) in content.lines().enumerate() {
             let line_num = line_idx + 1;
-            for (name, re) in &self.patterns {
-                if re.is_match(line) {
-                    // Truncate snippet if too long
-                    let snippet = if line.len() > 60 {
-                        format!("{}...", &line[..60])
-                    } else {
-                        line.to_string()
-                    };
-
+            // `arcane:allow` covers its own line and the next one
+            let allow = inline_allow(line);
+            let allowed = |name: &str| {
+                [&allow, &previous_allow]
+                    .into_iter()
+                    .flatten()
+                    .any(|rules| {
+                        rules.is_empty()
+                            || rules.iter().any(|r| {
+                                r.eq_ignore_ascii_case(name)
+                                    || (r.eq_ignore_ascii_case("entropy")
+                                        && name.starts_with("High-Entropy Value"))
+                            })
+                    })
+            };
+            let skip_line = self.allow_regexes.iter().any(|re| re.is_match(line));
+            // Field-sealed values are ciphertext: random-looking, but not secret
+            let sealed = fields::sealed_field_ranges(line);
+            let in_sealed = |range: &std::ops::Range<usize>| {
+                sealed
+                    .iter()
+                    .any(|s| range.start < s.end && s.start < range.end)
+            };
+            let candidates: Vec<usize> = match &self.prefilter {
+                _ if skip_line => Vec::new(),
+                Some(prefilter) => prefilter.candidates(line),
+                None => (0..self.rules.len()).collect(),
+            };
+            let mut lower = None;
+            let mut matched = false;
+            for rule in candidates.into_iter().map(|i| &self.rules[i]) {
+                if !rule.keywords.is_empty() {
+                    let lower: &String = lower.get_or_insert_with(|| line.to_lowercase());
+                    if !rule.keywords.iter().any(|k| lower.contains(k)) {
+                        continue;
+                    }
+                }
+                if let Some(m) = rule.regex.find(line).filter(|m| !in_sealed(&m.range())) {
+                    matched = true;
+                    if allowed(&rule.name) {
+                        continue;
+                    }
                     found.push(SecretFinding {
-                        name: name.clone(),
+                        rule_id: rule_id(&rule.name),
+                        name: rule.name.clone(),
+                        kind: FindingKind::Pattern,
+                        severity: rule.severity,
                         line: line_num,
-                        snippet,
+                        column: line[..m.start()].chars().count() + 1,
+                        range: m.range(),
+                        redacted: redact(m.as_str()),
                     });
                 }
             }
+
+            // Known formats say more than entropy does; only fall back to it
+            if self.entropy && !skip_line && !matched {
+                if let Some(finding) = self.scan_entropy(line, line_num) {
+                    if !allowed(&finding.name) && !in_sealed(&finding.range) {
+                        found.push(finding);
+                    }
+                }
+            }
+            previous_allow = allow;
         }
         found
     }
+
+    /// First assignment on the line whose value looks random enough to be a secret
+    fn scan_entropy(&self, line: &str, line_num: usize) -> Option<SecretFinding> {
+        for caps in self.assignment.captures_iter(line) {
+            let key = caps["key"].to_ascii_lowercase();
+            let value = &caps["value"];
+
+            let words: Vec<&str> = key.split(['_', '-', '.']).collect();
+            if words.iter().any(|w| NON_SECRET_KEYWORDS.contains(w)) {
+                continue;
+            }
+            let keyword = SECRET_KEYWORDS.iter().any(|k| key.contains(k));
+            let (min_len, ratio) = if keyword {
+                (ENTROPY_MIN_LEN_KEYWORD, ENTROPY_RATIO_KEYWORD)
+            } else {
+                (ENTROPY_MIN_LEN, ENTROPY_RATIO)
+            };
+
+            // Paths, URLs and templated values aren't secrets themselves
+            if value.len() < min_len
+                || value.starts_with('/')
+                || value.contains(['$', '{', '}', '(', ')'])
+            {
+                continue;
+            }
+
+            let (charset, bits_per_char) = if value.chars().all(|c| c.is_ascii_hexdigit()) {
+                ("hex", 4.0)
+            } else if value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '/' | '=' | '-' | '_'))
+            {
+                ("base64", 6.0)
+            } else if value.chars().all(|c| c.is_ascii_graphic()) {
+                ("mixed", 94f64.log2())
+            } else {
+                continue;
+            };
+            // Random tokens mix letters and digits; identifiers and words usually don't
+            if !value.chars().any(|c| c.is_ascii_digit())
+                || !value.chars().any(|c| c.is_ascii_alphabetic())
+            {
+                continue;
+            }
+
+            let max_entropy = (value.len() as f64).log2().min(bits_per_char);
+            if shannon_entropy(value) >= ratio * max_entropy {
+                let name = format!("High-Entropy Value ({})", charset);
+                let range = caps.name("value").map_or(0..line.len(), |v| v.range());
+                return Some(SecretFinding {
+                    rule_id: rule_id(&name),
+                    name,
+                    kind: FindingKind::Entropy,
+                    severity: Severity::Medium,
+                    line: line_num,
+                    column: line[..range.start].chars().count() + 1,
+                    range,
+                    redacted: redact(value),
+                });
+            }
+        }
+        None
+    }
+
     /// Returns the number of patterns loaded
     pub fn pattern_count(&self) -> usize {
-        self.patterns.len()
+        self.rules.len()
+    }
+}
+
+/// How a `SecretFinding` was detected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FindingKind {
+    /// Matched a known secret format
+    Pattern,
+    /// A random-looking value assigned to a variable
+    Entropy,
+}
+
+/// How bad a finding is, lowest first
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+            Severity::Critical => "critical",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::str::FromStr for Severity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "low" => Ok(Severity::Low),
+            "medium" => Ok(Severity::Medium),
+            "high" => Ok(Severity::High),
+            "critical" => Ok(Severity::Critical),
+            other => Err(anyhow::anyhow!("Unknown severity '{}'", other)),
+        }
+    }
+}
+
+/// Severity of a built-in rule: key material is critical, keyword-only guesses medium
+fn builtin_severity(name: &str) -> Severity {
+    if name.starts_with("Generic")
+        || name == "Private Token Pattern"
+        || name == "Private Key Variable (Unquoted)"
+    {
+        Severity::Medium
+    } else if name.contains("Private Key") || name == "Age Secret Key" {
+        Severity::Critical
+    } else {
+        Severity::High
+    }
+}
+
+/// Findings in one blob from history, with the commit that first added the blob
+#[derive(Debug, Clone)]
+pub struct HistoryFinding {
+    pub commit: String,
+    /// `Name <email>`
+    pub author: String,
+    pub date: String,
+    pub blob: String,
+    pub path: PathBuf,
+    pub findings: Vec<SecretFinding>,
+}
+
+/// Findings grouped by file
+pub type ScanResults = Vec<(PathBuf, Vec<SecretFinding>)>;
+
+/// The path in a `+++ b/<path>` header. Git quotes paths with unusual characters
+/// C-style (`"b/caf\303\251.env"`) and ends ones with spaces in a tab.
+/// `None` for `/dev/null` or anything else that isn't a `b/` path.
+fn diff_header_path(header: &str) -> Option<PathBuf> {
+    let header = header.strip_suffix('\t').unwrap_or(header);
+    let path = match header
+        .strip_prefix('"')
+        .and_then(|quoted| quoted.strip_suffix('"'))
+    {
+        Some(quoted) => c_unquote(quoted)?,
+        None => header.to_string(),
+    };
+    path.strip_prefix("b/").map(PathBuf::from)
+}
+
+/// Undo git's C-style quoting: `\"`, `\\`, `\t`-style escapes and `\ooo` octal bytes
+fn c_unquote(quoted: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(quoted.len());
+    let mut rest = quoted.as_bytes();
+    while let Some((&b, after)) = rest.split_first() {
+        rest = after;
+        if b != b'\\' {
+            bytes.push(b);
+            continue;
+        }
+        let (&escape, after) = rest.split_first()?;
+        rest = after;
+        bytes.push(match escape {
+            b'a' => 0x07,
+            b'b' => 0x08,
+            b't' => b'\t',
+            b'n' => b'\n',
+            b'v' => 0x0b,
+            b'f' => 0x0c,
+            b'r' => b'\r',
+            b'0'..=b'3' => {
+                let digits = rest.get(..2)?;
+                if !digits.iter().all(|d| (b'0'..=b'7').contains(d)) {
+                    return None;
+                }
+                rest = &rest[2..];
+                (escape - b'0') * 64 + (digits[0] - b'0') * 8 + (digits[1] - b'0')
+            }
+            other => other,
+        });
     }
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// A line of the new file shown in a diff hunk
+struct DiffLine<'a> {
+    number: usize,
+    text: &'a str,
+    /// Added by the diff, as opposed to context
+    added: bool,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct SecretFinding {
+    /// Stable id for tools, e.g. `aws-access-key-id`
+    pub rule_id: String,
     pub name: String,
+    pub kind: FindingKind,
+    pub severity: Severity,
     pub line: usize,
-    pub snippet: String,
+    /// 1-based, in characters
+    pub column: usize,
+    /// Byte range of the match within the line
+    pub range: std::ops::Range<usize>,
+    /// The match with most of it masked, e.g. `sk_live_****c9f2`. Findings never
+    /// carry the secret itself; show or log this instead.
+    pub redacted: String,
 }
 
 #[derive(Zeroize, ZeroizeOnDrop)]
@@ -327,10 +893,355 @@ impl RepoKey {
         }
         Ok(RepoKey(bytes))
     }
+
+    /// Short, non-secret identifier of this key (stored in sealed file headers)
+    pub fn fingerprint(&self) -> [u8; KEY_FINGERPRINT_LEN] {
+        use sha2::Digest;
+        let mut hasher = Sha256::new();
+        hasher.update(KEY_FINGERPRINT_CONTEXT);
+        hasher.update(&self.0);
+        let digest = hasher.finalize();
+
+        let mut fingerprint = [0u8; KEY_FINGERPRINT_LEN];
+        fingerprint.copy_from_slice(&digest[..KEY_FINGERPRINT_LEN]);
+        fingerprint
+    }
+
+    /// Hex form of the fingerprint (used in file names and messages)
+    pub fn fingerprint_hex(&self) -> String {
+        hex::encode(self.fingerprint())
+    }
+}
+
+/// Cipher used for the payload of a sealed file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SealCipher {
+    Aes256Gcm = 1,
+}
+
+impl SealCipher {
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(SealCipher::Aes256Gcm),
+            _ => None,
+        }
+    }
+}
+
+/// Self-describing header at the start of every sealed file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SealHeader {
+    pub version: u8,
+    pub cipher: SealCipher,
+    pub key_fingerprint: [u8; KEY_FINGERPRINT_LEN],
+}
+
+impl SealHeader {
+    fn new(repo_key: &RepoKey) -> Self {
+        Self {
+            version: SEAL_VERSION,
+            cipher: SealCipher::Aes256Gcm,
+            key_fingerprint: repo_key.fingerprint(),
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(SEAL_HEADER_LEN);
+        bytes.extend_from_slice(SEAL_MAGIC);
+        bytes.push(self.version);
+        bytes.push(self.cipher as u8);
+        bytes.extend_from_slice(&self.key_fingerprint);
+        bytes
+    }
+
+    /// Parse the header of a sealed blob. Returns `Ok(None)` if the data has no Arcane magic.
+    pub fn parse(data: &[u8]) -> Result<Option<Self>> {
+        if !data.starts_with(SEAL_MAGIC) {
+            return Ok(None);
+        }
+        if data.len() < SEAL_HEADER_LEN {
+            return Err(anyhow::anyhow!("Truncated Arcane header"));
+        }
+
+        let version = data[SEAL_MAGIC.len()];
+        if version != SEAL_VERSION {
+            return Err(anyhow::anyhow!(
+                "Unsupported Arcane format version {} (upgrade arcane)",
+                version
+            ));
+        }
+
+        let cipher_id = data[SEAL_MAGIC.len() + 1];
+        let cipher = SealCipher::from_id(cipher_id)
+            .ok_or_else(|| anyhow::anyhow!("Unsupported Arcane cipher id {}", cipher_id))?;
+
+        let mut key_fingerprint = [0u8; KEY_FINGERPRINT_LEN];
+        key_fingerprint.copy_from_slice(&data[SEAL_MAGIC.len() + 2..SEAL_HEADER_LEN]);
+
+        Ok(Some(Self {
+            version,
+            cipher,
+            key_fingerprint,
+        }))
+    }
+
+    pub fn key_fingerprint_hex(&self) -> String {
+        hex::encode(self.key_fingerprint)
+    }
+}
+
+/// A keyring whose key was created or rotated
+#[derive(Debug, Clone)]
+pub struct KeyringUpdate {
+    /// "repo" or the environment name
+    pub name: String,
+    /// Fingerprint of the new key
+    pub key_fingerprint: String,
+    /// Recipients the new key was wrapped for
+    pub rewrapped: Vec<String>,
+    /// Recipients without a `.pub` file, who lost access
+    pub skipped: Vec<String>,
+}
+
+/// What `remove_team_member` / `revoke_env_access` did, for printing a summary
+#[derive(Debug, Clone)]
+pub struct RevocationSummary {
+    pub alias: String,
+    /// Every keyring the alias was removed from
+    pub keyrings: Vec<KeyringUpdate>,
+    /// Files re-sealed with a new key
+    pub resealed: Vec<String>,
+    /// History snapshots the member's key files were removed from
+    pub purged_snapshots: usize,
+    /// Short hash of the revocation commit, if anything changed
+    pub commit: Option<String>,
+}
+
+/// What `init_env_key` did
+#[derive(Debug, Clone)]
+pub struct EnvKeyInit {
+    pub keyring: KeyringUpdate,
+    /// Files of the environment re-sealed with its new key
+    pub resealed: Vec<String>,
+    /// Short hash of the commit, if anything changed
+    pub commit: Option<String>,
+}
+
+/// What `rotate_team` did, for printing a summary
+#[derive(Debug, Clone, Default)]
+pub struct TeamRotation {
+    pub team: String,
+    /// Repos re-wrapped for the new team key: (repo, keyrings, commit)
+    pub rewrapped: Vec<(PathBuf, Vec<String>, Option<String>)>,
+    /// Repos that couldn't be re-wrapped, with the reason
+    pub unreachable: Vec<(PathBuf, String)>,
+    /// Invites issued with the new key
+    pub invites: Vec<PathBuf>,
+    /// Pending invites for the old key that were deleted
+    pub revoked_invites: usize,
+    /// Members were dropped, so the repo and environment keys were rotated too
+    pub rekeyed: bool,
+    /// Files re-sealed with the new keys, across all repos
+    pub resealed: usize,
+}
+
+/// Contents of a team invite, encrypted for the invited user
+#[derive(serde::Serialize, serde::Deserialize, Zeroize, ZeroizeOnDrop)]
+struct TeamInvite {
+    version: u8,
+    team: String,
+    /// Public key the invite was issued to
+    recipient: String,
+    /// Random id; an invite is refused once its nonce has been accepted
+    nonce: String,
+    created_at: String,
+    expires_at: String,
+    /// The team identity (AGE-SECRET-KEY-1...)
+    team_key: String,
+}
+
+/// Key files checked against the signed membership manifest
+#[derive(Debug, Clone, Default)]
+pub struct KeyringAudit {
+    /// Entries in the manifest
+    pub entries: usize,
+    /// Recipients per keyring ("repo" or the environment name)
+    pub members: Vec<(String, Vec<String>)>,
+    /// Key files (`<keyring>/<alias>`) the manifest has no record of
+    pub unaccounted: Vec<String>,
+    /// `.pub` files (`<keyring>/<alias>`) whose key differs from the recorded one
+    pub mismatched: Vec<String>,
+    /// The manifest no longer starts with the entry this clone first saw
+    pub genesis_changed: bool,
+}
+
+impl KeyringAudit {
+    pub fn is_clean(&self) -> bool {
+        self.unaccounted.is_empty() && self.mismatched.is_empty() && !self.genesis_changed
+    }
+
+    /// One line per problem, for warnings
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems: Vec<String> = self
+            .unaccounted
+            .iter()
+            .map(|k| format!("{} is not in the key manifest", k))
+            .collect();
+        problems.extend(
+            self.mismatched
+                .iter()
+                .map(|k| format!("{}.pub differs from the key recorded in the manifest", k)),
+        );
+        if self.genesis_changed {
+            problems.push(
+                "the key manifest was replaced since this clone first verified it".to_string(),
+            );
+        }
+        problems
+    }
+}
+
+/// A public key the repo key can be wrapped for: an age key (`age1...`) or an SSH key
+/// (`ssh-ed25519 AAAA...` / `ssh-rsa AAAA...`)
+#[derive(Clone)]
+pub enum RecipientKey {
+    Age(x25519::Recipient),
+    Ssh(age::ssh::Recipient),
+}
+
+impl RecipientKey {
+    pub fn as_recipient(&self) -> &dyn age::Recipient {
+        match self {
+            RecipientKey::Age(r) => r,
+            RecipientKey::Ssh(r) => r,
+        }
+    }
+
+    /// Filename-safe short id, used for `machine:<id>` aliases
+    pub fn short_id(&self) -> String {
+        match self {
+            RecipientKey::Age(r) => r.to_string().replace(':', "_").chars().take(12).collect(),
+            RecipientKey::Ssh(r) => {
+                use sha2::Digest;
+                let digest = Sha256::digest(r.to_string().as_bytes());
+                format!("ssh-{}", &hex::encode(digest)[..8])
+            }
+        }
+    }
+}
+
+/// Accepts an age public key, an SSH public key, or a whole `authorized_keys` line
+/// (leading options and the trailing comment are dropped).
+impl std::str::FromStr for RecipientKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s.starts_with("age1") {
+            return s
+                .parse()
+                .map(RecipientKey::Age)
+                .map_err(|e| anyhow::anyhow!("Invalid age public key: {}", e));
+        }
+
+        let tokens: Vec<&str> = s.split_whitespace().collect();
+        let key_type = tokens
+            .iter()
+            .position(|t| t.starts_with("ssh-") || t.starts_with("ecdsa-") || t.starts_with("sk-"))
+            .ok_or_else(|| anyhow::anyhow!("Not an age or SSH public key"))?;
+        let key = match tokens.get(key_type + 1) {
+            Some(body) => format!("{} {}", tokens[key_type], body),
+            None => return Err(anyhow::anyhow!("SSH public key has no key data")),
+        };
+
+        use age::ssh::ParseRecipientKeyError;
+        key.parse().map(RecipientKey::Ssh).map_err(|e| match e {
+            ParseRecipientKeyError::Unsupported(t) => anyhow::anyhow!(
+                "Unsupported SSH key type '{}'; use ssh-ed25519 or ssh-rsa",
+                t
+            ),
+            ParseRecipientKeyError::RsaModulusTooSmall => {
+                anyhow::anyhow!("SSH RSA key is too small (need at least 2048 bits)")
+            }
+            ParseRecipientKeyError::RsaModulusTooLarge => {
+                anyhow::anyhow!("SSH RSA key is too large")
+            }
+            _ => anyhow::anyhow!("Invalid SSH public key"),
+        })
+    }
+}
+
+/// Canonical form, as stored in `<alias>.pub` (no options or comment)
+impl std::fmt::Display for RecipientKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RecipientKey::Age(r) => write!(f, "{}", r),
+            RecipientKey::Ssh(r) => write!(f, "{}", r),
+        }
+    }
+}
+
+/// The identity this machine unlocks keys with: `~/.arcane/identity.age`, or an
+/// SSH key from `~/.ssh` when there is no age identity
+#[derive(Clone)]
+pub enum MasterIdentity {
+    Age(x25519::Identity),
+    Ssh {
+        identity: age::ssh::Identity,
+        recipient: Box<age::ssh::Recipient>,
+    },
+}
+
+impl MasterIdentity {
+    pub fn to_public(&self) -> RecipientKey {
+        match self {
+            MasterIdentity::Age(id) => RecipientKey::Age(id.to_public()),
+            MasterIdentity::Ssh { recipient, .. } => RecipientKey::Ssh((**recipient).clone()),
+        }
+    }
+
+    pub fn as_identity(&self) -> &dyn age::Identity {
+        match self {
+            MasterIdentity::Age(id) => id,
+            MasterIdentity::Ssh { identity, .. } => identity,
+        }
+    }
+}
+
+/// How the secret key in `~/.arcane/identity.age` is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdentityStorage {
+    /// The bare `AGE-SECRET-KEY-...` line
+    Plaintext,
+    /// Encrypted with a passphrase (age scrypt, ASCII-armored)
+    Passphrase,
+    /// Kept in the OS keyring (Secret Service, macOS Keychain, Windows Credential Manager)
+    Keyring,
+}
+
+impl IdentityStorage {
+    fn of(content: &str) -> Self {
+        match identity_body(content).as_str() {
+            b if b.starts_with("-----BEGIN AGE ENCRYPTED FILE-----") => IdentityStorage::Passphrase,
+            IDENTITY_KEYRING_MARKER => IdentityStorage::Keyring,
+            _ => IdentityStorage::Plaintext,
+        }
+    }
+}
+
+impl std::fmt::Display for IdentityStorage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            IdentityStorage::Plaintext => write!(f, "plaintext"),
+            IdentityStorage::Passphrase => write!(f, "passphrase"),
+            IdentityStorage::Keyring => write!(f, "OS keyring"),
+        }
+    }
 }
 
 pub struct ArcaneSecurity {
-    master_identity: Option<x25519::Identity>,
+    /// Loaded on first use, so commands that never touch keys don't prompt for a passphrase
+    master_identity: std::sync::OnceLock<Option<MasterIdentity>>,
     imported_identities: Vec<x25519::Identity>,
     #[allow(dead_code)]
     repo_keys: std::collections::HashMap<PathBuf, RepoKey>,
@@ -346,19 +1257,13 @@ impl ArcaneSecurity {
 
     pub fn new(repo_path: Option<&Path>) -> Result<Self> {
         let mut security = Self {
-            master_identity: None,
+            master_identity: std::sync::OnceLock::new(),
             imported_identities: Vec::new(),
             repo_keys: std::collections::HashMap::new(),
             scanner: SecretScanner::new(),
             repo_root: repo_path.map(|p| p.to_path_buf()),
         };
 
-        let idx = match security.load_master_identity() {
-            Ok(id) => Some(id),
-            Err(_) => None,
-        };
-        security.master_identity = idx;
-
         // Load imported legacy keys
         if let Ok(imported) = security.load_imported_identities() {
             if !imported.is_empty() {
@@ -370,9 +1275,66 @@ impl ArcaneSecurity {
         Ok(security)
     }
 
+    /// Like `new`, but keys are unlocked with `identity` instead of the one in `~/.arcane`
+    pub fn with_identity(repo_path: Option<&Path>, identity: MasterIdentity) -> Result<Self> {
+        let security = Self::new(repo_path)?;
+        let _ = security.master_identity.set(Some(identity));
+        Ok(security)
+    }
+
+    /// The master identity, loaded (and unlocked) the first time it is needed.
+    /// A running agent's copy wins, so a passphrase is asked for once, not per file.
+    fn master(&self) -> Option<&MasterIdentity> {
+        self.master_identity
+            .get_or_init(|| {
+                if let Some(secret) = crate::agent::get_identity() {
+                    use std::str::FromStr;
+                    if let Ok(id) = x25519::Identity::from_str(secret.trim()) {
+                        return Some(MasterIdentity::Age(id));
+                    }
+                }
+                self.load_master_identity_or_warn()
+            })
+            .as_ref()
+    }
+
+    fn load_master_identity_or_warn(&self) -> Option<MasterIdentity> {
+        match self.load_master_identity() {
+            Ok(id) => Some(id),
+            Err(e) => {
+                // A key that exists but can't be used (wrong passphrase, locked SSH
+                // key) would otherwise only show up as "Access Denied" from the filters
+                if e.to_string() != IDENTITY_NOT_FOUND {
+                    eprintln!("⚠️  {}", e);
+                }
+                None
+            }
+        }
+    }
+
+    /// Key for a keyring from the agent, or unlocked with `unlock` and handed to the
+    /// agent for next time. Without an agent this is just `unlock()`.
+    fn cached_key(
+        &self,
+        label: Option<String>,
+        unlock: impl FnOnce() -> Result<RepoKey>,
+    ) -> Result<RepoKey> {
+        if let Some(label) = &label {
+            if let Some(key) = crate::agent::get_key(label) {
+                return Ok(RepoKey(key.to_vec()));
+            }
+        }
+        let key = unlock()?;
+        if let Some(label) = &label {
+            crate::agent::put_key(label, &key.0);
+            crate::agent::put_key(&fingerprint_cache_label(&key.fingerprint()), &key.0);
+        }
+        Ok(key)
+    }
+
     /// Load generic identities from ~/.arcane/keys/*.age (e.g. Git Seal keys)
     fn load_imported_identities(&self) -> Result<Vec<x25519::Identity>> {
-        let home = dirs::home_dir().context("Could not find home directory")?;
+        let home = home_dir()?;
         let keys_dir = home.join(".arcane").join("keys");
         let mut identities = Vec::new();
 
@@ -400,52 +1362,252 @@ impl ArcaneSecurity {
         Ok(identities)
     }
 
-    /// Load the Master Identity from ~/.arcane/identity.age
-    pub fn load_master_identity(&self) -> Result<x25519::Identity> {
-        let home = dirs::home_dir().context("Could not find home directory")?;
+    /// Load the Master Identity from ~/.arcane/identity.age, falling back to an
+    /// SSH key (~/.ssh/id_ed25519, then ~/.ssh/id_rsa) if there is none
+    pub fn load_master_identity(&self) -> Result<MasterIdentity> {
+        let home = home_dir()?;
         let identity_path = home.join(".arcane").join("identity.age");
 
         if !identity_path.exists() {
-            return Err(anyhow::anyhow!("Identity file not found"));
+            for name in ["id_ed25519", "id_rsa"] {
+                let ssh_path = home.join(".ssh").join(name);
+                if ssh_path.exists() {
+                    return Self::load_ssh_identity(&ssh_path);
+                }
+            }
+            return Err(anyhow::anyhow!(IDENTITY_NOT_FOUND));
         }
 
         let content = fs::read_to_string(&identity_path)?;
-        // Assuming the file contains the Bech32 secret key string (AGE-SECRET-KEY-...)
-        // potentially surrounded by whitespace or comments
-        let key_str = content
-            .lines()
-            .find(|l| !l.starts_with('#') && !l.trim().is_empty())
-            .ok_or_else(|| anyhow::anyhow!("No key found in identity file"))?
-            .trim();
+        Self::decode_identity(&content, &|| {
+            read_passphrase("Passphrase for ~/.arcane/identity.age: ", false)
+        })
+        .map(MasterIdentity::Age)
+    }
+
+    /// Parse the contents of `identity.age`: a bare secret key, a passphrase-encrypted
+    /// one (asking `passphrase` for it), or a pointer to the OS keyring
+    pub(crate) fn decode_identity(
+        content: &str,
+        passphrase: &dyn Fn() -> Result<SecretString>,
+    ) -> Result<x25519::Identity> {
+        let mut secret = match IdentityStorage::of(content) {
+            IdentityStorage::Plaintext => identity_body(content),
+            IdentityStorage::Passphrase => {
+                let body = identity_body(content);
+                let decryptor =
+                    age::Decryptor::new(age::armor::ArmoredReader::new(body.as_bytes()))?;
+                let identity = age::scrypt::Identity::new(passphrase()?);
+                let mut reader = decryptor
+                    .decrypt(std::iter::once(&identity as &dyn age::Identity))
+                    .map_err(|_| anyhow::anyhow!("Wrong passphrase for identity.age"))?;
+                let mut secret = String::new();
+                reader.read_to_string(&mut secret)?;
+                secret
+            }
+            IdentityStorage::Keyring => keyring_entry()?
+                .get_password()
+                .map_err(|e| anyhow::anyhow!("Failed to read identity from OS keyring: {}", e))?,
+        };
 
         use std::str::FromStr;
-        x25519::Identity::from_str(key_str)
-            .map_err(|e| anyhow::anyhow!("Failed to parse identity: {}", e))
+        let parsed = x25519::Identity::from_str(secret.trim())
+            .map_err(|e| anyhow::anyhow!("Failed to parse identity: {}", e));
+        secret.zeroize();
+        parsed
+    }
+
+    /// Render `identity.age` for a given storage. The public key goes in a comment so
+    /// `identity show` never needs the passphrase. For `Keyring`, the caller stores the
+    /// secret itself.
+    pub(crate) fn encode_identity(
+        identity: &x25519::Identity,
+        storage: IdentityStorage,
+        passphrase: Option<SecretString>,
+    ) -> Result<String> {
+        let secret = identity.to_string();
+        let body = match storage {
+            IdentityStorage::Plaintext => secret.expose_secret().to_string(),
+            IdentityStorage::Passphrase => {
+                let passphrase =
+                    passphrase.ok_or_else(|| anyhow::anyhow!("A passphrase is required"))?;
+                let encryptor = age::Encryptor::with_user_passphrase(passphrase);
+                let mut armored = vec![];
+                let armor = age::armor::ArmoredWriter::wrap_output(
+                    &mut armored,
+                    age::armor::Format::AsciiArmor,
+                )?;
+                let mut writer = encryptor.wrap_output(armor)?;
+                writer.write_all(secret.expose_secret().as_bytes())?;
+                writer.finish()?.finish()?;
+                String::from_utf8(armored)?.trim_end().to_string()
+            }
+            IdentityStorage::Keyring => IDENTITY_KEYRING_MARKER.to_string(),
+        };
+
+        Ok(format!(
+            "# created: {}\n# public key: {}\n{}\n",
+            chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
+            identity.to_public(),
+            body
+        ))
+    }
+
+    /// How the current `identity.age` is stored, if there is one
+    pub fn identity_storage() -> Result<Option<IdentityStorage>> {
+        let path = Self::master_identity_path()?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(IdentityStorage::of(&fs::read_to_string(path)?)))
+    }
+
+    /// Public key of the master identity. Read from the `# public key:` comment when
+    /// there is one, so it never prompts for a passphrase.
+    pub fn master_public_key(&self) -> Result<String> {
+        let path = Self::master_identity_path()?;
+        if let Ok(content) = fs::read_to_string(&path) {
+            if let Some(pk) = content
+                .lines()
+                .find_map(|l| l.strip_prefix("# public key:"))
+            {
+                return Ok(pk.trim().to_string());
+            }
+        }
+        self.master()
+            .map(|id| id.to_public().to_string())
+            .ok_or_else(|| anyhow::anyhow!(IDENTITY_NOT_FOUND))
+    }
+
+    fn master_identity_path() -> Result<PathBuf> {
+        let home = home_dir()?;
+        Ok(home.join(".arcane").join("identity.age"))
+    }
+
+    /// Load an OpenSSH private key. Passphrase-protected keys are decrypted once with
+    /// ARCANE_SSH_PASSPHRASE, since git filters have no terminal to prompt on.
+    pub fn load_ssh_identity(path: &Path) -> Result<MasterIdentity> {
+        let file = fs::File::open(path)?;
+        let filename = path.to_string_lossy().to_string();
+        let identity =
+            age::ssh::Identity::from_buffer(std::io::BufReader::new(file), Some(filename))
+                .with_context(|| format!("Failed to read SSH key {:?}", path))?;
+
+        let recipient = age::ssh::Recipient::try_from(identity.clone())
+            .map_err(|_| anyhow::anyhow!("Unsupported SSH key {:?}; use ed25519 or RSA", path))?;
+
+        let identity = match identity {
+            age::ssh::Identity::Unencrypted(_) => identity,
+            age::ssh::Identity::Encrypted(key) => {
+                let passphrase = std::env::var("ARCANE_SSH_PASSPHRASE").map_err(|_| {
+                    anyhow::anyhow!(
+                        "SSH key {:?} is passphrase-protected; set ARCANE_SSH_PASSPHRASE",
+                        path
+                    )
+                })?;
+                key.decrypt(passphrase.into())
+                    .map_err(|e| anyhow::anyhow!("Failed to decrypt SSH key {:?}: {}", path, e))?
+                    .into()
+            }
+            age::ssh::Identity::Unsupported(_) => {
+                return Err(anyhow::anyhow!(
+                    "Unsupported SSH key {:?}; use ed25519 or RSA",
+                    path
+                ))
+            }
+        };
+
+        Ok(MasterIdentity::Ssh {
+            identity,
+            recipient: Box::new(recipient),
+        })
     }
 
     pub fn has_master_identity(&self) -> bool {
-        self.master_identity.is_some()
+        self.master().is_some()
     }
 
     /// Explicitly generate and save a new Master Identity
     pub fn generate_master_identity(&mut self) -> Result<()> {
-        let home = dirs::home_dir().context("Could not find home directory")?;
-        let identity_path = home.join(".arcane").join("identity.age");
+        self.create_master_identity(IdentityStorage::Plaintext)
+            .map(|_| ())
+    }
 
+    /// Generate a new Master Identity stored the given way. Returns its public key.
+    pub fn create_master_identity(&mut self, storage: IdentityStorage) -> Result<String> {
+        let identity_path = Self::master_identity_path()?;
         if identity_path.exists() {
             return Err(anyhow::anyhow!("Identity already exists"));
         }
 
-        // Generate new identity
         let key = x25519::Identity::generate();
-        if let Some(parent) = identity_path.parent() {
-            fs::create_dir_all(parent)?;
+        Self::write_master_identity(&identity_path, &key, storage)?;
+
+        let public_key = key.to_public().to_string();
+        self.master_identity = std::sync::OnceLock::from(Some(MasterIdentity::Age(key)));
+        Ok(public_key)
+    }
+
+    /// Re-wrap the existing identity.age with a different storage (e.g. protect a
+    /// plaintext identity with a passphrase). The key itself doesn't change, so no
+    /// repo needs re-keying. Returns the previous storage.
+    pub fn migrate_master_identity(&mut self, storage: IdentityStorage) -> Result<IdentityStorage> {
+        let identity_path = Self::master_identity_path()?;
+        let previous = Self::identity_storage()?.ok_or_else(|| {
+            anyhow::anyhow!("No identity found. Run 'arcane identity new' first.")
+        })?;
+        if previous == storage {
+            return Err(anyhow::anyhow!("Identity is already stored as {}", storage));
+        }
+
+        let key = match self.load_master_identity()? {
+            MasterIdentity::Age(key) => key,
+            MasterIdentity::Ssh { .. } => {
+                return Err(anyhow::anyhow!(
+                    "~/.arcane/identity.age is gone (only an SSH key was found), nothing to migrate"
+                ))
+            }
+        };
+        Self::write_master_identity(&identity_path, &key, storage)?;
+
+        // The old copy in the keyring is no longer referenced
+        if previous == IdentityStorage::Keyring {
+            let _ = keyring_entry()?.delete_credential();
         }
 
-        let mut file = fs::File::create(&identity_path)?;
-        writeln!(file, "{}", key.to_string().expose_secret())?;
+        self.master_identity = std::sync::OnceLock::from(Some(MasterIdentity::Age(key)));
+        Ok(previous)
+    }
+
+    fn write_master_identity(
+        path: &Path,
+        key: &x25519::Identity,
+        storage: IdentityStorage,
+    ) -> Result<()> {
+        let passphrase = match storage {
+            IdentityStorage::Passphrase => Some(read_passphrase("New passphrase: ", true)?),
+            _ => None,
+        };
+        let content = Self::encode_identity(key, storage, passphrase)?;
+
+        if storage == IdentityStorage::Keyring {
+            keyring_entry()?
+                .set_password(key.to_string().expose_secret())
+                .map_err(|e| anyhow::anyhow!("Failed to store identity in OS keyring: {}", e))?;
+        }
 
-        self.master_identity = Some(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Write next to it and rename, so a failure never leaves half an identity
+        let tmp = path.with_extension("age.tmp");
+        fs::write(&tmp, content)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&tmp, fs::Permissions::from_mode(0o600))?;
+        }
+        fs::rename(&tmp, path)?;
         Ok(())
     }
 
@@ -457,9 +1619,84 @@ impl ArcaneSecurity {
         Self::find_repo_root()
     }
 
+    /// Local keyring inside .git (never pushed; the original layout)
+    pub fn local_keys_dir(repo_root: &Path) -> PathBuf {
+        repo_root.join(".git").join("arcane").join("keys")
+    }
+
+    /// Tracked keyring committed with the repo, so a fresh clone can unlock itself.
+    /// It only holds the repo key wrapped for each recipient, never plaintext keys.
+    pub fn tracked_keys_dir(repo_root: &Path) -> PathBuf {
+        repo_root.join(TRACKED_KEYS_DIR)
+    }
+
+    /// Keyring in use for a repo: `.arcane/keys` if it exists, else `.git/arcane/keys`
+    pub fn keys_dir_for(repo_root: &Path) -> PathBuf {
+        let tracked = Self::tracked_keys_dir(repo_root);
+        if tracked.is_dir() {
+            tracked
+        } else {
+            Self::local_keys_dir(repo_root)
+        }
+    }
+
+    /// Keyring in use for the current repo
+    pub fn keys_dir(&self) -> Result<PathBuf> {
+        Ok(Self::keys_dir_for(&self.get_repo_root()?))
+    }
+
+    /// Move the local `.git/arcane/keys` keyring (including history) to the tracked
+    /// `.arcane/keys` directory and make sure .gitignore doesn't hide it.
+    /// Returns the new keyring directory; the caller commits it.
+    pub fn migrate_keys_to_tracked(&self) -> Result<PathBuf> {
+        let repo_root = self.get_repo_root()?;
+        let local = Self::local_keys_dir(&repo_root);
+        let tracked = Self::tracked_keys_dir(&repo_root);
+
+        if !local.is_dir() || local.read_dir()?.next().is_none() {
+            return Err(anyhow::anyhow!(
+                "No local keyring at {:?}. Run 'arcane init' first.",
+                local
+            ));
+        }
+        if tracked.is_dir() && tracked.read_dir()?.next().is_some() {
+            return Err(anyhow::anyhow!(
+                "Tracked keyring {:?} already exists and is not empty",
+                tracked
+            ));
+        }
+
+        // Older .gitignore files ignored the keyring; lift that so it can be committed
+        let auto_ignore = AutoGitIgnore::new(&repo_root);
+        auto_ignore.ensure_tracked(&[".arcane/keys/", ".arcane/keys"])?;
+
+        let still_ignored = std::process::Command::new("git")
+            .args(["check-ignore", "-q", "--no-index", TRACKED_KEYS_DIR])
+            .current_dir(&repo_root)
+            .status()
+            .map(|s| s.success())
+            .unwrap_or(false);
+        if still_ignored {
+            return Err(anyhow::anyhow!(
+                "{} is still ignored by a broader .gitignore rule; un-ignore it and retry",
+                TRACKED_KEYS_DIR
+            ));
+        }
+
+        copy_dir_recursive(&local, &tracked)?;
+        fs::remove_dir_all(&local)?;
+
+        Ok(tracked)
+    }
+
     /// Find the git repository root from the current directory
     pub fn find_repo_root() -> Result<PathBuf> {
-        let mut current = std::env::current_dir()?;
+        Self::find_repo_root_from(&std::env::current_dir()?)
+    }
+
+    /// Find the git repository root containing `dir`
+    pub fn find_repo_root_from(dir: &Path) -> Result<PathBuf> {
+        let mut current = dir.to_path_buf();
         loop {
             if current.join(".git").exists() {
                 return Ok(current);
@@ -470,14 +1707,24 @@ impl ArcaneSecurity {
         }
     }
 
-    /// Load the repo key from .git/arcane/keys/*.age or history
+    /// Load the repo key, from the agent when it holds this keyring's current key
+    pub fn load_repo_key(&self) -> Result<RepoKey> {
+        let label = self
+            .get_repo_root()
+            .ok()
+            .and_then(|root| keyring_cache_label(&Self::keys_dir_for(&root)));
+        self.cached_key(label, || self.unlock_repo_key())
+    }
+
+    /// Unlock the current repo key from .git/arcane/keys/*.age
     /// hierarchy:
     /// 1. Direct User Key: keys/<user>.age
     /// 2. Team Key: keys/team:<team>.age (decrypted via ~/.arcane/teams/<team>.key)
     /// 3. Machine Key: keys/machine:<hash>.age (decrypted via env var ARCANE_MACHINE_KEY)
-    pub fn load_repo_key(&self) -> Result<RepoKey> {
+    fn unlock_repo_key(&self) -> Result<RepoKey> {
         let repo_root = self.get_repo_root()?;
-        let keys_dir = repo_root.join(".git").join("arcane").join("keys");
+        let keys_dir = Self::keys_dir_for(&repo_root);
+        Self::warn_on_keyring_audit(&repo_root);
 
         if !keys_dir.exists() {
             // Fallback logic for legacy/uninit
@@ -500,11 +1747,11 @@ impl ArcaneSecurity {
         }
 
         // Check for Master Identity (Interactve User)
-        let identity_opt = self.master_identity.as_ref();
+        let identity_opt = self.master();
 
         if let Some(identity) = identity_opt {
             // 1. Try direct User access (keys/*.age)
-            if let Ok(key) = self.try_decrypt_directory(&keys_dir, identity) {
+            if let Ok(key) = self.try_decrypt_directory(&keys_dir, identity.as_identity()) {
                 return Ok(key);
             }
 
@@ -517,40 +1764,13 @@ impl ArcaneSecurity {
             }
 
             // 2. Try Team access (keys/team:*.age)
-            for entry in fs::read_dir(&keys_dir)? {
-                let entry = entry?;
-                let path = entry.path();
-                if let Some(filename) = path.file_name().and_then(|s| s.to_str()) {
-                    if filename.starts_with("team:") && filename.ends_with(".age") {
-                        let team_name = filename
-                            .trim_start_matches("team:")
-                            .trim_end_matches(".age");
-
-                        if let Ok(team_key) = self.load_team_key(team_name) {
-                            if let Ok(repo_key) =
-                                self.decrypt_repo_key_with_team_key(&path, &team_key)
-                            {
-                                return Ok(repo_key);
-                            }
-                        }
-                    }
-                }
+            if let Ok(key) = self.try_decrypt_team_wraps(&keys_dir) {
+                return Ok(key);
             }
 
-            // 3. Try history keys (latest to oldest)
-            let history_dir = keys_dir.join("history");
-            if history_dir.exists() {
-                let mut entries: Vec<_> =
-                    fs::read_dir(history_dir)?.filter_map(|e| e.ok()).collect();
-                entries.sort_by_key(|e| std::cmp::Reverse(e.file_name()));
-                for entry in entries {
-                    if entry.path().is_dir() {
-                        if let Ok(key) = self.try_decrypt_directory(&entry.path(), identity) {
-                            return Ok(key);
-                        }
-                    }
-                }
-            }
+            // History snapshots are not tried: an old key would seal new content for
+            // whoever was removed since. They only open blobs sealed with them, through
+            // `load_repo_key_by_fingerprint`.
         }
 
         // 4. Last Resort: Legacy repo.key
@@ -564,14 +1784,179 @@ impl ArcaneSecurity {
         ))
     }
 
+    /// Load the key a sealed blob was encrypted with.
+    /// Uses the key fingerprint in the header to pick the current key or the matching
+    /// `keys/history/<timestamp>` snapshot. Headerless (legacy) blobs get the current key.
+    pub fn load_repo_key_for_blob(&self, data: &[u8]) -> Result<RepoKey> {
+        match SealHeader::parse(data)? {
+            Some(header) => self.load_repo_key_by_fingerprint(&header.key_fingerprint),
+            None => self.load_repo_key(),
+        }
+    }
+
+    /// Load the current or a historical repo key by its fingerprint
+    pub fn load_repo_key_by_fingerprint(
+        &self,
+        fingerprint: &[u8; KEY_FINGERPRINT_LEN],
+    ) -> Result<RepoKey> {
+        let label = fingerprint_cache_label(fingerprint);
+        if let Some(key) = crate::agent::get_key(&label) {
+            return Ok(RepoKey(key.to_vec()));
+        }
+
+        if let Ok(key) = self.load_repo_key() {
+            if &key.fingerprint() == fingerprint {
+                return Ok(key);
+            }
+        }
+
+        let repo_root = self.get_repo_root()?;
+        let mut keyrings = vec![Self::keys_dir_for(&repo_root)];
+        for env in self.list_env_keyrings().unwrap_or_default() {
+            keyrings.push(Self::env_keys_dir_for(&repo_root, &env));
+        }
+        for dir in keyrings {
+            if let Some(key) = self.find_key_in_keyring(&dir, fingerprint) {
+                crate::agent::put_key(&label, &key.0);
+                return Ok(key);
+            }
+        }
+
+        let wanted = hex::encode(fingerprint);
+        if let Some(env) = self.env_for_fingerprint(fingerprint) {
+            return Err(anyhow::anyhow!(
+                "No access to '{}' secrets (key {}); ask a recipient to run 'arcane env grant'",
+                env,
+                wanted
+            ));
+        }
+        Err(anyhow::anyhow!(
+            "No accessible repo key matches fingerprint {}",
+            wanted
+        ))
+    }
+
+    /// Search a keyring and its history snapshots (newest first) for the key with
+    /// `fingerprint`
+    fn find_key_in_keyring(
+        &self,
+        dir: &Path,
+        fingerprint: &[u8; KEY_FINGERPRINT_LEN],
+    ) -> Option<RepoKey> {
+        let wanted = hex::encode(fingerprint);
+        let mut candidates = vec![dir.to_path_buf()];
+        if let Ok(entries) = fs::read_dir(dir.join("history")) {
+            let mut snapshots: Vec<PathBuf> = entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.is_dir())
+                .collect();
+            snapshots.sort_by(|a, b| b.cmp(a));
+            candidates.extend(snapshots);
+        }
+
+        for candidate in candidates {
+            // Snapshots and env keyrings record their fingerprint,
+            // so only the matching one needs to be decrypted.
+            if let Ok(recorded) = fs::read_to_string(candidate.join(KEY_FINGERPRINT_FILE)) {
+                if recorded.trim() != wanted {
+                    continue;
+                }
+            }
+            if let Ok(key) = self.try_unlock_directory(&candidate) {
+                if &key.fingerprint() == fingerprint {
+                    return Some(key);
+                }
+            }
+        }
+        None
+    }
+
+    /// The environment whose keyring (current or history) holds `fingerprint`.
+    /// Only needs the recorded fingerprints, so it works without access.
+    pub fn env_for_fingerprint(&self, fingerprint: &[u8; KEY_FINGERPRINT_LEN]) -> Option<String> {
+        let wanted = hex::encode(fingerprint);
+        let repo_root = self.get_repo_root().ok()?;
+        let records = |dir: &Path| {
+            fs::read_to_string(dir.join(KEY_FINGERPRINT_FILE))
+                .map(|r| r.trim() == wanted)
+                .unwrap_or(false)
+        };
+
+        self.list_env_keyrings()
+            .unwrap_or_default()
+            .into_iter()
+            .find(|env| {
+                let dir = Self::env_keys_dir_for(&repo_root, env);
+                records(&dir)
+                    || fs::read_dir(dir.join("history"))
+                        .map(|entries| entries.filter_map(|e| e.ok()).any(|e| records(&e.path())))
+                        .unwrap_or(false)
+            })
+    }
+
+    /// Try every identity we hold (master, imported, machine) against a key directory
+    fn try_unlock_directory(&self, dir: &Path) -> Result<RepoKey> {
+        if let Ok(machine_key_str) = std::env::var("ARCANE_MACHINE_KEY") {
+            use std::str::FromStr;
+            if let Ok(machine_identity) = x25519::Identity::from_str(&machine_key_str) {
+                if let Ok(key) = self.try_decrypt_directory(dir, &machine_identity) {
+                    return Ok(key);
+                }
+            }
+        }
+
+        let identities = self.master().into_iter().map(|id| id.as_identity()).chain(
+            self.imported_identities
+                .iter()
+                .map(|id| id as &dyn age::Identity),
+        );
+        for identity in identities {
+            if let Ok(key) = self.try_decrypt_directory(dir, identity) {
+                return Ok(key);
+            }
+        }
+
+        if let Ok(key) = self.try_decrypt_team_wraps(dir) {
+            return Ok(key);
+        }
+
+        Err(anyhow::anyhow!("No decryptable key in {:?}", dir))
+    }
+
+    /// Try the `team:<name>.age` wraps in a key directory with the team keys we hold
+    fn try_decrypt_team_wraps(&self, dir: &Path) -> Result<RepoKey> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if let Some(filename) = path.file_name().and_then(|s| s.to_str()) {
+                if filename.starts_with("team:") && filename.ends_with(".age") {
+                    let team_name = filename
+                        .trim_start_matches("team:")
+                        .trim_end_matches(".age");
+
+                    if let Ok(team_key) = self.load_team_key(team_name) {
+                        if let Ok(repo_key) = self.decrypt_repo_key_with_team_key(&path, &team_key)
+                        {
+                            return Ok(repo_key);
+                        }
+                    }
+                }
+            }
+        }
+        Err(anyhow::anyhow!("No team key unlocks {:?}", dir))
+    }
+
     /// Authorize a new recipient (Machine or User) to access this repository
     pub fn authorize_recipient(&self, recipient: &age::x25519::Recipient) -> Result<()> {
         let repo_key = self.load_repo_key()?;
         let repo_root = self.get_repo_root()?;
-        let keys_dir = repo_root.join(".git").join("arcane").join("keys");
+        let keys_dir = Self::keys_dir_for(&repo_root);
         std::fs::create_dir_all(&keys_dir)?;
 
         let output_path = keys_dir.join(format!("{}.age", recipient));
+        let alias = recipient.to_string();
+        self.record_membership(ManifestOp::Add, None, &alias, &alias)?;
 
         // Encrypt the repo key for the recipient
         let recipients: Vec<Box<dyn age::Recipient + Send>> = vec![Box::new(recipient.clone())];
@@ -628,7 +2013,7 @@ impl ArcaneSecurity {
 
     /// Authorize a Machine (Public Key) to access this repo
     pub fn whitelist_machine(&self, public_key_str: &str) -> Result<()> {
-        let recipient: x25519::Recipient = public_key_str
+        let recipient: RecipientKey = public_key_str
             .parse()
             .map_err(|e| anyhow::anyhow!("Invalid machine public key: {}", e))?;
 
@@ -637,26 +2022,31 @@ impl ArcaneSecurity {
             .context("Must have access to repo to whitelist machines")?;
 
         let repo_root = self.get_repo_root()?;
-        let keys_dir = repo_root.join(".git").join("arcane").join("keys");
-
-        // Use hash or similar ID for filename
-        // To keep it clean, maybe just first 16 chars of pubkey?
-        // Pubkey is "age1..." (Bech32).
-        let safe_name = public_key_str
-            .replace(":", "_")
-            .chars()
-            .take(12)
-            .collect::<String>();
-        let machine_file = keys_dir.join(format!("machine:{}.age", safe_name));
+        let keys_dir = Self::keys_dir_for(&repo_root);
 
-        self.encrypt_and_save_key(&repo_key, &recipient, &machine_file)?;
+        // Use a short ID for the filename ("age1..." prefix, or a hash for SSH keys)
+        let safe_name = recipient.short_id();
+        let machine_file = keys_dir.join(format!("machine:{}.age", safe_name));
+        self.record_membership(
+            ManifestOp::Add,
+            None,
+            &format!("machine:{}", safe_name),
+            &recipient.to_string(),
+        )?;
+
+        // Keep the public key so rotation can re-wrap for this machine
+        fs::write(
+            keys_dir.join(format!("machine:{}.pub", safe_name)),
+            recipient.to_string(),
+        )?;
+        self.encrypt_and_save_key(&repo_key, recipient.as_recipient(), &machine_file)?;
 
         Ok(())
     }
 
     /// Load a Team Key from ~/.arcane/teams/<name>.key
     pub fn load_team_key(&self, team_name: &str) -> Result<TeamKey> {
-        let home = dirs::home_dir().context("Could not find home directory")?;
+        let home = home_dir()?;
         let team_key_path = home
             .join(".arcane")
             .join("teams")
@@ -671,22 +2061,21 @@ impl ArcaneSecurity {
 
         // Team keys are encrypted with Master Identity
         let identity = self
-            .master_identity
-            .as_ref()
+            .master()
             .context("Master identity required to unlock team keys")?;
 
         // Decrypt the file
         let encrypted_bytes = fs::read(&team_key_path)?;
         let decryptor = age::Decryptor::new(&encrypted_bytes[..])?;
-        let mut reader = decryptor.decrypt(std::iter::once(identity as &dyn age::Identity))?;
+        let mut reader = decryptor.decrypt(std::iter::once(identity.as_identity()))?;
 
         let mut key_bytes = Vec::new();
         use std::io::Read;
         reader.read_to_end(&mut key_bytes)?;
 
-        if key_bytes.len() != 32 {
-            return Err(anyhow::anyhow!("Invalid team key length"));
-        }
+        // The keychain holds the team identity string (AGE-SECRET-KEY-1...)
+        team_identity_from_bytes(&key_bytes)
+            .with_context(|| format!("Invalid team key for '{}'", team_name))?;
 
         Ok(TeamKey(key_bytes))
     }
@@ -734,12 +2123,7 @@ impl ArcaneSecurity {
             return Err(anyhow::anyhow!("Invalid team name"));
         }
 
-        let home = dirs::home_dir().context("Could not find home directory")?;
-        let team_dir = home.join(".arcane").join("teams");
-        fs::create_dir_all(&team_dir)?;
-
-        let team_key_path = team_dir.join(format!("{}.key", team_name));
-        if team_key_path.exists() {
+        if teams_dir()?.join(format!("{}.key", team_name)).exists() {
             return Err(anyhow::anyhow!(
                 "Team '{}' already exists in your keychain",
                 team_name
@@ -748,24 +2132,29 @@ impl ArcaneSecurity {
 
         // Generate new Identity for the team
         let team_identity = x25519::Identity::generate();
-        let team_identity_string = team_identity.to_string(); // Extend lifetime
-        let team_secret = team_identity_string.expose_secret();
+        self.save_team_key(team_name, team_identity.to_string().expose_secret())
+    }
 
-        // Encrypt this secret with Master Identity for storage
-        let master = self
-            .master_identity
-            .as_ref()
-            .context("Master identity required")?;
+    /// Store a team identity in ~/.arcane/teams/<name>.key, encrypted for your
+    /// Master Identity
+    fn save_team_key(&self, team_name: &str, team_secret: &str) -> Result<()> {
+        let team_dir = teams_dir()?;
+        fs::create_dir_all(&team_dir)?;
+
+        let master = self.master().context("Master identity required")?;
         let recipient = master.to_public();
 
-        // Encryption logic
-        let recipients = vec![&recipient as &dyn age::Recipient];
+        let recipients = vec![recipient.as_recipient()];
         let encryptor = age::Encryptor::with_recipients(recipients.into_iter())?;
 
-        let mut file = fs::File::create(&team_key_path)?;
+        // Write next to the old key and swap, so a failure never leaves a broken key
+        let team_key_path = team_dir.join(format!("{}.key", team_name));
+        let tmp_path = team_dir.join(format!(".{}.key.tmp", team_name));
+        let mut file = fs::File::create(&tmp_path)?;
         let mut writer = encryptor.wrap_output(&mut file)?;
         writer.write_all(team_secret.as_bytes())?;
         writer.finish()?;
+        fs::rename(&tmp_path, &team_key_path)?;
 
         Ok(())
     }
@@ -784,15 +2173,26 @@ impl ArcaneSecurity {
         let repo_key = self.load_repo_key()?;
 
         let repo_root = self.get_repo_root()?;
-        let keys_dir = repo_root.join(".git").join("arcane").join("keys");
+        let keys_dir = Self::keys_dir_for(&repo_root);
         let team_file_path = keys_dir.join(format!("team:{}.age", team_name));
-
+        self.record_membership(
+            ManifestOp::Add,
+            None,
+            &format!("team:{}", team_name),
+            &team_recipient.to_string(),
+        )?;
+
+        // Keep the public key so rotation can re-wrap for this team
+        fs::write(
+            keys_dir.join(format!("team:{}.pub", team_name)),
+            team_recipient.to_string(),
+        )?;
         self.encrypt_and_save_key(&repo_key, &team_recipient, &team_file_path)?;
 
         Ok(())
     }
 
-    fn try_decrypt_directory(&self, dir: &Path, identity: &x25519::Identity) -> Result<RepoKey> {
+    fn try_decrypt_directory(&self, dir: &Path, identity: &dyn age::Identity) -> Result<RepoKey> {
         for entry in fs::read_dir(dir)? {
             let entry = entry?;
             let path = entry.path();
@@ -812,9 +2212,34 @@ impl ArcaneSecurity {
     /// 3. Encrypts new key for all 'kept' members (using .pub files)
     ///
     pub fn rotate_repo_key(&self, keep_aliases: &[String]) -> Result<()> {
-        let repo_root = self.get_repo_root()?;
-        let keys_dir = repo_root.join(".git").join("arcane").join("keys");
-        let history_dir = keys_dir.join("history");
+        let (_, skipped) = self.rotate_repo_key_for(keep_aliases)?;
+        for alias in skipped {
+            eprintln!(
+                "⚠️ Warning: No public key found for '{}', skipping (they will lose access)",
+                alias
+            );
+        }
+        Ok(())
+    }
+
+    /// Rotate the repo key, returning the new key and the aliases that could not be
+    /// re-wrapped because no `.pub` file exists for them.
+    fn rotate_repo_key_for(&self, keep_aliases: &[String]) -> Result<(RepoKey, Vec<String>)> {
+        let keys_dir = self.keys_dir()?;
+        self.check_rotation_recipients(None, &keys_dir, keep_aliases)?;
+        let old_key = self.load_repo_key().ok();
+        self.rotate_keyring(&keys_dir, old_key.as_ref(), keep_aliases)
+    }
+
+    /// Snapshot a keyring into `<dir>/history/<timestamp>/`, generate a new key and wrap
+    /// it for `keep_aliases` using their `.pub` files in the same directory.
+    fn rotate_keyring(
+        &self,
+        dir: &Path,
+        old_key: Option<&RepoKey>,
+        keep_aliases: &[String],
+    ) -> Result<(RepoKey, Vec<String>)> {
+        let history_dir = dir.join("history");
 
         // 1. Create history timestamp dir
         let timestamp = std::time::SystemTime::now()
@@ -827,14 +2252,18 @@ impl ArcaneSecurity {
         // 2. Move existing .age files to history
         // Note: We copy .pub files too? Or leave them? We leave them for re-encryption.
         // Actually, let's copy everything to history to be safe state snapshot,
-        // then delete .age files from keys_dir.
-        for entry in fs::read_dir(&keys_dir)? {
+        // then delete .age files from the keyring.
+        for entry in fs::read_dir(dir)? {
             let entry = entry?;
             let path = entry.path();
             if path.is_file() {
                 // Move .age, Copy .pub?
                 // Simpler: Move everything that is a key file.
                 let name = path.file_name().unwrap();
+                // The manifest is append-only history already
+                if name == manifest::MANIFEST_FILE {
+                    continue;
+                }
                 fs::copy(&path, backup_path.join(name))?;
 
                 // Remove old .age files from current dir
@@ -844,125 +2273,928 @@ impl ArcaneSecurity {
             }
         }
 
+        // Record which key this snapshot holds so sealed files can find it directly
+        if let Some(old_key) = old_key {
+            fs::write(
+                backup_path.join(KEY_FINGERPRINT_FILE),
+                old_key.fingerprint_hex(),
+            )?;
+        }
+
         // 3. Generate New Key
         let new_repo_key = self.generate_repo_key()?;
 
         // 4. Encrypt for kept members
+        let mut skipped = Vec::new();
         for alias in keep_aliases {
-            let pub_path = keys_dir.join(format!("{}.pub", alias));
+            let pub_path = dir.join(format!("{}.pub", alias));
             if !pub_path.exists() {
-                eprintln!(
-                    "⚠️ Warning: No public key found for '{}', skipping (they will lose access)",
-                    alias
-                );
+                skipped.push(alias.clone());
                 continue;
             }
 
             let pub_key_str = fs::read_to_string(&pub_path)?;
-            let recipient: x25519::Recipient = pub_key_str
-                .trim()
+            let recipient: RecipientKey = pub_key_str
                 .parse()
                 .map_err(|e| anyhow::anyhow!("Invalid stored public key for {}: {}", alias, e))?;
 
-            let key_path = keys_dir.join(format!("{}.age", alias));
-            self.encrypt_and_save_key(&new_repo_key, &recipient, &key_path)?;
+            let key_path = dir.join(format!("{}.age", alias));
+            self.encrypt_and_save_key(&new_repo_key, recipient.as_recipient(), &key_path)?;
         }
 
-        Ok(())
+        // Env keyrings advertise their current fingerprint (the repo keyring doesn't)
+        if dir.join(KEY_FINGERPRINT_FILE).exists() {
+            fs::write(
+                dir.join(KEY_FINGERPRINT_FILE),
+                new_repo_key.fingerprint_hex(),
+            )?;
+        }
+
+        Ok((new_repo_key, skipped))
     }
 
-    fn try_decrypt_key_file(&self, path: &Path, identity: &x25519::Identity) -> Result<RepoKey> {
-        let encrypted_bytes = fs::read(path)?;
-        let decryptor = age::Decryptor::new(&encrypted_bytes[..])?;
+    /// Revoke a recipient (`<alias>`, `team:<name>` or `machine:<id>`) from this repo
+    /// and from every environment keyring it is in:
+    ///
+    /// 1. Removes its key files, including from every `history` snapshot
+    /// 2. Rotates each affected key and re-wraps it for every remaining recipient
+    /// 3. Re-seals every tracked file with the `filter=git-arcane` attribute that used it
+    /// 4. Commits the result in a single commit
+    ///
+    /// Anything the member already pulled stays readable to them; the rotation protects
+    /// everything committed from here on.
+    pub fn remove_team_member(&self, alias: &str) -> Result<RevocationSummary> {
+        self.revoke_recipient(alias, None, None)
+    }
 
-        // Decryptor is a struct in 0.11+, handles recipients internally
-        let mut reader = decryptor.decrypt(std::iter::once(identity as &dyn age::Identity))?;
+    /// Revoke a recipient from one environment keyring only (see `remove_team_member`)
+    pub fn revoke_env_access(&self, env: &str, alias: &str) -> Result<RevocationSummary> {
+        self.revoke_recipient(alias, Some(env), None)
+    }
 
-        let mut key_bytes = Vec::new();
-        use std::io::Read;
-        reader.read_to_end(&mut key_bytes)?;
+    /// Revoke `alias`, or with `replacement`, keep it as a recipient under that new
+    /// public key. Either way every affected key is rotated and its files re-sealed.
+    fn revoke_recipient(
+        &self,
+        alias: &str,
+        only_env: Option<&str>,
+        replacement: Option<&x25519::Recipient>,
+    ) -> Result<RevocationSummary> {
+        let alias = alias.trim();
+        if alias.is_empty() || alias.contains('/') || alias.contains('\\') {
+            return Err(anyhow::anyhow!("Invalid alias"));
+        }
 
-        if key_bytes.len() != REPO_KEY_LEN {
-            return Err(anyhow::anyhow!("Invalid decrypted key length"));
+        let repo_root = self.get_repo_root()?;
+        let keys_dir = Self::keys_dir_for(&repo_root);
+
+        // Keyrings holding a wrap for the alias: None = repo keyring, Some(env) = env keyring
+        let mut targets: Vec<(Option<String>, PathBuf)> = Vec::new();
+        if only_env.is_none() && keys_dir.join(format!("{}.age", alias)).exists() {
+            targets.push((None, keys_dir.clone()));
+        }
+        let envs = match only_env {
+            Some(env) => vec![env.to_string()],
+            None => self.list_env_keyrings()?,
+        };
+        for env in envs {
+            let dir = Self::env_keys_dir_for(&repo_root, &env);
+            if dir.join(format!("{}.age", alias)).exists() {
+                targets.push((Some(env), dir));
+            }
+        }
+        if targets.is_empty() {
+            return Err(match only_env {
+                Some(env) => anyhow::anyhow!("'{}' is not a recipient of '{}'", alias, env),
+                None => anyhow::anyhow!("No member '{}' in {:?}", alias, keys_dir),
+            });
         }
 
-        Ok(RepoKey(key_bytes))
-    }
+        if let Some(identity) = self.master() {
+            let me = identity.to_public().to_string();
+            for (_, dir) in &targets {
+                let pub_key = fs::read_to_string(dir.join(format!("{}.pub", alias)));
+                if pub_key.map(|k| k.trim() == me).unwrap_or(false) {
+                    return Err(anyhow::anyhow!(
+                        "'{}' is your own identity; removing it would lock you out",
+                        alias
+                    ));
+                }
+            }
+        }
 
-    /// Import an existing raw 32-byte key (e.g. from Git Seal)
-    pub fn import_repo_key(&self, key_bytes: &[u8]) -> Result<PathBuf> {
-        if key_bytes.len() != REPO_KEY_LEN {
+        for (env, dir) in &targets {
+            let remaining: Vec<String> = wrapped_aliases(dir)?
+                .into_iter()
+                .filter(|a| a != alias)
+                .collect();
+            self.check_rotation_recipients(env.as_deref(), dir, &remaining)?;
+        }
+
+        if git_in(&repo_root, &["diff", "--cached", "--quiet"]).is_err() {
             return Err(anyhow::anyhow!(
-                "Invalid key length: expected 32 bytes, got {}",
-                key_bytes.len()
+                "The index has staged changes; commit or unstage them first"
             ));
         }
 
-        let repo_root = self.get_repo_root()?;
-        let arcane_dir = repo_root.join(".git").join("arcane");
-        let keys_dir = arcane_dir.join("keys");
+        // Open every affected key and decrypt the files sealed with it before touching
+        // anything, so a failure leaves the repo as it was.
+        let mut old_keys: std::collections::HashMap<Option<String>, RepoKey> =
+            std::collections::HashMap::new();
+        for (env, _) in &targets {
+            let key = match env {
+                None => self.load_repo_key()?,
+                Some(env) => self
+                    .load_env_key(env)?
+                    .with_context(|| format!("No keyring for '{}'", env))?,
+            };
+            old_keys.insert(env.clone(), key);
+        }
 
-        if keys_dir.exists() {
-            // stricter check? or allow overwrite?
-            // For import, we probably want to fail if already initialized to avoid accidental overwrite
-            if keys_dir.read_dir()?.next().is_some() {
-                return Err(anyhow::anyhow!(
-                    "Repo already initialized. Remove keys to import."
-                ));
-            }
+        let env_keyrings = self.list_env_keyrings()?;
+        let mut plaintexts = Vec::new();
+        for (mode, path) in self.filtered_index_entries(&repo_root)? {
+            let owner = crate::config::env::env_name_for_path(&path)
+                .filter(|env| env_keyrings.iter().any(|k| k == env))
+                .map(String::from);
+            let Some(old_key) = old_keys.get(&owner) else {
+                continue;
+            };
+            let plain = self.read_index_plaintext(&repo_root, &path, old_key)?;
+            plaintexts.push((owner, mode, path, plain));
         }
-        fs::create_dir_all(&keys_dir)?;
 
-        let repo_key = RepoKey(key_bytes.to_vec());
+        self.backfill_recipient_pubs(&keys_dir)?;
 
-        // Get own identity to encrypt for self
-        let identity = self
-            .master_identity
-            .as_ref()
-            .context("Master identity needed to import")?;
-        let recipient = identity.to_public();
+        for (env, dir) in &targets {
+            let pub_key =
+                fs::read_to_string(dir.join(format!("{}.pub", alias))).unwrap_or_default();
+            self.record_membership(ManifestOp::Remove, env.as_deref(), alias, pub_key.trim())?;
+        }
 
-        // Save owner's public key
-        let pub_key_path = keys_dir.join("owner.pub");
-        fs::write(&pub_key_path, recipient.to_string())?;
+        let mut purged_snapshots = 0;
+        let mut keyrings = Vec::new();
+        let mut new_keys: std::collections::HashMap<Option<String>, RepoKey> =
+            std::collections::HashMap::new();
+        for (env, dir) in &targets {
+            fs::remove_file(dir.join(format!("{}.age", alias)))?;
+            let pub_path = dir.join(format!("{}.pub", alias));
+            if pub_path.exists() {
+                fs::remove_file(&pub_path)?;
+            }
+            purged_snapshots += purge_alias_from_history(&dir.join("history"), alias)?;
+
+            let mut remaining = wrapped_aliases(dir)?;
+            let (new_key, skipped) = self.rotate_keyring(dir, old_keys.get(env), &remaining)?;
+            if let Some(recipient) = replacement {
+                self.record_membership(
+                    ManifestOp::Add,
+                    env.as_deref(),
+                    alias,
+                    &recipient.to_string(),
+                )?;
+                fs::write(&pub_path, recipient.to_string())?;
+                self.encrypt_and_save_key(
+                    &new_key,
+                    recipient,
+                    &dir.join(format!("{}.age", alias)),
+                )?;
+                remaining.push(alias.to_string());
+            }
+            keyrings.push(KeyringUpdate {
+                name: env.clone().unwrap_or_else(|| "repo".to_string()),
+                key_fingerprint: new_key.fingerprint_hex(),
+                rewrapped: remaining
+                    .into_iter()
+                    .filter(|a| !skipped.contains(a))
+                    .collect(),
+                skipped,
+            });
+            new_keys.insert(env.clone(), new_key);
+        }
 
-        // Encrypt and save as 'owner.age'
-        let key_path = keys_dir.join("owner.age");
-        self.encrypt_and_save_key(&repo_key, &recipient, &key_path)?;
+        let mut resealed = Vec::new();
+        for (owner, mode, path, plain) in plaintexts {
+            self.reseal_in_index(&repo_root, &mode, &path, &plain, &new_keys[&owner])?;
+            resealed.push(path);
+        }
 
-        // Auto-configure Git filters
-        self.configure_git_filters(&repo_root)?;
+        let names: Vec<&str> = keyrings.iter().map(|k| k.name.as_str()).collect();
+        let action = match replacement {
+            Some(_) => "Replace",
+            None => "Revoke",
+        };
+        let commit = self.commit_keyring_change(
+            &repo_root,
+            &format!("{} {} and rotate keys: {}", action, alias, names.join(", ")),
+        )?;
+
+        Ok(RevocationSummary {
+            alias: alias.to_string(),
+            keyrings,
+            resealed,
+            purged_snapshots,
+            commit,
+        })
+    }
 
-        Ok(key_path)
+    /// Keyring for one environment: `<keys_dir>/env/<env>/`
+    pub fn env_keys_dir_for(repo_root: &Path, env: &str) -> PathBuf {
+        Self::keys_dir_for(repo_root).join(ENV_KEYS_DIR).join(env)
     }
 
-    /// Initialize security for the current repo (generate key and encrypt for self)
-    pub fn init_repo(&self) -> Result<PathBuf> {
-        let repo_root = self.get_repo_root()?;
-        let arcane_dir = repo_root.join(".git").join("arcane");
-        let keys_dir = arcane_dir.join("keys");
+    /// Environments that have their own keyring
+    pub fn list_env_keyrings(&self) -> Result<Vec<String>> {
+        let env_dir = self.keys_dir()?.join(ENV_KEYS_DIR);
+        if !env_dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut envs: Vec<String> = fs::read_dir(env_dir)?
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_dir())
+            .filter_map(|e| e.file_name().to_str().map(String::from))
+            .collect();
+        envs.sort();
+        Ok(envs)
+    }
 
-        if keys_dir.exists() {
-            // If directory exists but is empty, allow re-initialization
-            if keys_dir.read_dir()?.next().is_some() {
-                return Err(anyhow::anyhow!(
-                    "Repo already initialized at {:?}",
-                    keys_dir
-                ));
-            }
+    /// Recipients of an environment keyring
+    pub fn list_env_members(&self, env: &str) -> Result<Vec<String>> {
+        let dir = Self::env_keys_dir_for(&self.get_repo_root()?, env);
+        if !dir.is_dir() {
+            return Err(anyhow::anyhow!("No keyring for '{}'", env));
         }
+        wrapped_aliases(&dir)
+    }
 
-        fs::create_dir_all(&keys_dir)?;
+    /// Load the key for an environment. `Ok(None)` means the environment has no keyring
+    /// of its own and its files are sealed with the repo key.
+    pub fn load_env_key(&self, env: &str) -> Result<Option<RepoKey>> {
+        let dir = Self::env_keys_dir_for(&self.get_repo_root()?, env);
+        if !dir.is_dir() {
+            return Ok(None);
+        }
+        self.cached_key(keyring_cache_label(&dir), || {
+            self.try_unlock_directory(&dir)
+        })
+        .map(Some)
+        .map_err(|_| {
+            anyhow::anyhow!(
+                "No access to '{}' secrets: you are not a recipient of {:?}",
+                env,
+                dir
+            )
+        })
+    }
 
-        // Generate new repo key
-        let repo_key = self.generate_repo_key()?;
+    /// Key a file is sealed with: its environment's key (`config/envs/<env>.env`) when
+    /// that environment has a keyring, otherwise the repo key
+    pub fn sealing_key_for_path(&self, path: &str) -> Result<RepoKey> {
+        if let Some(env) = crate::config::env::env_name_for_path(path) {
+            if let Some(key) = self.load_env_key(env)? {
+                return Ok(key);
+            }
+        }
+        self.load_repo_key()
+    }
 
-        // Get own identity to encrypt for self
-        let identity = self
-            .master_identity
-            .as_ref()
-            .context("Master identity needed to init")?;
-        let recipient = identity.to_public();
+    /// Give an environment its own key, wrapped only for `recipients` (aliases with a
+    /// `.pub` in the repo keyring, e.g. `alice`, `team:ops`, `machine:age1abc`).
+    /// With no recipients the key is wrapped for you. The environment's tracked files
+    /// are re-sealed with the new key and committed.
+    pub fn init_env_key(&self, env: &str, recipients: &[String]) -> Result<EnvKeyInit> {
+        let env = env.trim();
+        if env.is_empty() || env == "base" || env.contains('/') || env.contains('\\') {
+            return Err(anyhow::anyhow!("Invalid environment name '{}'", env));
+        }
+
+        let repo_root = self.get_repo_root()?;
+        let keys_dir = Self::keys_dir_for(&repo_root);
+        let dir = Self::env_keys_dir_for(&repo_root, env);
+        if !wrapped_aliases(&dir).unwrap_or_default().is_empty() {
+            return Err(anyhow::anyhow!(
+                "'{}' already has a keyring at {:?}",
+                env,
+                dir
+            ));
+        }
+        if git_in(&repo_root, &["diff", "--cached", "--quiet"]).is_err() {
+            return Err(anyhow::anyhow!(
+                "The index has staged changes; commit or unstage them first"
+            ));
+        }
+
+        // Resolve every recipient before writing anything
+        let mut wraps: Vec<(String, String)> = Vec::new();
+        if recipients.is_empty() {
+            let me = self
+                .master()
+                .context("Master identity required")?
+                .to_public()
+                .to_string();
+            let alias = alias_for_public_key(&keys_dir, &me).unwrap_or_else(|| "owner".into());
+            wraps.push((alias, me));
+        }
+        for alias in recipients {
+            let pub_key =
+                fs::read_to_string(keys_dir.join(format!("{}.pub", alias))).map_err(|_| {
+                    anyhow::anyhow!(
+                        "No public key for '{}' in {:?}; add them to the repo first",
+                        alias,
+                        keys_dir
+                    )
+                })?;
+            wraps.push((alias.clone(), pub_key.trim().to_string()));
+        }
+
+        let mut recipients_parsed = Vec::new();
+        for (alias, pub_key) in &wraps {
+            let recipient: RecipientKey = pub_key
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid public key for {}: {}", alias, e))?;
+            recipients_parsed.push(recipient);
+        }
+
+        let repo_key = self.load_repo_key()?;
+        let mut plaintexts = Vec::new();
+        for (mode, path) in self.filtered_index_entries(&repo_root)? {
+            if crate::config::env::env_name_for_path(&path) == Some(env) {
+                // The working tree copy may be replaced below, so it must match the index
+                if git_in(&repo_root, &["diff", "--quiet", "--", &path]).is_err() {
+                    return Err(anyhow::anyhow!(
+                        "{} has uncommitted changes; commit or stash them first",
+                        path
+                    ));
+                }
+                let plain = self.read_index_plaintext(&repo_root, &path, &repo_key)?;
+                plaintexts.push((mode, path, plain));
+            }
+        }
+
+        for ((alias, _), recipient) in wraps.iter().zip(&recipients_parsed) {
+            self.record_membership(ManifestOp::Add, Some(env), alias, &recipient.to_string())?;
+        }
+
+        fs::create_dir_all(&dir)?;
+        let env_key = self.generate_repo_key()?;
+        for ((alias, _), recipient) in wraps.iter().zip(&recipients_parsed) {
+            fs::write(dir.join(format!("{}.pub", alias)), recipient.to_string())?;
+            self.encrypt_and_save_key(
+                &env_key,
+                recipient.as_recipient(),
+                &dir.join(format!("{}.age", alias)),
+            )?;
+        }
+        fs::write(dir.join(KEY_FINGERPRINT_FILE), env_key.fingerprint_hex())?;
+
+        let mut resealed = Vec::new();
+        for (mode, path, plain) in plaintexts {
+            self.reseal_in_index(&repo_root, &mode, &path, &plain, &env_key)?;
+            resealed.push(path);
+        }
+
+        // Not a recipient yourself: swap the plaintext you can no longer re-seal for the
+        // sealed blob, exactly what a fresh checkout would give you.
+        if self.load_env_key(env).is_err() && !resealed.is_empty() {
+            let mut args = vec!["checkout", "--"];
+            args.extend(resealed.iter().map(|p| p.as_str()));
+            git_in(&repo_root, &args)?;
+        }
+
+        let commit =
+            self.commit_keyring_change(&repo_root, &format!("Give {} its own key", env))?;
+
+        Ok(EnvKeyInit {
+            keyring: KeyringUpdate {
+                name: env.to_string(),
+                key_fingerprint: env_key.fingerprint_hex(),
+                rewrapped: wraps.into_iter().map(|(alias, _)| alias).collect(),
+                skipped: Vec::new(),
+            },
+            resealed,
+            commit,
+        })
+    }
+
+    /// Wrap an environment key for another recipient. The public key comes from
+    /// `public_key` or from `<alias>.pub` in the repo keyring.
+    pub fn grant_env_access(&self, env: &str, alias: &str, public_key: Option<&str>) -> Result<()> {
+        let alias = alias.trim();
+        if alias.is_empty() || alias.contains('/') || alias.contains('\\') {
+            return Err(anyhow::anyhow!("Invalid alias"));
+        }
+
+        let repo_root = self.get_repo_root()?;
+        let env_key = self.load_env_key(env)?.with_context(|| {
+            format!(
+                "No keyring for '{}'. Run 'arcane env init {}' first.",
+                env, env
+            )
+        })?;
+
+        let pub_key =
+            match public_key {
+                Some(key) => key.trim().to_string(),
+                None => fs::read_to_string(
+                    Self::keys_dir_for(&repo_root).join(format!("{}.pub", alias)),
+                )
+                .map_err(|_| anyhow::anyhow!("No public key for '{}'; pass it with --key", alias))?
+                .trim()
+                .to_string(),
+            };
+        let recipient: RecipientKey = pub_key
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid public key: {}", e))?;
+
+        self.record_membership(ManifestOp::Add, Some(env), alias, &recipient.to_string())?;
+
+        let dir = Self::env_keys_dir_for(&repo_root, env);
+        fs::write(dir.join(format!("{}.pub", alias)), recipient.to_string())?;
+        self.encrypt_and_save_key(
+            &env_key,
+            recipient.as_recipient(),
+            &dir.join(format!("{}.age", alias)),
+        )
+    }
+
+    /// Start a signed key manifest for a keyring created before manifests existed,
+    /// recording every recipient that has a `.pub` file. Signed by you, so your key
+    /// must be in the repo keyring. Returns the number of entries written.
+    pub fn init_key_manifest(&self) -> Result<usize> {
+        let repo_root = self.get_repo_root()?;
+        let keys_dir = Self::keys_dir_for(&repo_root);
+        if manifest::exists(&keys_dir) {
+            return Err(anyhow::anyhow!(
+                "{:?} already has a key manifest",
+                keys_dir.join(manifest::MANIFEST_FILE)
+            ));
+        }
+        self.backfill_recipient_pubs(&keys_dir)?;
+
+        let secret = self.manifest_signing_key()?;
+        let me = self
+            .master()
+            .context("Master identity required")?
+            .to_public()
+            .to_string();
+        let my_alias = alias_for_public_key(&keys_dir, &me).with_context(|| {
+            format!(
+                "Your public key has no .pub in {:?}; only a member can sign",
+                keys_dir
+            )
+        })?;
+
+        manifest::append(
+            &keys_dir,
+            &secret,
+            &my_alias,
+            ManifestOp::Add,
+            None,
+            &my_alias,
+            &me,
+        )?;
+        let mut written = 1;
+
+        let mut keyrings = vec![(None, keys_dir.clone())];
+        for env in self.list_env_keyrings()? {
+            let dir = Self::env_keys_dir_for(&repo_root, &env);
+            keyrings.push((Some(env), dir));
+        }
+        for (env, dir) in &keyrings {
+            for alias in wrapped_aliases(dir)? {
+                if env.is_none() && alias == my_alias {
+                    continue;
+                }
+                let Ok(pub_key) = fs::read_to_string(dir.join(format!("{}.pub", alias))) else {
+                    continue;
+                };
+                manifest::append(
+                    &keys_dir,
+                    &secret,
+                    &my_alias,
+                    ManifestOp::Add,
+                    env.as_deref(),
+                    &alias,
+                    pub_key.trim(),
+                )?;
+                written += 1;
+            }
+        }
+        Ok(written)
+    }
+
+    /// Check every keyring of a repo against its signed key manifest, pinning the
+    /// manifest's first entry the first time this clone sees it.
+    /// `Ok(None)` if the repo has no manifest; `Err` if the manifest itself doesn't verify.
+    pub fn audit_keyrings(repo_root: &Path) -> Result<Option<KeyringAudit>> {
+        Self::audit_keyrings_with(repo_root, true)
+    }
+
+    /// Like `audit_keyrings`, but never writes the pin: for diagnostics, which must not
+    /// change what this clone trusts.
+    pub fn inspect_keyrings(repo_root: &Path) -> Result<Option<KeyringAudit>> {
+        Self::audit_keyrings_with(repo_root, false)
+    }
+
+    fn audit_keyrings_with(repo_root: &Path, pin_genesis: bool) -> Result<Option<KeyringAudit>> {
+        let keys_dir = Self::keys_dir_for(repo_root);
+        let Some(membership) = manifest::verify(&keys_dir)? else {
+            return Ok(None);
+        };
+
+        let mut audit = KeyringAudit {
+            entries: membership.entries,
+            ..Default::default()
+        };
+
+        let mut keyrings = vec![(None, keys_dir.clone())];
+        if let Ok(entries) = fs::read_dir(keys_dir.join(ENV_KEYS_DIR)) {
+            let mut envs: Vec<String> = entries
+                .filter_map(|e| e.ok())
+                .filter(|e| e.path().is_dir())
+                .filter_map(|e| e.file_name().to_str().map(String::from))
+                .collect();
+            envs.sort();
+            keyrings.extend(envs.into_iter().map(|env| {
+                let dir = Self::env_keys_dir_for(repo_root, &env);
+                (Some(env), dir)
+            }));
+        }
+
+        for (env, dir) in &keyrings {
+            let name = env.clone().unwrap_or_else(|| "repo".to_string());
+            let mut aliases: Vec<String> = fs::read_dir(dir)?
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| {
+                    p.is_file()
+                        && matches!(p.extension().and_then(|e| e.to_str()), Some("age" | "pub"))
+                })
+                .filter_map(|p| p.file_stem().and_then(|s| s.to_str()).map(String::from))
+                .collect();
+            aliases.sort();
+            aliases.dedup();
+
+            for alias in aliases {
+                match membership.recipient(env.as_deref(), &alias) {
+                    None => audit.unaccounted.push(format!("{}/{}", name, alias)),
+                    Some(recorded) => {
+                        if let Ok(pub_key) = fs::read_to_string(dir.join(format!("{}.pub", alias)))
+                        {
+                            if pub_key.trim() != recorded {
+                                audit.mismatched.push(format!("{}/{}", name, alias));
+                            }
+                        }
+                    }
+                }
+            }
+
+            let members = membership
+                .keyrings
+                .get(env)
+                .map(|m| m.keys().cloned().collect())
+                .unwrap_or_default();
+            audit.members.push((name, members));
+        }
+
+        // Pin the first entry per clone, so swapping in a whole new manifest is noticed
+        let pin = repo_root
+            .join(".git")
+            .join("arcane")
+            .join(MANIFEST_PIN_FILE);
+        match fs::read_to_string(&pin) {
+            Ok(pinned) => audit.genesis_changed = pinned.trim() != membership.genesis,
+            Err(_) if pin_genesis => {
+                let _ = fs::create_dir_all(repo_root.join(".git").join("arcane"));
+                let _ = fs::write(&pin, &membership.genesis);
+            }
+            Err(_) => {}
+        }
+
+        Ok(Some(audit))
+    }
+
+    /// Record a membership change in the key manifest, signed with your identity.
+    /// Keyrings without a manifest are left alone.
+    fn record_membership(
+        &self,
+        op: ManifestOp,
+        env: Option<&str>,
+        alias: &str,
+        recipient: &str,
+    ) -> Result<()> {
+        let keys_dir = self.keys_dir()?;
+        let Some(membership) = manifest::verify(&keys_dir)? else {
+            return Ok(());
+        };
+        let secret = self.manifest_signing_key()?;
+        let me = self
+            .master()
+            .context("Master identity required")?
+            .to_public()
+            .to_string();
+        let signer = membership
+            .member_alias(None, &me)
+            .or_else(|| membership.member_alias(env, &me))
+            .context("Your key is not in the key manifest, so you can't sign changes to it")?
+            .to_string();
+        manifest::append(&keys_dir, &secret, &signer, op, env, alias, recipient)
+            .context("Failed to record the change in the key manifest")
+    }
+
+    /// Begin the manifest of a new keyring with its owner. SSH identities can't sign,
+    /// so their keyrings start without one.
+    fn start_key_manifest(&self, keys_dir: &Path, alias: &str) -> Result<()> {
+        let Some(MasterIdentity::Age(identity)) = self.master() else {
+            return Ok(());
+        };
+        let secret = self.manifest_signing_key()?;
+        let recipient = identity.to_public().to_string();
+        manifest::append(
+            keys_dir,
+            &secret,
+            alias,
+            ManifestOp::Add,
+            None,
+            alias,
+            &recipient,
+        )
+    }
+
+    /// Raw x25519 secret of your identity, for signing manifest entries
+    fn manifest_signing_key(&self) -> Result<zeroize::Zeroizing<[u8; 32]>> {
+        match self.master().context("Master identity required")? {
+            MasterIdentity::Age(identity) => Ok(zeroize::Zeroizing::new(
+                manifest::decode_age_secret_key(identity.to_string().expose_secret())?,
+            )),
+            MasterIdentity::Ssh { .. } => Err(anyhow::anyhow!(
+                "SSH identities can't sign the key manifest; use an age identity ('arcane identity new')"
+            )),
+        }
+    }
+
+    /// Refuse to wrap a new key for recipients the manifest doesn't account for
+    fn check_rotation_recipients(
+        &self,
+        env: Option<&str>,
+        dir: &Path,
+        aliases: &[String],
+    ) -> Result<()> {
+        let Some(membership) = manifest::verify(&self.keys_dir()?)? else {
+            return Ok(());
+        };
+        let name = env.unwrap_or("repo");
+        for alias in aliases {
+            let Some(recorded) = membership.recipient(env, alias) else {
+                return Err(anyhow::anyhow!(
+                    "Refusing to rotate: {}/{} is not in the key manifest. Remove its key files or add it with arcane.",
+                    name,
+                    alias
+                ));
+            };
+            if let Ok(pub_key) = fs::read_to_string(dir.join(format!("{}.pub", alias))) {
+                if pub_key.trim() != recorded {
+                    return Err(anyhow::anyhow!(
+                        "Refusing to rotate: {}/{}.pub differs from the key recorded in the manifest",
+                        name,
+                        alias
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Print any keyring audit problems once per process
+    fn warn_on_keyring_audit(repo_root: &Path) {
+        static WARNED: std::sync::Once = std::sync::Once::new();
+        WARNED.call_once(|| match Self::audit_keyrings(repo_root) {
+            Ok(Some(audit)) => {
+                for problem in audit.problems() {
+                    eprintln!("⚠️ Keyring: {}", problem);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("⚠️ Key manifest failed verification: {:#}", e),
+        });
+    }
+
+    /// Plaintext of a file as staged in the index. Sealed blobs use whichever key
+    /// their header names; legacy blobs are tried with `key`, else kept as-is.
+    /// Field-sealed YAML/JSON/TOML values are opened too.
+    fn read_index_plaintext(&self, repo_root: &Path, path: &str, key: &RepoKey) -> Result<Vec<u8>> {
+        let blob = git_in(repo_root, &["cat-file", "blob", &format!(":{}", path)])?;
+        let plain = if Self::is_sealed(&blob) {
+            let blob_key = self.load_repo_key_for_blob(&blob)?;
+            self.decrypt_with_repo_key(&blob_key, &blob)
+                .with_context(|| format!("Failed to decrypt {}", path))?
+        } else {
+            // Legacy headerless blob, or plaintext that predates the filter
+            self.decrypt_with_repo_key(key, &blob).unwrap_or(blob)
+        };
+
+        match std::str::from_utf8(&plain) {
+            Ok(text)
+                if StructuredFormat::from_path(Path::new(path)).is_some()
+                    && fields::has_sealed_fields(text) =>
+            {
+                let format = StructuredFormat::from_path(Path::new(path)).unwrap();
+                let opened = self
+                    .open_fields(text, format, Some(key))
+                    .with_context(|| format!("Failed to decrypt {}", path))?;
+                Ok(opened.into_bytes())
+            }
+            _ => Ok(plain),
+        }
+    }
+
+    /// Seal `plaintext` with `key`, the way the clean filter would for `path`, and stage
+    /// it without touching the working tree
+    fn reseal_in_index(
+        &self,
+        repo_root: &Path,
+        mode: &str,
+        path: &str,
+        plaintext: &[u8],
+        key: &RepoKey,
+    ) -> Result<()> {
+        let sealed = self.seal_with(key, Some(path), plaintext)?;
+        let sha = git_in_with_input(
+            repo_root,
+            &["hash-object", "-w", "--stdin", "--no-filters"],
+            &sealed,
+        )?;
+        git_in(
+            repo_root,
+            &[
+                "update-index",
+                "--cacheinfo",
+                &format!("{},{},{}", mode, sha.trim(), path),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Stage the tracked keyring (if any) and commit whatever is staged.
+    /// Returns the short hash, or None if nothing changed.
+    fn commit_keyring_change(&self, repo_root: &Path, message: &str) -> Result<Option<String>> {
+        if Self::keys_dir_for(repo_root).starts_with(Self::tracked_keys_dir(repo_root)) {
+            git_in(repo_root, &["add", "-A", "--", TRACKED_KEYS_DIR])?;
+        }
+        if git_in(repo_root, &["diff", "--cached", "--quiet"]).is_ok() {
+            return Ok(None);
+        }
+        git_in(repo_root, &["commit", "-q", "-m", message])
+            .map_err(|e| anyhow::anyhow!("Keys changed but the commit failed: {}", e))?;
+        let head = git_in(repo_root, &["rev-parse", "--short", "HEAD"])?;
+        Ok(Some(String::from_utf8_lossy(&head).trim().to_string()))
+    }
+
+    /// Tracked files whose `filter` attribute is `git-arcane`, as (mode, path)
+    fn filtered_index_entries(&self, repo_root: &Path) -> Result<Vec<(String, String)>> {
+        let listing = git_in(repo_root, &["ls-files", "-s", "-z"])?;
+        let mut entries = Vec::new();
+        for record in listing.split(|b| *b == 0).filter(|r| !r.is_empty()) {
+            let record = String::from_utf8_lossy(record);
+            // "<mode> <sha> <stage>\t<path>"
+            if let Some((meta, path)) = record.split_once('\t') {
+                let mode = meta.split(' ').next().unwrap_or("100644");
+                entries.push((mode.to_string(), path.to_string()));
+            }
+        }
+        if entries.is_empty() {
+            return Ok(entries);
+        }
+
+        let paths: Vec<u8> = entries
+            .iter()
+            .flat_map(|(_, p)| p.bytes().chain(std::iter::once(0)))
+            .collect();
+        let attrs = git_in_with_input(
+            repo_root,
+            &["check-attr", "-z", "--stdin", "filter"],
+            &paths,
+        )?;
+        let filtered = filtered_paths(&attrs);
+        entries.retain(|(_, p)| filtered.contains(p));
+        Ok(entries)
+    }
+
+    /// Older `team:` and `machine:` wraps were written without a `.pub` file, so
+    /// rotation couldn't re-wrap them. Recreate the team ones we can derive.
+    fn backfill_recipient_pubs(&self, keys_dir: &Path) -> Result<()> {
+        for entry in fs::read_dir(keys_dir)? {
+            let path = entry?.path();
+            let Some(stem) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            if path.extension().and_then(|e| e.to_str()) != Some("age") {
+                continue;
+            }
+            let pub_path = keys_dir.join(format!("{}.pub", stem));
+            if pub_path.exists() {
+                continue;
+            }
+            if let Some(team_name) = stem.strip_prefix("team:") {
+                if let Ok(team_key) = self.load_team_key(team_name) {
+                    use std::str::FromStr;
+                    if let Ok(identity) = std::str::from_utf8(&team_key.0)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|k| x25519::Identity::from_str(k).map_err(anyhow::Error::msg))
+                    {
+                        fs::write(&pub_path, identity.to_public().to_string())?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn try_decrypt_key_file(&self, path: &Path, identity: &dyn age::Identity) -> Result<RepoKey> {
+        let encrypted_bytes = fs::read(path)?;
+        let decryptor = age::Decryptor::new(&encrypted_bytes[..])?;
+
+        // Decryptor is a struct in 0.11+, handles recipients internally
+        let mut reader = decryptor.decrypt(std::iter::once(identity))?;
+
+        let mut key_bytes = Vec::new();
+        use std::io::Read;
+        reader.read_to_end(&mut key_bytes)?;
+
+        if key_bytes.len() != REPO_KEY_LEN {
+            return Err(anyhow::anyhow!("Invalid decrypted key length"));
+        }
+
+        Ok(RepoKey(key_bytes))
+    }
+
+    /// Import an existing raw 32-byte key (e.g. from Git Seal)
+    pub fn import_repo_key(&self, key_bytes: &[u8]) -> Result<PathBuf> {
+        if key_bytes.len() != REPO_KEY_LEN {
+            return Err(anyhow::anyhow!(
+                "Invalid key length: expected 32 bytes, got {}",
+                key_bytes.len()
+            ));
+        }
+
+        let repo_root = self.get_repo_root()?;
+        let keys_dir = Self::keys_dir_for(&repo_root);
+
+        if keys_dir.exists() {
+            // stricter check? or allow overwrite?
+            // For import, we probably want to fail if already initialized to avoid accidental overwrite
+            if keys_dir.read_dir()?.next().is_some() {
+                return Err(anyhow::anyhow!(
+                    "Repo already initialized. Remove keys to import."
+                ));
+            }
+        }
+        fs::create_dir_all(&keys_dir)?;
+
+        let repo_key = RepoKey(key_bytes.to_vec());
+
+        // Get own identity to encrypt for self
+        let identity = self.master().context("Master identity needed to import")?;
+        let recipient = identity.to_public();
+
+        // Save owner's public key
+        let pub_key_path = keys_dir.join("owner.pub");
+        fs::write(&pub_key_path, recipient.to_string())?;
+
+        // Encrypt and save as 'owner.age'
+        let key_path = keys_dir.join("owner.age");
+        self.encrypt_and_save_key(&repo_key, recipient.as_recipient(), &key_path)?;
+        self.start_key_manifest(&keys_dir, "owner")?;
+
+        // Auto-configure Git filters
+        self.configure_git_filters(&repo_root)?;
+
+        Ok(key_path)
+    }
+
+    /// Initialize security for the current repo (generate key and encrypt for self)
+    pub fn init_repo(&self) -> Result<PathBuf> {
+        let repo_root = self.get_repo_root()?;
+        let keys_dir = Self::keys_dir_for(&repo_root);
+
+        if keys_dir.exists() {
+            // If directory exists but is empty, allow re-initialization
+            if keys_dir.read_dir()?.next().is_some() {
+                return Err(anyhow::anyhow!(
+                    "Repo already initialized at {:?}",
+                    keys_dir
+                ));
+            }
+        }
+
+        fs::create_dir_all(&keys_dir)?;
+
+        // Generate new repo key
+        let repo_key = self.generate_repo_key()?;
+
+        // Get own identity to encrypt for self
+        let identity = self.master().context("Master identity needed to init")?;
+        let recipient = identity.to_public();
 
         // Save owner's public key for rotation
         let pub_key_path = keys_dir.join("owner.pub");
@@ -971,7 +3203,8 @@ impl ArcaneSecurity {
         // Encrypt and save as 'owner.age' (or derived name)
         // Using 'owner.age' for the initial key
         let key_path = keys_dir.join("owner.age");
-        self.encrypt_and_save_key(&repo_key, &recipient, &key_path)?;
+        self.encrypt_and_save_key(&repo_key, recipient.as_recipient(), &key_path)?;
+        self.start_key_manifest(&keys_dir, "owner")?;
 
         // Auto-configure Git filters
         self.configure_git_filters(&repo_root)?;
@@ -982,10 +3215,10 @@ impl ArcaneSecurity {
     fn encrypt_and_save_key(
         &self,
         repo_key: &RepoKey,
-        recipient: &x25519::Recipient,
+        recipient: &dyn age::Recipient,
         path: &Path,
     ) -> Result<()> {
-        let recipients = vec![recipient as &dyn age::Recipient];
+        let recipients = vec![recipient];
         let encryptor = age::Encryptor::with_recipients(recipients.into_iter())
             .context("Failed to create encryptor")?;
 
@@ -999,7 +3232,7 @@ impl ArcaneSecurity {
 
     /// Add a new team member by encrypting the repo key for them
     pub fn add_team_member(&self, alias: &str, public_key_str: &str) -> Result<()> {
-        let recipient: x25519::Recipient = public_key_str
+        let recipient: RecipientKey = public_key_str
             .parse()
             .map_err(|e| anyhow::anyhow!("Invalid public key: {}", e))?;
 
@@ -1014,122 +3247,323 @@ impl ArcaneSecurity {
         }
 
         let repo_root = self.get_repo_root()?;
-        let keys_dir = repo_root.join(".git").join("arcane").join("keys");
+        let keys_dir = Self::keys_dir_for(&repo_root);
         let key_path = keys_dir.join(format!("{}.age", alias));
         let pub_key_path = keys_dir.join(format!("{}.pub", alias));
 
         if key_path.exists() {
             return Err(anyhow::anyhow!("Member '{}' already exists", alias));
         }
+        self.record_membership(ManifestOp::Add, None, alias, &recipient.to_string())?;
 
-        // Save public key
-        fs::write(&pub_key_path, public_key_str)?;
+        // Save public key (canonical form, so authorized_keys options/comments are dropped)
+        fs::write(&pub_key_path, recipient.to_string())?;
 
         // Save Age key
-        self.encrypt_and_save_key(&repo_key, &recipient, &key_path)?;
+        self.encrypt_and_save_key(&repo_key, recipient.as_recipient(), &key_path)?;
 
         Ok(())
     }
 
-    /// Create an Invite for a user to join a Team
-    /// Result: arcane/invites/<team>/<invite_file>.age
-    pub fn create_team_invite(&self, team_name: &str, user_public_key: &str) -> Result<()> {
+    /// Create an invite for a user to join a Team, valid for `expires_in`.
+    /// Result: arcane/invites/<team>/<id>.age, readable only by that user
+    pub fn create_team_invite(
+        &self,
+        team_name: &str,
+        user_public_key: &str,
+        expires_in: std::time::Duration,
+    ) -> Result<PathBuf> {
         let team_key = self.load_team_key(team_name)?;
 
-        let recipient: x25519::Recipient = user_public_key
+        let recipient: RecipientKey = user_public_key
             .parse()
             .map_err(|e| anyhow::anyhow!("Invalid user public key: {}", e))?;
 
         let repo_root = self.get_repo_root()?;
+        let invite_path =
+            write_team_invite(&repo_root, team_name, &team_key, &recipient, expires_in)?;
+
+        // Remember who holds the team key, so a rotation can re-invite them
+        let mut roster = team_roster(team_name);
+        let member = recipient.to_string();
+        if !roster.contains(&member) {
+            roster.push(member);
+            write_team_roster(team_name, &roster)?;
+        }
 
-        // Use hash of pubkey for filename to avoid leakage/collisions
-        // Or just use a random ID?
-        // Let's use a safe-to-filename version of the pubkey (or first 8 chars)
-        // age1... -> age1...
-        // But better is to just generate a random invite ID.
-        let invite_id = uuid::Uuid::new_v4().to_string();
-
-        let invites_dir = repo_root.join("arcane").join("invites").join(team_name);
-        fs::create_dir_all(&invites_dir)?;
-
-        let invite_path = invites_dir.join(format!("{}.age", invite_id));
-
-        // Encrypt the TEAM KEY (bytes) for the USER
-        // We need to re-wrap TeamKey as a "RepoKey" structure just for the helper?
-        // Or just write bytes using encryptor manually.
-
-        let recipients = vec![&recipient as &dyn age::Recipient];
-        let encryptor = age::Encryptor::with_recipients(recipients.into_iter())?;
-
-        let mut file = fs::File::create(&invite_path)?;
-        let mut writer = encryptor.wrap_output(&mut file)?;
-        writer.write_all(&team_key.0)?;
-        writer.finish()?;
-
-        Ok(())
+        Ok(invite_path)
     }
 
-    /// Accept a Team Invite
-    /// Reads arbitrary invite file, decrypts it (expecting a Team Key), saves to keychain.
+    /// Accept a Team Invite: checks it was issued to you, hasn't expired and hasn't
+    /// been used before, then saves the team key to your keychain and deletes the invite.
     pub fn accept_team_invite(&self, invite_path: &Path) -> Result<String> {
         let identity = self
-            .master_identity
-            .as_ref()
+            .master()
             .context("Master identity required to accept invite")?;
 
         let encrypted_bytes = fs::read(invite_path)?;
         let decryptor = age::Decryptor::new(&encrypted_bytes[..])?;
-        let mut reader = decryptor.decrypt(std::iter::once(identity as &dyn age::Identity))?;
+        let mut reader = decryptor
+            .decrypt(std::iter::once(identity.as_identity()))
+            .context("This invite was not encrypted for your identity")?;
+
+        let mut payload = zeroize::Zeroizing::new(Vec::new());
+        reader.read_to_end(&mut payload)?;
+
+        let invite: TeamInvite = serde_json::from_slice(&payload).map_err(|_| {
+            anyhow::anyhow!(
+                "This invite has no metadata (made by an older arcane); ask for a new one"
+            )
+        })?;
+        if invite.version != TEAM_INVITE_VERSION {
+            return Err(anyhow::anyhow!(
+                "Unsupported invite version {}",
+                invite.version
+            ));
+        }
+        if invite.team.is_empty() || invite.team.contains(['/', '\\', ':']) {
+            return Err(anyhow::anyhow!("Invalid team name in invite"));
+        }
 
-        let mut key_bytes = Vec::new();
-        use std::io::Read; // Fix for E0599
-        reader.read_to_end(&mut key_bytes)?;
+        let me = identity.to_public().to_string();
+        if invite.recipient != me {
+            return Err(anyhow::anyhow!(
+                "This invite was issued to {}, not to you",
+                invite.recipient
+            ));
+        }
 
-        if key_bytes.len() != 32 {
-            return Err(anyhow::anyhow!("Invalid invite content"));
+        let expires_at = chrono::DateTime::parse_from_rfc3339(&invite.expires_at)
+            .context("Invalid expiry in invite")?;
+        if chrono::Utc::now() > expires_at {
+            return Err(anyhow::anyhow!(
+                "This invite expired on {}; ask for a new one",
+                expires_at.format("%Y-%m-%d %H:%M %Z")
+            ));
         }
 
-        // We have the raw team key. But we don't know the Team Name from the file content (bytes only).
-        // The user must provide the name, or we derive it from the invite path?
-        // Invite path: arcane/invites/<team_name>/...
-        let team_name = invite_path
+        // Nonces are recorded locally and, when the invite sits in a repo's
+        // arcane/invites/<team>/, in that directory's tracked used list
+        let mut used_paths = vec![teams_dir()?.join(format!("{}.used", invite.team))];
+        if let Some(dir) = invite_path
             .parent()
-            .and_then(|p| p.file_name())
-            .and_then(|s| s.to_str())
-            .ok_or_else(|| anyhow::anyhow!("Could not determine team name from path"))?;
+            .filter(|dir| dir.ends_with(Path::new("arcane").join("invites").join(&invite.team)))
+        {
+            used_paths.push(dir.join(USED_INVITES_FILE));
+        }
+        for used_path in &used_paths {
+            let used = fs::read_to_string(used_path).unwrap_or_default();
+            if used.lines().any(|nonce| nonce.trim() == invite.nonce) {
+                return Err(anyhow::anyhow!("This invite has already been used"));
+            }
+        }
 
-        // Save to key chain
-        let home = dirs::home_dir().context("Could not find home directory")?;
-        let team_dir = home.join(".arcane").join("teams");
-        fs::create_dir_all(&team_dir)?;
+        team_identity_from_bytes(invite.team_key.as_bytes())
+            .context("This invite holds an invalid team key")?;
+        self.save_team_key(&invite.team, &invite.team_key)?;
 
-        let team_key_path = team_dir.join(format!("{}.key", team_name));
+        for used_path in &used_paths {
+            record_used_invite(used_path, &invite.nonce)?;
+        }
+        let _ = fs::remove_file(invite_path);
 
-        if team_key_path.exists() {
-            // Overwrite? Warn?
-            // Checking if same key?
-            // For now, allow overwrite.
+        Ok(invite.team.clone())
+    }
+
+    /// Replace a team's identity. Every keyring in `repos` (default: this repo) holding
+    /// `team:<name>.age`, including environment keyrings, is re-wrapped for the new team
+    /// key and committed. When `drop` names anyone, those keys are rotated too and their
+    /// files re-sealed, since the dropped members still hold the old team key. The team's
+    /// pending invites are deleted and everyone you invited, except `drop`, gets a new
+    /// invite in every repo. Repos you can't unlock are reported and skipped.
+    pub fn rotate_team(
+        &self,
+        team_name: &str,
+        repos: &[PathBuf],
+        drop: &[String],
+        expires_in: std::time::Duration,
+    ) -> Result<TeamRotation> {
+        // Only a holder of the current key may rotate it
+        self.load_team_key(team_name)?;
+
+        let mut dropped = Vec::new();
+        for key in drop {
+            let recipient: RecipientKey = key
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid public key '{}': {}", key, e))?;
+            dropped.push(recipient.to_string());
         }
 
-        // Encrypt for local storage (Master Identity)
-        let master = self.master_identity.as_ref().unwrap();
-        let recipient = master.to_public();
+        let repos = if repos.is_empty() {
+            vec![self.get_repo_root()?]
+        } else {
+            repos.to_vec()
+        };
 
-        let recipients = vec![&recipient as &dyn age::Recipient];
-        let encryptor = age::Encryptor::with_recipients(recipients.into_iter())?;
+        let new_identity = x25519::Identity::generate();
+        let new_recipient = new_identity.to_public();
 
-        let mut file = fs::File::create(&team_key_path)?;
-        let mut writer = encryptor.wrap_output(&mut file)?;
-        writer.write_all(&key_bytes)?;
-        writer.finish()?;
+        let mut rotation = TeamRotation {
+            team: team_name.to_string(),
+            ..Default::default()
+        };
+        let current_root = self.get_repo_root().ok();
+        for repo in &repos {
+            let other;
+            let security = if current_root.as_deref() == Some(repo.as_path()) {
+                self
+            } else {
+                other = Self::new(Some(repo))?;
+                &other
+            };
+            let result = if dropped.is_empty() {
+                security.rewrap_team(team_name, &new_recipient)
+            } else {
+                let alias = format!("team:{}", team_name);
+                security
+                    .revoke_recipient(&alias, None, Some(&new_recipient))
+                    .map(|summary| {
+                        rotation.resealed += summary.resealed.len();
+                        let names = summary.keyrings.into_iter().map(|k| k.name).collect();
+                        (names, summary.commit)
+                    })
+            };
+            match result {
+                Ok((keyrings, commit)) => rotation.rewrapped.push((repo.clone(), keyrings, commit)),
+                Err(e) => rotation
+                    .unreachable
+                    .push((repo.clone(), format!("{:#}", e))),
+            }
+        }
+        rotation.rekeyed = !dropped.is_empty();
+        if rotation.rewrapped.is_empty() {
+            return Err(anyhow::anyhow!(
+                "No keyring could be re-wrapped for team '{}'; nothing was changed",
+                team_name
+            ));
+        }
+
+        // Outstanding invites carry the old key; replace them. Their nonces go on the
+        // repo's used list, so a copy restored from git history is refused too.
+        let reachable: Vec<PathBuf> = rotation
+            .rewrapped
+            .iter()
+            .map(|(repo, _, _)| repo.clone())
+            .collect();
+        for repo in &reachable {
+            let invites_dir = repo.join("arcane").join("invites").join(team_name);
+            let Ok(entries) = fs::read_dir(&invites_dir) else {
+                continue;
+            };
+            for entry in entries.filter_map(|e| e.ok()) {
+                let path = entry.path();
+                if path.extension().and_then(|e| e.to_str()) != Some("age") {
+                    continue;
+                }
+                if let Some(nonce) = path.file_stem().and_then(|s| s.to_str()) {
+                    record_used_invite(&invites_dir.join(USED_INVITES_FILE), nonce)?;
+                }
+                fs::remove_file(&path)?;
+                rotation.revoked_invites += 1;
+            }
+        }
+
+        let new_secret = new_identity.to_string();
+        let new_key = TeamKey(new_secret.expose_secret().as_bytes().to_vec());
+        let me = self
+            .master()
+            .context("Master identity required")?
+            .to_public()
+            .to_string();
+        let roster: Vec<String> = team_roster(team_name)
+            .into_iter()
+            .filter(|member| !dropped.contains(member) && *member != me)
+            .collect();
+        for member in &roster {
+            let recipient: RecipientKey = member
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid roster key '{}': {}", member, e))?;
+            // One invite, copied to every repo: accepting any copy uses up its nonce
+            let invite =
+                write_team_invite(&reachable[0], team_name, &new_key, &recipient, expires_in)?;
+            for repo in &reachable[1..] {
+                let invites_dir = repo.join("arcane").join("invites").join(team_name);
+                fs::create_dir_all(&invites_dir)?;
+                let copy = invites_dir.join(invite.file_name().context("Invalid invite path")?);
+                fs::copy(&invite, &copy)?;
+                rotation.invites.push(copy);
+            }
+            rotation.invites.push(invite);
+        }
+
+        self.save_team_key(team_name, new_secret.expose_secret())?;
+        write_team_roster(team_name, &roster)?;
 
-        Ok(team_name.to_string())
+        Ok(rotation)
+    }
+
+    /// Wrap every keyring of this repo that holds `team:<name>.age` for a new team key
+    /// and commit. Returns the keyrings ("repo" or the environment) and the commit.
+    fn rewrap_team(
+        &self,
+        team_name: &str,
+        new_recipient: &x25519::Recipient,
+    ) -> Result<(Vec<String>, Option<String>)> {
+        let repo_root = self.get_repo_root()?;
+        let alias = format!("team:{}", team_name);
+
+        let mut keyrings = vec![(None, Self::keys_dir_for(&repo_root))];
+        for env in self.list_env_keyrings()? {
+            let dir = Self::env_keys_dir_for(&repo_root, &env);
+            keyrings.push((Some(env), dir));
+        }
+        keyrings.retain(|(_, dir)| dir.join(format!("{}.age", alias)).exists());
+        if keyrings.is_empty() {
+            return Err(anyhow::anyhow!("No {}.age in this repo", alias));
+        }
+        if git_in(&repo_root, &["diff", "--cached", "--quiet"]).is_err() {
+            return Err(anyhow::anyhow!(
+                "The index has staged changes; commit or unstage them first"
+            ));
+        }
+
+        // Unlock everything before writing anything
+        let mut keys = Vec::new();
+        for (env, _) in &keyrings {
+            let key = match env {
+                None => self.load_repo_key()?,
+                Some(env) => self
+                    .load_env_key(env)?
+                    .with_context(|| format!("No keyring for '{}'", env))?,
+            };
+            keys.push(key);
+        }
+
+        let mut names = Vec::new();
+        for ((env, dir), key) in keyrings.iter().zip(&keys) {
+            self.record_membership(
+                ManifestOp::Add,
+                env.as_deref(),
+                &alias,
+                &new_recipient.to_string(),
+            )?;
+            fs::write(
+                dir.join(format!("{}.pub", alias)),
+                new_recipient.to_string(),
+            )?;
+            self.encrypt_and_save_key(key, new_recipient, &dir.join(format!("{}.age", alias)))?;
+            names.push(env.clone().unwrap_or_else(|| "repo".to_string()));
+        }
+
+        let commit =
+            self.commit_keyring_change(&repo_root, &format!("Rotate team {} key", team_name))?;
+        Ok((names, commit))
     }
 
     /// List all team members (aliases)
     pub fn list_team_members(&self) -> Result<Vec<String>> {
         let repo_root = self.get_repo_root()?;
-        let keys_dir = repo_root.join(".git").join("arcane").join("keys");
+        let keys_dir = Self::keys_dir_for(&repo_root);
 
         if !keys_dir.exists() {
             return Ok(Vec::new());
@@ -1167,23 +3601,55 @@ impl ArcaneSecurity {
             .output()
             .context("Failed to configure git-arcane.clean")?;
 
-        // smudge command
+        // smudge command
+        Command::new("git")
+            .current_dir(repo_root)
+            .args(&[
+                "config",
+                "filter.git-arcane.smudge",
+                &format!("'{}' smudge %f", exe_str),
+            ])
+            .output()
+            .context("Failed to configure git-arcane.smudge")?;
+
+        // required
+        Command::new("git")
+            .current_dir(repo_root)
+            .args(&["config", "filter.git-arcane.required", "true"])
+            .output()
+            .context("Failed to configure git-arcane.required")?;
+
+        // diff driver (decrypt blobs so `git diff` shows readable changes)
+        Command::new("git")
+            .current_dir(repo_root)
+            .args([
+                "config",
+                "diff.git-arcane.textconv",
+                &format!("'{}' textconv", exe_str),
+            ])
+            .output()
+            .context("Failed to configure git-arcane textconv")?;
+
+        // merge driver (key-by-key merge of encrypted env files)
         Command::new("git")
             .current_dir(repo_root)
-            .args(&[
+            .args([
                 "config",
-                "filter.git-arcane.smudge",
-                &format!("'{}' smudge", exe_str),
+                "merge.git-arcane.name",
+                "Arcane encrypted env merge",
             ])
             .output()
-            .context("Failed to configure git-arcane.smudge")?;
+            .context("Failed to configure git-arcane merge name")?;
 
-        // required
         Command::new("git")
             .current_dir(repo_root)
-            .args(&["config", "filter.git-arcane.required", "true"])
+            .args([
+                "config",
+                "merge.git-arcane.driver",
+                &format!("'{}' merge-driver %O %A %B %P", exe_str),
+            ])
             .output()
-            .context("Failed to configure git-arcane.required")?;
+            .context("Failed to configure git-arcane merge driver")?;
 
         // 4. Update .gitattributes (Enforce Config Source of Truth)
         let attributes_path = repo_root.join(".gitattributes");
@@ -1245,44 +3711,287 @@ impl ArcaneSecurity {
     }
 
     /// Recursively scan the repository for secrets (respecting .gitignore)
-    pub fn scan_repo(&self) -> Result<Vec<(PathBuf, Vec<SecretFinding>)>> {
+    pub fn scan_repo(&self) -> Result<ScanResults> {
         let repo_root = self.get_repo_root()?;
-        let mut findings = Vec::new();
+        let scanner = SecretScanner::for_repo(&repo_root)?;
+        Ok(Self::walk_text_files(&repo_root, |_, relative, content| {
+            let matches = scanner.scan_file(relative, content);
+            (!matches.is_empty()).then_some(matches)
+        }))
+    }
+
+    /// Record every current finding in `.arcane/scan-baseline.json`, so later scans
+    /// only report new ones. Returns the file and how many findings it holds.
+    pub fn update_scan_baseline(&self) -> Result<(PathBuf, usize)> {
+        let repo_root = self.get_repo_root()?;
+        // Everything except what's already in the baseline being replaced
+        let scanner = SecretScanner::with_config(&ScannerConfig::load(&repo_root)?)?;
+        let entries = Self::walk_text_files(&repo_root, |_, relative, content| {
+            let lines: Vec<&str> = content.lines().collect();
+            let entries: Vec<BaselineEntry> = scanner
+                .scan_file(relative, content)
+                .into_iter()
+                .map(|finding| BaselineEntry {
+                    path: relative.to_string_lossy().replace('\\', "/"),
+                    fingerprint: finding_fingerprint(relative, &finding, lines[finding.line - 1]),
+                    rule: finding.name,
+                })
+                .collect();
+            Some(entries)
+        });
+        let mut baseline = ScanBaseline {
+            findings: entries.into_iter().flat_map(|(_, e)| e).collect(),
+            ..Default::default()
+        };
+        baseline.save(&repo_root)?;
+        Ok((repo_root.join(SCAN_BASELINE_FILE), baseline.findings.len()))
+    }
 
-        let walker = ignore::WalkBuilder::new(&repo_root)
+    /// Call `f(path, path relative to the repo root, content)` for every text file in
+    /// the repo that .gitignore doesn't exclude, on all cores. Returns what it returned
+    /// per file, sorted by path. Binary files and files over `MAX_SCAN_FILE_BYTES` are
+    /// skipped.
+    fn walk_text_files<T: Send>(
+        repo_root: &Path,
+        f: impl Fn(&Path, &Path, &str) -> Option<T> + Sync,
+    ) -> Vec<(PathBuf, T)> {
+        let results = std::sync::Mutex::new(Vec::new());
+        ignore::WalkBuilder::new(repo_root)
             .hidden(false) // Don't skip hidden files automatically (we want .env)
             .git_ignore(true) // DO respect .gitignore (skip node_modules)
-            .build();
-
-        for result in walker {
-            match result {
-                Ok(entry) => {
-                    if entry.file_type().map_or(false, |ft| ft.is_file()) {
-                        let path = entry.path();
-
-                        // Explicitly skip .git/ directory content if it somehow leaks through
-                        // (WalkBuilder usually handles this, but .git/arcane might be interesting? No, don't scan internal storage)
-                        if path.components().any(|c| c.as_os_str() == ".git") {
-                            continue;
+            // Never descend into internal storage (.git/arcane holds keys)
+            .filter_entry(|entry| entry.file_name() != ".git")
+            .build_parallel()
+            .run(|| {
+                Box::new(|result| {
+                    let entry = match result {
+                        Ok(entry) => entry,
+                        Err(err) => {
+                            eprintln!("Scan warning: {}", err);
+                            return ignore::WalkState::Continue;
                         }
+                    };
+                    if !entry.file_type().is_some_and(|ft| ft.is_file())
+                        || entry
+                            .metadata()
+                            .map_or(true, |m| m.len() > MAX_SCAN_FILE_BYTES)
+                    {
+                        return ignore::WalkState::Continue;
+                    }
 
-                        // Try reading as text. If it fails (binary), we skip.
-                        // We also likely want to skip the identity file itself if it's in the repo?
-                        // But finding it would be good (it shouldn't be in repo).
-                        if let Ok(content) = fs::read_to_string(path) {
-                            let matches = self.scan_content(&content);
-                            if !matches.is_empty() {
-                                findings.push((path.to_path_buf(), matches));
-                            }
+                    let path = entry.path();
+                    let Ok(data) = fs::read(path) else {
+                        return ignore::WalkState::Continue;
+                    };
+                    if looks_binary(&data) {
+                        return ignore::WalkState::Continue;
+                    }
+                    if let Ok(content) = String::from_utf8(data) {
+                        let relative = path.strip_prefix(repo_root).unwrap_or(path);
+                        if let Some(result) = f(path, relative, &content) {
+                            results
+                                .lock()
+                                .expect("scan results lock")
+                                .push((path.to_path_buf(), result));
                         }
                     }
+                    ignore::WalkState::Continue
+                })
+            });
+
+        let mut results = results.into_inner().expect("scan results lock");
+        results.sort_by(|a, b| a.0.cmp(&b.0));
+        results
+    }
+
+    /// Scan every blob added or changed by the commits reachable from `refs` (all refs
+    /// if empty), except those reachable from `since`. Each blob is read once, sealed
+    /// blobs are skipped, and a finding is reported only for the oldest commit it
+    /// appears in.
+    pub fn scan_history(
+        &self,
+        refs: &[String],
+        since: Option<&str>,
+    ) -> Result<Vec<HistoryFinding>> {
+        use std::io::BufRead;
+        use std::process::Stdio;
+
+        let repo_root = self.get_repo_root()?;
+        let scanner = SecretScanner::for_repo(&repo_root)?;
+
+        // Oldest first, so the first commit a blob shows up in is the one that added it
+        let mut args: Vec<String> = [
+            "log",
+            "--reverse",
+            "--raw",
+            "--no-abbrev",
+            "--no-renames",
+            "--diff-filter=AMT",
+            // NUL-terminated raw entries, so paths arrive unquoted
+            "-z",
+            "--format=%x01%H%x1f%an <%ae>%x1f%aI",
+        ]
+        .iter()
+        .map(|a| a.to_string())
+        .collect();
+        if refs.is_empty() {
+            args.push("--all".to_string());
+        } else {
+            args.extend(refs.iter().cloned());
+        }
+        if let Some(since) = since {
+            args.push(format!("^{}", since));
+        }
+        args.push("--".to_string());
+
+        let mut log = std::process::Command::new("git")
+            .args(&args)
+            .current_dir(&repo_root)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Failed to run git log")?;
+
+        // Drained on its own thread, so a chatty git can't fill the pipe and stall
+        let mut stderr = log.stderr.take().context("git log stderr unavailable")?;
+        let errors = std::thread::spawn(move || {
+            let mut text = String::new();
+            let _ = stderr.read_to_string(&mut text);
+            text
+        });
+
+        let mut commits: Vec<(String, String, String)> = Vec::new();
+        // (blob, path, index into commits), in the order they were introduced
+        let mut blobs: Vec<(String, PathBuf, usize)> = Vec::new();
+        let mut seen = HashSet::new();
+        let stdout = log.stdout.take().context("git log stdout unavailable")?;
+        // Records: "\x01<header>", then ":<old mode> <new mode> <old blob> <new blob>
+        // <status>" and "<path>" per file, each NUL-terminated
+        let mut records = std::io::BufReader::new(stdout).split(0);
+        while let Some(record) = records.next() {
+            let record = String::from_utf8_lossy(&record?).into_owned();
+            let record = record.trim_start_matches('\n');
+            if let Some(header) = record.strip_prefix('\u{1}') {
+                let mut parts = header.splitn(3, '\u{1f}');
+                let mut next = || parts.next().unwrap_or_default().to_string();
+                commits.push((next(), next(), next()));
+            } else if let Some(meta) = record.strip_prefix(':') {
+                let Some(path) = records.next().transpose()? else {
+                    break;
+                };
+                let path = PathBuf::from(String::from_utf8_lossy(&path).into_owned());
+                let fields: Vec<&str> = meta.split(' ').collect();
+                let (Some(mode), Some(blob)) = (fields.get(1), fields.get(3)) else {
+                    continue;
+                };
+                // Submodules point at commits, not blobs
+                if *mode == "160000" || commits.is_empty() {
+                    continue;
                 }
-                Err(err) => eprintln!("Scan warning: {}", err),
+                if !scanner.is_path_allowed(&path) && seen.insert(blob.to_string()) {
+                    blobs.push((blob.to_string(), path, commits.len() - 1));
+                }
+            }
+        }
+        let status = log.wait()?;
+        let stderr = errors.join().unwrap_or_default();
+        if !status.success() {
+            return Err(anyhow::anyhow!("git log failed: {}", stderr.trim()));
+        }
+
+        // Stream the blobs through one `git cat-file --batch`, fed from another thread
+        // so neither side blocks on a full pipe
+        let mut cat = std::process::Command::new("git")
+            .args(["cat-file", "--batch"])
+            .current_dir(&repo_root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("Failed to run git cat-file")?;
+        let mut stdin = cat.stdin.take().context("git stdin unavailable")?;
+        let ids: Vec<String> = blobs.iter().map(|(blob, _, _)| blob.clone()).collect();
+        let feeder = std::thread::spawn(move || -> std::io::Result<()> {
+            for id in ids {
+                writeln!(stdin, "{}", id)?;
+            }
+            Ok(())
+        });
+
+        let mut reader =
+            std::io::BufReader::new(cat.stdout.take().context("git stdout unavailable")?);
+        let mut reported = HashSet::new();
+        let mut findings = Vec::new();
+        for (blob, path, commit) in &blobs {
+            let mut header = String::new();
+            reader.read_line(&mut header)?;
+            let size: usize = match header.split_whitespace().collect::<Vec<_>>()[..] {
+                [_, "blob", size] => size.parse()?,
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "Unexpected git cat-file output for {}",
+                        blob
+                    ))
+                }
+            };
+            let mut data = vec![0u8; size + 1];
+            reader.read_exact(&mut data)?;
+            data.truncate(size);
+
+            if Self::is_sealed(&data) || size as u64 > MAX_SCAN_FILE_BYTES || looks_binary(&data) {
+                continue;
+            }
+            let Ok(content) = String::from_utf8(data) else {
+                continue;
+            };
+            let lines: Vec<&str> = content.lines().collect();
+            let new: Vec<SecretFinding> = scanner
+                .scan_file(path, &content)
+                .into_iter()
+                .filter(|f| reported.insert(finding_fingerprint(path, f, lines[f.line - 1])))
+                .collect();
+            if !new.is_empty() {
+                let (hash, author, date) = &commits[*commit];
+                findings.push(HistoryFinding {
+                    commit: hash.clone(),
+                    author: author.clone(),
+                    date: date.clone(),
+                    blob: blob.clone(),
+                    path: path.clone(),
+                    findings: new,
+                });
             }
         }
+        feeder
+            .join()
+            .map_err(|_| anyhow::anyhow!("git cat-file feeder panicked"))??;
+        cat.wait()?;
         Ok(findings)
     }
 
+    /// Scan what `git commit` would record: the lines added in the index, with this
+    /// repo's `.arcane/scanner.toml`. Sealed files are binary in the diff and skipped.
+    pub fn scan_staged(&self) -> Result<ScanResults> {
+        let repo_root = self.get_repo_root()?;
+        let scanner = SecretScanner::for_repo(&repo_root)?;
+        let diff = git_in(
+            &repo_root,
+            &[
+                // Paths with non-ASCII characters as they are, not octal-escaped
+                "-c",
+                "core.quotePath=false",
+                "diff",
+                "--cached",
+                "--no-textconv",
+                "--no-ext-diff",
+                "--no-color",
+                // One line of context, for `arcane:allow` on an unchanged line above
+                "-U1",
+                "--diff-filter=ACMR",
+            ],
+        )?;
+        Ok(scanner.scan_diff(&String::from_utf8_lossy(&diff)))
+    }
+
     /// Scan content for secrets
     pub fn scan_content(&self, content: &str) -> Vec<SecretFinding> {
         self.scanner.scan(content)
@@ -1291,7 +4000,24 @@ impl ArcaneSecurity {
     /// Git Clean Filter: Encrypt stdin -> stdout
     /// If file_path is provided and matches .env pattern, create a plaintext backup.
     pub fn seal_clean(&self, file_path: Option<&str>) -> Result<()> {
-        use std::io::{Read, Write};
+        // Environment files with their own keyring are sealed with that key
+        if let Some(env) = file_path.and_then(crate::config::env::env_name_for_path) {
+            match self.load_env_key(env) {
+                Ok(Some(env_key)) => return self.seal_clean_with(&env_key, file_path),
+                Ok(None) => {}
+                Err(e) => {
+                    // Without access the checkout holds ciphertext, which passes through
+                    use std::io::{Read, Write};
+                    let mut buffer = Vec::new();
+                    std::io::stdin().read_to_end(&mut buffer)?;
+                    if !Self::is_sealed(&buffer) && !self.fields_all_sealed(file_path, &buffer) {
+                        return Err(e);
+                    }
+                    std::io::stdout().write_all(&buffer)?;
+                    return Ok(());
+                }
+            }
+        }
 
         // Auto-init if no key found (enables global .gitattributes config)
         let repo_key = match self.load_repo_key() {
@@ -1306,12 +4032,24 @@ impl ArcaneSecurity {
             }
         };
 
+        self.seal_clean_with(&repo_key, file_path)
+    }
+
+    fn seal_clean_with(&self, repo_key: &RepoKey, file_path: Option<&str>) -> Result<()> {
+        use std::io::{Read, Write};
+
         // 1. Read plaintext from stdin
         let mut buffer = Vec::new();
         std::io::stdin().read_to_end(&mut buffer)?;
 
-        // 2. Encrypt
-        let encrypted = self.encrypt_with_repo_key(&repo_key, &buffer)?;
+        // Already sealed (e.g. a checkout without access): never double-encrypt
+        if Self::is_sealed(&buffer) {
+            std::io::stdout().write_all(&buffer)?;
+            return Ok(());
+        }
+
+        // 2. Encrypt (deterministic, so unchanged files keep the same blob)
+        let encrypted = self.seal_with(repo_key, file_path, &buffer)?;
 
         // 3. Backup if necessary (Safety Net for .env files)
         if let Some(path) = file_path {
@@ -1327,6 +4065,93 @@ impl ArcaneSecurity {
         Ok(())
     }
 
+    /// Seal content like the clean filter does: structured config with secret keys
+    /// value by value, anything else whole
+    fn seal_with(
+        &self,
+        repo_key: &RepoKey,
+        file_path: Option<&str>,
+        content: &[u8],
+    ) -> Result<Vec<u8>> {
+        // Structured config: only the secret values are sealed, the rest stays readable
+        let format = file_path.and_then(|p| StructuredFormat::from_path(Path::new(p)));
+        if let Some(format) = format {
+            if let Some(sealed) = self.seal_fields(repo_key, format, content)? {
+                return Ok(sealed.into_bytes());
+            }
+        }
+        self.encrypt_deterministic_with_repo_key(repo_key, content)
+    }
+
+    /// Whether a YAML/JSON/TOML document has sealed values and no secret value left in
+    /// plaintext, so it can be committed as is by someone without the key
+    fn fields_all_sealed(&self, file_path: Option<&str>, content: &[u8]) -> bool {
+        let Some(format) = file_path.and_then(|p| StructuredFormat::from_path(Path::new(p))) else {
+            return false;
+        };
+        let Ok(text) = std::str::from_utf8(content) else {
+            return false;
+        };
+        let rules = self
+            .get_repo_root()
+            .and_then(|root| FieldsConfig::load(&root))
+            .and_then(|config| config.rules());
+        let Ok(rules) = rules else {
+            return false;
+        };
+        // Existing markers are kept; any plaintext secret would need the key
+        let sealed = fields::seal_fields(text, format, &rules, |_| {
+            Err(anyhow::anyhow!("Secret value in plaintext"))
+        });
+        fields::has_sealed_fields(text) && matches!(sealed, Ok(Some(_)))
+    }
+
+    /// Seal new content for a file (relative to the repo root) with the key the clean
+    /// filter would use, so it can be written to the working tree already sealed
+    pub fn seal_for_path(&self, path: &str, content: &[u8]) -> Result<Vec<u8>> {
+        let key = self.sealing_key_for_path(path)?;
+        self.seal_with(&key, Some(path), content)
+    }
+
+    /// Seal the values of secret keys (per `.arcane/fields.toml`) in a YAML/JSON/TOML
+    /// document. `None` if it has none, or isn't text: the file is then sealed whole.
+    fn seal_fields(
+        &self,
+        repo_key: &RepoKey,
+        format: StructuredFormat,
+        content: &[u8],
+    ) -> Result<Option<String>> {
+        let Ok(text) = std::str::from_utf8(content) else {
+            return Ok(None);
+        };
+        let rules = FieldsConfig::load(&self.get_repo_root()?)?.rules()?;
+        fields::seal_fields(text, format, &rules, |value| {
+            self.encrypt_deterministic_with_repo_key(repo_key, value)
+        })
+    }
+
+    /// Decrypt the sealed values in a YAML/JSON/TOML document. `repo_key` is tried
+    /// first; otherwise each value's header names the key that opens it.
+    pub fn open_fields(
+        &self,
+        content: &str,
+        format: StructuredFormat,
+        repo_key: Option<&RepoKey>,
+    ) -> Result<String> {
+        let mut loaded: Option<RepoKey> = None;
+        fields::open_fields(content, format, |blob| {
+            if let Some(key) = repo_key.or(loaded.as_ref()) {
+                if let Ok(plaintext) = self.decrypt_with_repo_key(key, blob) {
+                    return Ok(plaintext);
+                }
+            }
+            let key = self.load_repo_key_for_blob(blob)?;
+            let plaintext = self.decrypt_with_repo_key(&key, blob)?;
+            loaded = Some(key);
+            Ok(plaintext)
+        })
+    }
+
     fn backup_secret(&self, original_path: &str, content: &[u8]) -> Result<()> {
         let repo_root = self.get_repo_root()?;
         let backup_dir = repo_root.join(".git").join("arcane").join("backups");
@@ -1342,13 +4167,12 @@ impl ArcaneSecurity {
         let backup_path = backup_dir.join(format!("{}.{}.bak.age", safe_name, timestamp));
 
         let identity = self
-            .master_identity
-            .as_ref()
+            .master()
             .context("Master identity required for secure backup")?;
         let recipient = identity.to_public();
 
         // Encrypt with Master Key
-        let recipients = vec![&recipient as &dyn age::Recipient];
+        let recipients = vec![recipient.as_recipient()];
         let encryptor = age::Encryptor::with_recipients(recipients.into_iter())
             .context("Failed to create encryptor for backup")?;
 
@@ -1405,14 +4229,13 @@ impl ArcaneSecurity {
         }
 
         let identity = self
-            .master_identity
-            .as_ref()
+            .master()
             .context("Master identity required to restore")?;
 
         // Decrypt
         let encrypted_bytes = fs::read(&backup_path)?;
         let decryptor = age::Decryptor::new(&encrypted_bytes[..])?;
-        let mut reader = decryptor.decrypt(std::iter::once(identity as &dyn age::Identity))?;
+        let mut reader = decryptor.decrypt(std::iter::once(identity.as_identity()))?;
 
         let mut plaintext = Vec::new();
         use std::io::Read;
@@ -1433,15 +4256,64 @@ impl ArcaneSecurity {
     }
 
     /// Git Smudge Filter: Decrypt stdin -> stdout
-    pub fn seal_smudge(&self) -> Result<()> {
+    /// `file_path` is missing when the filter was set up by an older version.
+    pub fn seal_smudge(&self, file_path: Option<&str>) -> Result<()> {
         use std::io::{Read, Write};
 
-        let repo_key = self.load_repo_key()?;
-
         // 1. Read ciphertext from stdin
         let mut buffer = Vec::new();
         std::io::stdin().read_to_end(&mut buffer)?;
 
+        // Structured config with sealed values: open them in place
+        let structured =
+            file_path.is_none_or(|p| StructuredFormat::from_path(Path::new(p)).is_some());
+        if structured && !Self::is_sealed(&buffer) {
+            if let Some(text) = std::str::from_utf8(&buffer)
+                .ok()
+                .filter(|text| fields::has_sealed_fields(text))
+            {
+                let format = file_path
+                    .and_then(|p| StructuredFormat::from_path(Path::new(p)))
+                    .unwrap_or_else(|| StructuredFormat::detect(text));
+                match self.open_fields(text, format, None) {
+                    Ok(opened) => std::io::stdout().write_all(opened.as_bytes())?,
+                    Err(e) => {
+                        // Like a sealed file: values we can't open stay sealed in the checkout
+                        let env = fields::sealed_field_blobs(text).iter().find_map(|blob| {
+                            SealHeader::parse(blob)
+                                .ok()
+                                .flatten()
+                                .and_then(|h| self.env_for_fingerprint(&h.key_fingerprint))
+                        });
+                        let Some(env) = env else {
+                            return Err(e);
+                        };
+                        eprintln!(
+                            "🔒 No access to '{}' secrets; leaving values encrypted",
+                            env
+                        );
+                        std::io::stdout().write_all(&buffer)?;
+                    }
+                }
+                return Ok(());
+            }
+        }
+
+        let repo_key = match self.load_repo_key_for_blob(&buffer) {
+            Ok(key) => key,
+            Err(e) => {
+                // Environment secrets we aren't a recipient of stay encrypted in the checkout
+                let env = SealHeader::parse(&buffer)?
+                    .and_then(|h| self.env_for_fingerprint(&h.key_fingerprint));
+                if let Some(env) = env {
+                    eprintln!("🔒 No access to '{}' secrets; leaving file encrypted", env);
+                    std::io::stdout().write_all(&buffer)?;
+                    return Ok(());
+                }
+                return Err(e);
+            }
+        };
+
         // 2. Decrypt
         let plaintext = self.decrypt_with_repo_key(&repo_key, &buffer)?;
 
@@ -1450,6 +4322,118 @@ impl ArcaneSecurity {
         Ok(())
     }
 
+    /// Git textconv driver: print the decrypted content of a file to stdout.
+    /// With `ARCANE_DIFF_MASK=1`, values are replaced by a short keyed hash so reviewers
+    /// can see which keys changed without seeing the secrets.
+    pub fn textconv(&self, path: &Path) -> Result<()> {
+        use std::io::Write;
+
+        let content = fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+
+        let (plaintext, repo_key) = if Self::is_sealed(&content) {
+            let key = self.load_repo_key_for_blob(&content)?;
+            (self.decrypt_with_repo_key(&key, &content)?, Some(key))
+        } else {
+            // Working tree copies are already plaintext; legacy blobs may still decrypt
+            match self.load_repo_key() {
+                Ok(key) => match self.decrypt_with_repo_key(&key, &content) {
+                    Ok(plaintext) => (plaintext, Some(key)),
+                    Err(_) => (content, Some(key)),
+                },
+                Err(_) => (content, None),
+            }
+        };
+
+        let mask = std::env::var("ARCANE_DIFF_MASK")
+            .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+
+        // Sealed values in structured config are opened; masked, they stay as they are
+        let plaintext = match std::str::from_utf8(&plaintext) {
+            Ok(text) if !mask && fields::has_sealed_fields(text) => {
+                let format = StructuredFormat::from_path(path)
+                    .unwrap_or_else(|| StructuredFormat::detect(text));
+                self.open_fields(text, format, repo_key.as_ref())?
+                    .into_bytes()
+            }
+            _ => plaintext,
+        };
+
+        let output = if mask {
+            let key = repo_key.context("Repo key required to mask values")?;
+            let text = String::from_utf8_lossy(&plaintext);
+            crate::config::env::mask_env_values(&text, |value| {
+                Self::value_tag(&key, value)
+                    .map(|tag| format!("****({})", tag))
+                    .unwrap_or_else(|_| "****".to_string())
+            })
+            .into_bytes()
+        } else {
+            plaintext
+        };
+
+        std::io::stdout().write_all(&output)?;
+        Ok(())
+    }
+
+    /// Git merge driver: three-way merge of sealed env files, key by key.
+    /// Writes the re-sealed result to `ours` (as git expects) and returns the
+    /// conflicting keys. Conflicts are left as markers inside the sealed file.
+    pub fn merge_driver(
+        &self,
+        base: &Path,
+        ours: &Path,
+        theirs: &Path,
+        path: Option<&str>,
+    ) -> Result<Vec<String>> {
+        let repo_key = match path {
+            Some(path) => self.sealing_key_for_path(path)?,
+            None => self.load_repo_key()?,
+        };
+
+        let base_text = self.read_for_merge(base)?;
+        let ours_text = self.read_for_merge(ours)?;
+        let theirs_text = self.read_for_merge(theirs)?;
+
+        let merged = crate::config::env::merge_env(&base_text, &ours_text, &theirs_text);
+
+        let sealed =
+            self.encrypt_deterministic_with_repo_key(&repo_key, merged.content.as_bytes())?;
+        fs::write(ours, sealed).with_context(|| format!("Failed to write {:?}", ours))?;
+
+        Ok(merged.conflicts)
+    }
+
+    /// Read one merge stage: sealed, legacy ciphertext or plaintext
+    fn read_for_merge(&self, path: &Path) -> Result<String> {
+        let content = fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+        if content.is_empty() {
+            return Ok(String::new());
+        }
+
+        let plaintext = if Self::is_sealed(&content) {
+            let key = self.load_repo_key_for_blob(&content)?;
+            self.decrypt_with_repo_key(&key, &content)?
+        } else {
+            let key = self.load_repo_key()?;
+            self.decrypt_with_repo_key(&key, &content)
+                .unwrap_or(content)
+        };
+
+        String::from_utf8(plaintext).context("Merged file is not valid UTF-8 text")
+    }
+
+    /// Short keyed hash of a value. Equal values give equal tags, but tags can't be
+    /// brute-forced without the repo key.
+    pub fn value_tag(repo_key: &RepoKey, value: &str) -> Result<String> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&repo_key.0)
+            .map_err(|e| anyhow::anyhow!("Invalid repo key: {}", e))?;
+        mac.update(b"arcane-diff-mask-v1");
+        mac.update(value.as_bytes());
+        let tag = mac.finalize().into_bytes();
+        Ok(hex::encode(&tag[..4]))
+    }
+
     /// Generate a new symmetric key for a repository
     pub fn generate_repo_key(&self) -> Result<RepoKey> {
         let mut key_bytes = [0u8; REPO_KEY_LEN];
@@ -1466,22 +4450,118 @@ impl ArcaneSecurity {
         rand::rng().fill_bytes(&mut nonce_bytes);
         let nonce = Nonce::from_slice(&nonce_bytes); // 96-bits; unique per message
 
+        // The header is authenticated as associated data
+        let header = SealHeader::new(repo_key).to_bytes();
+        let ciphertext = cipher
+            .encrypt(
+                nonce,
+                Payload {
+                    msg: data,
+                    aad: &header,
+                },
+            )
+            .map_err(|e| anyhow::anyhow!("Encryption failure: {}", e))?;
+
+        // header || nonce || ciphertext
+        let mut result = header;
+        result.extend_from_slice(&nonce_bytes);
+        result.extend(ciphertext);
+        Ok(result)
+    }
+
+    /// Encrypt data deterministically using the repo key (AES-GCM, synthetic nonce)
+    ///
+    /// The nonce is an HMAC-SHA256 of the plaintext under a subkey derived from the
+    /// repo key, so identical plaintext always produces identical ciphertext. The output
+    /// layout (`header || nonce || ciphertext`) is the same as `encrypt_with_repo_key`.
+    pub fn encrypt_deterministic_with_repo_key(
+        &self,
+        repo_key: &RepoKey,
+        data: &[u8],
+    ) -> Result<Vec<u8>> {
+        let nonce_bytes = Self::synthetic_nonce(repo_key, data)?;
+        let nonce = Nonce::from_slice(&nonce_bytes);
+
+        let key = Key::<Aes256Gcm>::from_slice(&repo_key.0);
+        let cipher = Aes256Gcm::new(key);
+
+        let header = SealHeader::new(repo_key).to_bytes();
         let ciphertext = cipher
-            .encrypt(nonce, data)
+            .encrypt(
+                nonce,
+                Payload {
+                    msg: data,
+                    aad: &header,
+                },
+            )
             .map_err(|e| anyhow::anyhow!("Encryption failure: {}", e))?;
 
-        // Prepend nonce to ciphertext
-        let mut result = nonce_bytes.to_vec();
+        let mut result = header;
+        result.extend_from_slice(&nonce_bytes);
         result.extend(ciphertext);
         Ok(result)
     }
 
-    /// Decrypt data using the repo key
+    /// Derive the nonce for deterministic encryption from the plaintext
+    fn synthetic_nonce(repo_key: &RepoKey, data: &[u8]) -> Result<[u8; NONCE_LEN]> {
+        // Never use the repo key directly as the MAC key: derive a dedicated subkey
+        let mut kdf = <Hmac<Sha256> as Mac>::new_from_slice(&repo_key.0)
+            .map_err(|e| anyhow::anyhow!("Invalid repo key: {}", e))?;
+        kdf.update(SIV_NONCE_CONTEXT);
+        let mut subkey = kdf.finalize().into_bytes();
+
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&subkey)
+            .map_err(|e| anyhow::anyhow!("Invalid nonce subkey: {}", e))?;
+        subkey.zeroize();
+        mac.update(data);
+        let tag = mac.finalize().into_bytes();
+
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce.copy_from_slice(&tag[..NONCE_LEN]);
+        Ok(nonce)
+    }
+
+    /// Returns true if the data starts with an Arcane seal header
+    pub fn is_sealed(data: &[u8]) -> bool {
+        data.starts_with(SEAL_MAGIC)
+    }
+
+    /// Decrypt data using the repo key.
+    /// Reads both headered blobs and legacy `nonce || ciphertext` blobs.
     pub fn decrypt_with_repo_key(
         &self,
         repo_key: &RepoKey,
         encrypted_data: &[u8],
     ) -> Result<Vec<u8>> {
+        if let Some(header) = SealHeader::parse(encrypted_data)? {
+            if header.key_fingerprint != repo_key.fingerprint() {
+                return Err(anyhow::anyhow!(
+                    "File was sealed with a different key (fingerprint {})",
+                    header.key_fingerprint_hex()
+                ));
+            }
+
+            let body = &encrypted_data[SEAL_HEADER_LEN..];
+            if body.len() < NONCE_LEN {
+                return Err(anyhow::anyhow!("Invalid ciphertext length"));
+            }
+            let nonce = Nonce::from_slice(&body[..NONCE_LEN]);
+
+            let key = Key::<Aes256Gcm>::from_slice(&repo_key.0);
+            let cipher = Aes256Gcm::new(key);
+
+            return cipher
+                .decrypt(
+                    nonce,
+                    Payload {
+                        msg: &body[NONCE_LEN..],
+                        aad: &encrypted_data[..SEAL_HEADER_LEN],
+                    },
+                )
+                .map_err(|e| anyhow::anyhow!("Decryption failure: {}", e));
+        }
+
+        // Legacy format: nonce || ciphertext
         if encrypted_data.len() < 12 {
             // Graceful fallback: If data is too short, it might be plain text or empty.
             // For filter, error to be safe.
@@ -1501,3 +4581,499 @@ impl ArcaneSecurity {
         Ok(plaintext)
     }
 }
+
+/// Copy a directory tree (keyring files and history snapshots)
+fn copy_dir_recursive(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir_recursive(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Run git in the repo and return its stdout, failing on a non-zero exit
+fn git_in(repo_root: &Path, args: &[&str]) -> Result<Vec<u8>> {
+    let output = std::process::Command::new("git")
+        .args(args)
+        .current_dir(repo_root)
+        .output()
+        .with_context(|| format!("Failed to run git {}", args.join(" ")))?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// Run git with `input` on stdin and return its stdout as text
+fn git_in_with_input(repo_root: &Path, args: &[&str], input: &[u8]) -> Result<String> {
+    use std::process::Stdio;
+
+    let mut child = std::process::Command::new("git")
+        .args(args)
+        .current_dir(repo_root)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run git {}", args.join(" ")))?;
+    child
+        .stdin
+        .take()
+        .context("git stdin unavailable")?
+        .write_all(input)?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Paths marked `filter: git-arcane` in `git check-attr -z` output
+/// (NUL-separated `path, attribute, value` triples)
+pub(crate) fn filtered_paths(check_attr_output: &str) -> Vec<String> {
+    let fields: Vec<&str> = check_attr_output.split('\0').collect();
+    fields
+        .chunks(3)
+        .filter(|c| c.len() == 3 && c[1] == "filter" && c[2] == "git-arcane")
+        .map(|c| c[0].to_string())
+        .collect()
+}
+
+/// Remove `<alias>.age` / `<alias>.pub` from every history snapshot
+fn purge_alias_from_history(history_dir: &Path, alias: &str) -> Result<usize> {
+    if !history_dir.is_dir() {
+        return Ok(0);
+    }
+    let mut purged = 0;
+    for entry in fs::read_dir(history_dir)? {
+        let dir = entry?.path();
+        if !dir.is_dir() {
+            continue;
+        }
+        let mut touched = false;
+        for ext in ["age", "pub"] {
+            let file = dir.join(format!("{}.{}", alias, ext));
+            if file.exists() {
+                fs::remove_file(file)?;
+                touched = true;
+            }
+        }
+        if touched {
+            purged += 1;
+        }
+    }
+    Ok(purged)
+}
+
+/// Rules named by an `arcane:allow` or `arcane:allow(Rule Name, ...)` comment on the
+/// line; an empty list allows every rule
+fn inline_allow(line: &str) -> Option<Vec<String>> {
+    let rest = &line[line.find("arcane:allow")? + "arcane:allow".len()..];
+    let rules = rest
+        .strip_prefix('(')
+        .and_then(|r| r.split_once(')'))
+        .map(|(names, _)| {
+            names
+                .split(',')
+                .map(|n| n.trim().to_string())
+                .filter(|n| !n.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+    Some(rules)
+}
+
+/// Baseline fingerprint of a finding: its file, rule and the trimmed line it's on, with
+/// the match replaced by its redacted form. It survives lines moving but (usually) not
+/// the secret changing, and is derived only from what a finding already shows: a
+/// hash of the real line could be brute-forced back to a short secret.
+pub fn finding_fingerprint(path: &Path, finding: &SecretFinding, line: &str) -> String {
+    use sha2::Digest;
+    let path = path.to_string_lossy().replace('\\', "/");
+    let masked = match (
+        line.get(..finding.range.start),
+        line.get(finding.range.end..),
+    ) {
+        (Some(before), Some(after)) => format!("{}{}{}", before, finding.redacted, after),
+        _ => finding.redacted.clone(),
+    };
+    let mut hasher = Sha256::new();
+    for part in [path.as_str(), finding.name.as_str(), masked.trim()] {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    hex::encode(&hasher.finalize()[..16])
+}
+
+/// Binary files have a NUL early on; git sniffs the same way
+pub(crate) fn looks_binary(data: &[u8]) -> bool {
+    data[..data.len().min(BINARY_SNIFF_BYTES)].contains(&0)
+}
+
+/// Shortest literal worth prefiltering on; shorter ones match nearly every line
+const PREFILTER_MIN_LITERAL: usize = 3;
+/// Rules needing more alternative literals than this just always run
+const PREFILTER_MAX_LITERALS: usize = 64;
+
+/// One Aho-Corasick pass over a line finds the rules that can match it
+struct Prefilter {
+    automaton: aho_corasick::AhoCorasick,
+    /// Rules (by index) each automaton pattern stands for
+    pattern_rules: Vec<Vec<usize>>,
+    /// Rules with no usable literal, run on every line
+    always: Vec<usize>,
+}
+
+impl Prefilter {
+    fn new(rules: &[ScanRule]) -> Option<Self> {
+        let mut patterns: Vec<Vec<u8>> = Vec::new();
+        let mut pattern_rules: Vec<Vec<usize>> = Vec::new();
+        let mut always = Vec::new();
+        for (i, rule) in rules.iter().enumerate() {
+            let Some(literals) = required_literals(rule.regex.as_str()) else {
+                always.push(i);
+                continue;
+            };
+            for literal in literals {
+                match patterns.iter().position(|p| *p == literal) {
+                    Some(existing) => pattern_rules[existing].push(i),
+                    None => {
+                        patterns.push(literal);
+                        pattern_rules.push(vec![i]);
+                    }
+                }
+            }
+        }
+        let automaton = aho_corasick::AhoCorasick::builder()
+            .ascii_case_insensitive(true)
+            .build(&patterns)
+            .ok()?;
+        Some(Self {
+            automaton,
+            pattern_rules,
+            always,
+        })
+    }
+
+    /// Rules worth running on `line`, in rule order
+    fn candidates(&self, line: &str) -> Vec<usize> {
+        let mut rules = self.always.clone();
+        for m in self.automaton.find_overlapping_iter(line) {
+            rules.extend(&self.pattern_rules[m.pattern().as_usize()]);
+        }
+        rules.sort_unstable();
+        rules.dedup();
+        rules
+    }
+}
+
+/// Lowercased literals one of which starts (or else ends) every match of `pattern`,
+/// or `None` if there's no small set of them
+fn required_literals(pattern: &str) -> Option<Vec<Vec<u8>>> {
+    use regex_syntax::hir::literal::{ExtractKind, Extractor};
+
+    let hir = regex_syntax::parse(pattern).ok()?;
+    [ExtractKind::Prefix, ExtractKind::Suffix]
+        .into_iter()
+        .find_map(|kind| {
+            let prefix = matches!(kind, ExtractKind::Prefix);
+            // Case-insensitive patterns expand into many variants that lowercase
+            // back into a few
+            let seq = Extractor::new().kind(kind).limit_total(4096).extract(&hir);
+            let mut literals: Vec<Vec<u8>> = seq
+                .literals()?
+                .iter()
+                .map(|l| l.as_bytes().to_ascii_lowercase())
+                .collect();
+            literals.sort();
+            literals.dedup();
+            // Too many alternatives (`xoxb-` then digits): what they all share will do
+            if literals.len() > PREFILTER_MAX_LITERALS {
+                let common = literals.iter().skip(1).fold(literals[0].clone(), |acc, l| {
+                    let shared = if prefix {
+                        acc.iter().zip(l).take_while(|(a, b)| a == b).count()
+                    } else {
+                        acc.iter()
+                            .rev()
+                            .zip(l.iter().rev())
+                            .take_while(|(a, b)| a == b)
+                            .count()
+                    };
+                    if prefix {
+                        acc[..shared].to_vec()
+                    } else {
+                        acc[acc.len() - shared..].to_vec()
+                    }
+                });
+                literals = vec![common];
+            }
+            let usable =
+                !literals.is_empty() && literals.iter().all(|l| l.len() >= PREFILTER_MIN_LITERAL);
+            usable.then_some(literals)
+        })
+}
+
+/// Rule id for tools: the rule name in kebab case
+pub fn rule_id(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Mask a secret, keeping a short prefix (often the token type, like `sk_live_`)
+/// and, for longer values, the last four characters
+pub fn redact(secret: &str) -> String {
+    let chars: Vec<char> = secret.chars().collect();
+    if chars.len() < 8 {
+        return "****".to_string();
+    }
+    let head = (chars.len() / 4).min(8);
+    let tail = if chars.len() >= 16 { 4 } else { 0 };
+    let prefix: String = chars[..head].iter().collect();
+    let suffix: String = chars[chars.len() - tail..].iter().collect();
+    format!("{}****{}", prefix, suffix)
+}
+
+/// Shannon entropy of a string, in bits per character
+fn shannon_entropy(s: &str) -> f64 {
+    let mut counts = std::collections::HashMap::new();
+    for c in s.chars() {
+        *counts.entry(c).or_insert(0usize) += 1;
+    }
+    let len = s.chars().count() as f64;
+    counts
+        .values()
+        .map(|&n| {
+            let p = n as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+thread_local! {
+    static TEST_HOME: std::cell::RefCell<Option<PathBuf>> = const { std::cell::RefCell::new(None) };
+}
+
+/// The user's home directory, or the one a test on this thread set with `with_home`
+fn home_dir() -> Result<PathBuf> {
+    #[cfg(test)]
+    if let Some(home) = TEST_HOME.with(|home| home.borrow().clone()) {
+        return Ok(home);
+    }
+    dirs::home_dir().context("Could not find home directory")
+}
+
+/// Run `f` as a user whose home directory is `home`, so tests can play several
+/// users (each with their own `~/.arcane`) without touching the real one
+#[cfg(test)]
+pub(crate) fn with_home<T>(home: &Path, f: impl FnOnce() -> T) -> T {
+    let previous = TEST_HOME.with(|h| h.replace(Some(home.to_path_buf())));
+    let result = f();
+    TEST_HOME.with(|h| *h.borrow_mut() = previous);
+    result
+}
+
+/// ~/.arcane/teams, where team keys, rosters and used invite nonces live
+fn teams_dir() -> Result<PathBuf> {
+    let home = home_dir()?;
+    Ok(home.join(".arcane").join("teams"))
+}
+
+fn team_identity_from_bytes(bytes: &[u8]) -> Result<x25519::Identity> {
+    use std::str::FromStr;
+    let key_str = std::str::from_utf8(bytes)?;
+    x25519::Identity::from_str(key_str.trim()).map_err(|e| anyhow::anyhow!(e))
+}
+
+/// Public keys you invited to a team (~/.arcane/teams/<name>.members)
+fn team_roster(team_name: &str) -> Vec<String> {
+    teams_dir()
+        .and_then(|dir| {
+            Ok(fs::read_to_string(
+                dir.join(format!("{}.members", team_name)),
+            )?)
+        })
+        .map(|content| {
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn write_team_roster(team_name: &str, members: &[String]) -> Result<()> {
+    let dir = teams_dir()?;
+    fs::create_dir_all(&dir)?;
+    let mut content = members.join("\n");
+    content.push('\n');
+    fs::write(dir.join(format!("{}.members", team_name)), content)?;
+    Ok(())
+}
+
+/// Append an invite nonce to a used list, creating it if needed
+fn record_used_invite(path: &Path, nonce: &str) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(file, "{}", nonce)?;
+    Ok(())
+}
+
+/// Encrypt a team key for `recipient` as `<repo>/arcane/invites/<team>/<nonce>.age`
+fn write_team_invite(
+    repo_root: &Path,
+    team_name: &str,
+    team_key: &TeamKey,
+    recipient: &RecipientKey,
+    expires_in: std::time::Duration,
+) -> Result<PathBuf> {
+    let now = chrono::Utc::now();
+    let expires_at = now + chrono::Duration::from_std(expires_in)?;
+    let mut nonce = [0u8; 16];
+    rand::rng().fill_bytes(&mut nonce);
+
+    let invite = TeamInvite {
+        version: TEAM_INVITE_VERSION,
+        team: team_name.to_string(),
+        recipient: recipient.to_string(),
+        nonce: hex::encode(nonce),
+        created_at: now.to_rfc3339(),
+        expires_at: expires_at.to_rfc3339(),
+        team_key: String::from_utf8(team_key.0.clone())?,
+    };
+    let payload = zeroize::Zeroizing::new(serde_json::to_vec(&invite)?);
+
+    let invites_dir = repo_root.join("arcane").join("invites").join(team_name);
+    fs::create_dir_all(&invites_dir)?;
+    let invite_path = invites_dir.join(format!("{}.age", invite.nonce));
+
+    let encryptor = age::Encryptor::with_recipients(std::iter::once(recipient.as_recipient()))?;
+    let mut file = fs::File::create(&invite_path)?;
+    let mut writer = encryptor.wrap_output(&mut file)?;
+    writer.write_all(&payload)?;
+    writer.finish()?;
+
+    Ok(invite_path)
+}
+
+/// Aliases with a `.age` wrap in a keyring directory
+fn wrapped_aliases(dir: &Path) -> Result<Vec<String>> {
+    let mut aliases: Vec<String> = fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().and_then(|e| e.to_str()) == Some("age"))
+        .filter_map(|p| p.file_stem().and_then(|s| s.to_str()).map(String::from))
+        .collect();
+    aliases.sort();
+    Ok(aliases)
+}
+
+/// Alias whose `.pub` in `keys_dir` holds `public_key`
+fn alias_for_public_key(keys_dir: &Path, public_key: &str) -> Option<String> {
+    fs::read_dir(keys_dir)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().and_then(|e| e.to_str()) == Some("pub"))
+        .find(|p| {
+            fs::read_to_string(p)
+                .map(|k| k.trim() == public_key)
+                .unwrap_or(false)
+        })
+        .and_then(|p| p.file_stem().and_then(|s| s.to_str()).map(String::from))
+}
+
+/// Non-comment lines of an `identity.age`
+fn identity_body(content: &str) -> String {
+    content
+        .lines()
+        .filter(|l| !l.starts_with('#') && !l.trim().is_empty())
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn keyring_entry() -> Result<keyring::Entry> {
+    keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER)
+        .map_err(|e| anyhow::anyhow!("OS keyring unavailable: {}", e))
+}
+
+/// Passphrase from ARCANE_PASSPHRASE, or prompted on the terminal (git filters still
+/// have one through /dev/tty)
+fn read_passphrase(prompt: &str, confirm: bool) -> Result<SecretString> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase.into());
+    }
+
+    let passphrase = rpassword::prompt_password(prompt).map_err(|e| {
+        anyhow::anyhow!(
+            "Cannot prompt for passphrase ({}); set {}",
+            e,
+            PASSPHRASE_ENV
+        )
+    })?;
+    if passphrase.is_empty() {
+        return Err(anyhow::anyhow!("Empty passphrase"));
+    }
+    if confirm {
+        let again = rpassword::prompt_password("Confirm passphrase: ")?;
+        if again != passphrase {
+            return Err(anyhow::anyhow!("Passphrases don't match"));
+        }
+    }
+    Ok(passphrase.into())
+}
+
+/// Agent label for a keyring's current key. Hashes the wrapped key files, so after a
+/// rotation (new wraps) the agent's old entry is never used.
+fn keyring_cache_label(dir: &Path) -> Option<String> {
+    use sha2::Digest;
+
+    let mut wraps: Vec<PathBuf> = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().and_then(|e| e.to_str()) == Some("age"))
+        .collect();
+    if wraps.is_empty() {
+        return None;
+    }
+    wraps.sort();
+
+    let mut hasher = Sha256::new();
+    hasher.update(dir.to_string_lossy().as_bytes());
+    for wrap in wraps {
+        hasher.update(wrap.file_name()?.to_string_lossy().as_bytes());
+        hasher.update(fs::read(&wrap).ok()?);
+    }
+    Some(format!("keyring:{}", hex::encode(hasher.finalize())))
+}
+
+/// Agent label for a key looked up by the fingerprint in a sealed header
+fn fingerprint_cache_label(fingerprint: &[u8; KEY_FINGERPRINT_LEN]) -> String {
+    format!("fingerprint:{}", hex::encode(fingerprint))
+}
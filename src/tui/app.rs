@@ -55,9 +55,10 @@ pub struct App {
     // Vault/Identity State
     pub identity_sub_tab: usize,
     pub master_pubkey: Option<String>,
+    pub keyring_dir: Option<String>,
     pub team_members: Vec<String>,
     pub machine_keys: Vec<String>,
-    pub scan_results: Vec<(String, Vec<String>)>,
+    pub scan_results: Vec<arcane::scan_report::ReportedFinding>,
     pub snapshots: Vec<(String, u64)>,
     pub selected_team_idx: usize,
     pub sub_tab_focused: bool, // True = focus on sub-tabs, False = focus on main tabs
@@ -177,6 +178,7 @@ impl App {
             shadow_branches: config.shadow_branches,
             identity_sub_tab: 0,
             master_pubkey: None,
+            keyring_dir: None,
             team_members: vec![],
             machine_keys: vec![],
             scan_results: vec![],
@@ -945,9 +947,24 @@ impl App {
         use arcane::security::ArcaneSecurity;
 
         if let Ok(sec) = ArcaneSecurity::new(None) {
-            // Load master public key
-            if let Ok(identity) = sec.load_master_identity() {
-                self.master_pubkey = Some(identity.to_public().to_string());
+            // Load master public key (from the file header, so a passphrase never prompts here)
+            if let Ok(public_key) = sec.master_public_key() {
+                self.master_pubkey = Some(public_key);
+            }
+
+            // Which keyring layout this repo uses (tracked .arcane/keys or local .git/arcane/keys)
+            if let Ok(root) = ArcaneSecurity::find_repo_root() {
+                let dir = ArcaneSecurity::keys_dir_for(&root);
+                let label = if dir.ends_with(arcane::security::TRACKED_KEYS_DIR) {
+                    "tracked, shared with clones"
+                } else {
+                    "local to this clone"
+                };
+                self.keyring_dir = Some(format!(
+                    "{} ({})",
+                    dir.strip_prefix(&root).unwrap_or(&dir).display(),
+                    label
+                ));
             }
 
             // Load team members
@@ -975,23 +992,18 @@ impl App {
         if let Ok(sec) = ArcaneSecurity::new(None) {
             match sec.scan_repo() {
                 Ok(results) => {
-                    self.scan_results = results
-                        .iter()
-                        .map(|(path, secrets)| {
-                            let formatted: Vec<String> = secrets
-                                .iter()
-                                .map(|s| format!("Line {}: {}", s.line, s.name))
-                                .collect();
-                            (path.to_string_lossy().to_string(), formatted)
-                        })
-                        .collect();
+                    // Same report as `arcane scan --format json`
+                    let repo_root = ArcaneSecurity::find_repo_root().unwrap_or_default();
+                    self.scan_results =
+                        arcane::scan_report::ScanReport::new(&repo_root, &results).findings;
 
                     if self.scan_results.is_empty() {
                         self.events.push("✅ No secrets detected!".to_string());
                     } else {
                         self.events.push(format!(
-                            "⚠️ Found {} files with secrets!",
-                            self.scan_results.len()
+                            "⚠️ Found {} secrets in {} files!",
+                            self.scan_results.len(),
+                            results.len()
                         ));
                     }
                 }
@@ -1141,16 +1153,27 @@ impl App {
             return;
         }
 
-        let removed = self.team_members.remove(idx);
+        use arcane::security::ArcaneSecurity;
 
-        // Persist to file
-        if let Err(e) = self.save_team_members() {
-            self.events.push(format!("❌ Failed to save: {}", e));
-        } else {
-            self.events.push(format!(
-                "❌ Removed: {}...",
-                &removed[..20.min(removed.len())]
-            ));
+        let alias = self.team_members[idx].clone();
+        self.events
+            .push(format!("🔄 Revoking {} and rotating keys...", alias));
+
+        // Revocation rotates the key and re-seals tracked secrets in one commit
+        match ArcaneSecurity::new(None).and_then(|sec| sec.remove_team_member(&alias)) {
+            Ok(summary) => {
+                self.events.push(format!(
+                    "✅ Removed {} ({} files re-sealed, {} keys rotated)",
+                    summary.alias,
+                    summary.resealed.len(),
+                    summary.keyrings.len()
+                ));
+                self.team_members.remove(idx);
+            }
+            Err(e) => {
+                self.events
+                    .push(format!("❌ Failed to remove {}: {}", alias, e));
+            }
         }
 
         // Adjust selection
@@ -1302,6 +1325,7 @@ impl App {
                 None,
                 None,  // compose path
                 false, // auto-ingress
+                false, // warn-image-secrets
                 false, // dry-run
                 false, // parallel
             )
@@ -602,9 +602,17 @@ fn render_my_identity(f: &mut Frame, app: &mut App, area: ratatui::layout::Rect)
         .clone()
         .unwrap_or_else(|| "No Master Identity Found (run 'arcane init')".to_string());
 
-    let content = Paragraph::new(format!("\n  Public Key:\n  {}", key_display))
-        .block(block)
-        .style(Style::default().add_modifier(Modifier::BOLD));
+    let keyring_display = app
+        .keyring_dir
+        .clone()
+        .unwrap_or_else(|| "Not in a repository".to_string());
+
+    let content = Paragraph::new(format!(
+        "\n  Public Key:\n  {}\n\n  Keyring:\n  {}",
+        key_display, keyring_display
+    ))
+    .block(block)
+    .style(Style::default().add_modifier(Modifier::BOLD));
 
     f.render_widget(content, area);
 }
@@ -686,9 +694,12 @@ fn render_security_ops(f: &mut Frame, app: &mut App, area: ratatui::layout::Rect
     } else {
         app.scan_results
             .iter()
-            .map(|(file, secrets)| {
-                ListItem::new(format!("  ⚠️ {} → {:?}", file, secrets))
-                    .style(Style::default().fg(Color::Red))
+            .map(|f| {
+                ListItem::new(format!(
+                    "  ⚠️ {}:{}:{} → {} [{}] {}",
+                    f.file, f.line, f.column, f.rule, f.severity, f.redacted
+                ))
+                .style(Style::default().fg(Color::Red))
             })
             .collect()
     };